@@ -0,0 +1,260 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::intersection_control::IntersectionDelays;
+use crate::map_model::DiagonalFilter;
+use crate::route::RouterInput;
+use crate::turn_restrictions::TurnRestriction;
+use crate::turns::TurnPenalties;
+use crate::{
+    Error, FilterKind, Intersection, IntersectionID, ModalFilter, Road, RoadID, TravelFlow,
+};
+
+/// The kinds of traffic a filter can let through
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VehicleClass {
+    Car,
+    Bus,
+    Emergency,
+    Bicycle,
+    Foot,
+}
+
+impl VehicleClass {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            Self::Car => "car",
+            Self::Bus => "bus",
+            Self::Emergency => "emergency",
+            Self::Bicycle => "bicycle",
+            Self::Foot => "foot",
+        }
+    }
+
+    pub fn from_string(x: &str) -> Result<Self, Error> {
+        match x {
+            "car" => Ok(Self::Car),
+            "bus" => Ok(Self::Bus),
+            "emergency" => Ok(Self::Emergency),
+            "bicycle" => Ok(Self::Bicycle),
+            "foot" => Ok(Self::Foot),
+            _ => Err(Error::UnknownVehicleClass(x.to_string())),
+        }
+    }
+}
+
+/// A daily period, in minutes since midnight. If `end` is before `start`, the window wraps past
+/// midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: u16,
+    /// Exclusive
+    pub end: u16,
+}
+
+impl TimeWindow {
+    pub fn contains(self, minute_of_day: u16) -> bool {
+        if self.start <= self.end {
+            self.start <= minute_of_day && minute_of_day < self.end
+        } else {
+            minute_of_day >= self.start || minute_of_day < self.end
+        }
+    }
+}
+
+/// Who a modal filter lets through, and when it applies
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    /// These can always pass
+    pub allowed: BTreeSet<VehicleClass>,
+    /// The filter only applies during these windows, and everyone can pass otherwise. If empty,
+    /// the filter always applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active: Vec<TimeWindow>,
+}
+
+impl AccessPolicy {
+    /// Walking and cycling are never blocked. Bus gates let buses through, and school streets
+    /// only apply around the start and end of the school day.
+    pub fn default_for(kind: FilterKind) -> Self {
+        let mut allowed = BTreeSet::from([VehicleClass::Bicycle, VehicleClass::Foot]);
+        let mut active = Vec::new();
+        match kind {
+            FilterKind::BusGate => {
+                allowed.insert(VehicleClass::Bus);
+            }
+            FilterKind::SchoolStreet => {
+                active.push(TimeWindow {
+                    start: 8 * 60,
+                    end: 9 * 60 + 30,
+                });
+                active.push(TimeWindow {
+                    start: 14 * 60 + 30,
+                    end: 16 * 60,
+                });
+            }
+            FilterKind::WalkCycleOnly | FilterKind::NoEntry | FilterKind::DiagonalFilter => {}
+        }
+        Self { allowed, active }
+    }
+
+    /// Does the filter stop this vehicle at this time? With no time, every time window is assumed
+    /// to apply.
+    pub fn blocks(&self, vehicle: VehicleClass, minute_of_day: Option<u16>) -> bool {
+        if self.allowed.contains(&vehicle) {
+            return false;
+        }
+        match minute_of_day {
+            Some(minute) if !self.active.is_empty() => {
+                self.active.iter().any(|window| window.contains(minute))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Who's routing and when. The default, cars at an unspecified time, treats every filter as
+/// blocking.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessQuery {
+    pub vehicle: VehicleClass,
+    /// Minutes since midnight
+    pub minute_of_day: Option<u16>,
+    /// Filters of these kinds can always be passed, whatever their policy. Emergency services may
+    /// be able to open some filters, like collapsible bollards.
+    pub passable_kinds: BTreeSet<FilterKind>,
+}
+
+impl Default for AccessQuery {
+    fn default() -> Self {
+        Self {
+            vehicle: VehicleClass::Car,
+            minute_of_day: None,
+            passable_kinds: BTreeSet::new(),
+        }
+    }
+}
+
+impl AccessQuery {
+    /// At any time, with no filters passable beyond their policy
+    pub fn vehicle(vehicle: VehicleClass) -> Self {
+        Self {
+            vehicle,
+            ..Default::default()
+        }
+    }
+
+    /// Emergency vehicles at any time, passing through filters of some kinds
+    pub fn emergency(passable_kinds: BTreeSet<FilterKind>) -> Self {
+        Self {
+            vehicle: VehicleClass::Emergency,
+            minute_of_day: None,
+            passable_kinds,
+        }
+    }
+
+    fn blocked_by(&self, kind: FilterKind, access: &AccessPolicy) -> bool {
+        !self.passable_kinds.contains(&kind) && access.blocks(self.vehicle, self.minute_of_day)
+    }
+
+    /// Wraps any `RouterInput`, so filters and turn restrictions that let this vehicle through at
+    /// this time don't block it. Diagonal filters only stop motor vehicles.
+    pub fn router_input<R: RouterInput>(self, inner: R) -> impl RouterInput {
+        struct AccessRouterInput<R> {
+            inner: R,
+            query: AccessQuery,
+        }
+
+        impl<R: RouterInput> RouterInput for AccessRouterInput<R> {
+            fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+                self.inner.roads_iter()
+            }
+
+            fn get_r(&self, r: RoadID) -> &Road {
+                self.inner.get_r(r)
+            }
+
+            fn get_i(&self, i: IntersectionID) -> &Intersection {
+                self.inner.get_i(i)
+            }
+
+            fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
+                self.inner.modal_filter(r)
+            }
+
+            fn has_modal_filter(&self, r: RoadID) -> bool {
+                self.inner
+                    .modal_filter(r)
+                    .is_some_and(|filter| self.query.blocked_by(filter.kind, &filter.access))
+            }
+
+            fn travel_flow(&self, r: RoadID) -> TravelFlow {
+                self.inner.travel_flow(r)
+            }
+
+            fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+                let filter = self.inner.diagonal_filter(i)?;
+                let kind = FilterKind::DiagonalFilter;
+                self.query
+                    .blocked_by(kind, &AccessPolicy::default_for(kind))
+                    .then_some(filter)
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                self.inner.cost_seconds(road)
+            }
+
+            fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+                self.inner.turn_banned(i, from, to)
+            }
+
+            fn turn_restriction_applies(&self, restriction: &TurnRestriction) -> bool {
+                restriction.applies(self.query.vehicle, self.query.minute_of_day)
+            }
+
+            fn turn_penalties(&self) -> &TurnPenalties {
+                self.inner.turn_penalties()
+            }
+
+            fn intersection_delays(&self) -> &IntersectionDelays {
+                self.inner.intersection_delays()
+            }
+        }
+
+        AccessRouterInput { inner, query: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn school_street_times() {
+        let policy = AccessPolicy::default_for(FilterKind::SchoolStreet);
+        assert!(policy.blocks(VehicleClass::Car, Some(8 * 60 + 15)));
+        assert!(!policy.blocks(VehicleClass::Car, Some(12 * 60)));
+        assert!(policy.blocks(VehicleClass::Car, None));
+        assert!(!policy.blocks(VehicleClass::Bicycle, Some(8 * 60 + 15)));
+    }
+
+    #[test]
+    fn bus_gates() {
+        let policy = AccessPolicy::default_for(FilterKind::BusGate);
+        assert!(!policy.blocks(VehicleClass::Bus, None));
+        assert!(policy.blocks(VehicleClass::Car, Some(12 * 60)));
+    }
+
+    #[test]
+    fn windows_past_midnight() {
+        let window = TimeWindow {
+            start: 22 * 60,
+            end: 6 * 60,
+        };
+        assert!(window.contains(23 * 60));
+        assert!(window.contains(60));
+        assert!(!window.contains(12 * 60));
+    }
+}
@@ -0,0 +1,290 @@
+use geo::{Distance, Euclidean, Length, LineInterpolatePoint, LineString, Point};
+use geojson::FeatureCollection;
+use rstar::{primitives::GeomWithData, RTree};
+use utils::Tags;
+
+use crate::map_model::DiagonalFilter;
+use crate::route::{Route, RouterInput};
+use crate::{
+    Intersection, IntersectionID, MapModel, ModalFilter, Road, RoadID, Router, TravelFlow,
+};
+
+/// Above this, pairs of roads are sampled for `permeability`
+const MAX_PAIRS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActiveMode {
+    Walking,
+    Cycling,
+}
+
+impl ActiveMode {
+    fn meters_per_second(self) -> f64 {
+        match self {
+            Self::Walking => 1.4,
+            Self::Cycling => 4.5,
+        }
+    }
+
+    /// Can people walk or cycle along this way at all?
+    fn allowed_on(self, tags: &Tags) -> bool {
+        if tags.is_any("highway", vec!["motorway", "motorway_link"]) {
+            return false;
+        }
+        match self {
+            Self::Walking => !tags.is("foot", "no"),
+            Self::Cycling => {
+                if tags.is("bicycle", "no") {
+                    return false;
+                }
+                // Cyclists must dismount on footways and steps, unless they're signed otherwise
+                !tags.is_any("highway", vec!["footway", "steps", "pedestrian"])
+                    || tags.is_any("bicycle", vec!["yes", "designated", "permissive"])
+            }
+        }
+    }
+}
+
+/// Indexes `ActiveTravelNetwork::roads`. These are numbered separately from `MapModel::roads`, so
+/// they can't be passed to `MapModel::get_r` by mistake.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct ActiveTravelRoadID(pub usize);
+
+/// Every way people can walk or cycle along, including footways, cycleways, and paths that
+/// vehicles can't use. Roads and intersections have their own IDs, separate from
+/// `MapModel::roads` and `MapModel::intersections`. Only the router for this network uses
+/// `RoadID` for them.
+pub struct ActiveTravelNetwork {
+    pub roads: Vec<Road>,
+    pub intersections: Vec<Intersection>,
+    pub closest_road: RTree<GeomWithData<LineString, ActiveTravelRoadID>>,
+    /// For each road that vehicles can also use, the matching `MapModel` road, so edited travel
+    /// flows apply to cycling too
+    pub vehicle_roads: Vec<Option<RoadID>>,
+}
+
+impl ActiveTravelNetwork {
+    /// Modal filters and diagonal filters are always passable. Walking ignores travel flows.
+    /// Cycling follows them, unless OSM says cycling is allowed against a one-way. With
+    /// `after_edits`, edited travel flows on vehicle roads apply.
+    pub fn router_input<'a>(
+        &'a self,
+        map: &'a MapModel,
+        mode: ActiveMode,
+        after_edits: bool,
+    ) -> impl RouterInput + 'a {
+        struct ActiveTravelRouterInput<'a> {
+            network: &'a ActiveTravelNetwork,
+            map: &'a MapModel,
+            mode: ActiveMode,
+            after_edits: bool,
+        }
+
+        impl RouterInput for ActiveTravelRouterInput<'_> {
+            fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+                self.network
+                    .roads
+                    .iter()
+                    .filter(|r| self.mode.allowed_on(&r.tags))
+            }
+
+            fn get_r(&self, r: RoadID) -> &Road {
+                &self.network.roads[r.0]
+            }
+
+            fn get_i(&self, i: IntersectionID) -> &Intersection {
+                &self.network.intersections[i.0]
+            }
+
+            fn modal_filter(&self, _: RoadID) -> Option<&ModalFilter> {
+                None
+            }
+
+            fn travel_flow(&self, r: RoadID) -> TravelFlow {
+                if self.mode == ActiveMode::Walking {
+                    return TravelFlow::BothWays;
+                }
+                let road = self.get_r(r);
+                if road.tags.is("oneway:bicycle", "no")
+                    || road.tags.is_any(
+                        "cycleway",
+                        vec!["opposite", "opposite_lane", "opposite_track"],
+                    )
+                {
+                    return TravelFlow::BothWays;
+                }
+                if self.after_edits {
+                    // Both roads follow the direction of the same OSM way
+                    if let Some(vehicle_road) = self.network.vehicle_roads[r.0] {
+                        return self.map.travel_flows[&vehicle_road];
+                    }
+                }
+                TravelFlow::from_osm(&road.tags)
+            }
+
+            fn diagonal_filter(&self, _: IntersectionID) -> Option<&DiagonalFilter> {
+                None
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                Euclidean.length(&road.linestring) / self.mode.meters_per_second()
+            }
+        }
+
+        ActiveTravelRouterInput {
+            network: self,
+            map,
+            mode,
+            after_edits,
+        }
+    }
+
+    pub fn router(&self, map: &MapModel, mode: ActiveMode, after_edits: bool) -> Router {
+        Router::new(&self.router_input(map, mode, after_edits), 1.0)
+    }
+
+    /// In meters
+    fn route_length(&self, route: &Route) -> f64 {
+        route
+            .steps
+            .iter()
+            .map(|(r, _)| Euclidean.length(&self.roads[r.0].linestring))
+            .sum()
+    }
+
+    pub fn get_r(&self, r: ActiveTravelRoadID) -> &Road {
+        &self.roads[r.0]
+    }
+
+    /// Footways, cycleways, and paths that vehicles can't use
+    pub fn paths(&self) -> impl Iterator<Item = &Road> {
+        self.roads.iter().filter(|r| !r.motorised)
+    }
+
+    /// As an ID for this network's router
+    fn closest_road(&self, pt: Point) -> Option<RoadID> {
+        self.closest_road
+            .nearest_neighbor(&pt)
+            .map(|obj| RoadID(obj.data.0))
+    }
+}
+
+/// Sums of directness for pairs of roads, to average later
+#[derive(Default)]
+struct Directness {
+    sum: f64,
+    count: usize,
+}
+
+impl Directness {
+    fn add(&mut self, route_length: Option<f64>, straight_line: f64) {
+        if let Some(length) = route_length {
+            self.sum += length / straight_line;
+            self.count += 1;
+        }
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+const METRICS: [&str; 5] = [
+    "driving_before",
+    "driving_after",
+    "cycling_before",
+    "cycling_after",
+    "walking",
+];
+
+impl MapModel {
+    /// How direct are trips between pairs of `roads` by car, bike, and foot, before and after
+    /// edits? Directness is the route length divided by the straight-line distance, so 1 is
+    /// perfectly direct. Modal filters should make driving less direct, without changing walking
+    /// or cycling. Edits never affect walking.
+    ///
+    /// Returns GJ with a LineString per road, with the mean directness of trips to or from it, and
+    /// the mean over all trips as foreign members. Missing values mean no trips were possible.
+    pub fn permeability(&mut self, roads: Vec<RoadID>) -> FeatureCollection {
+        self.rebuild_router(1.0);
+        let network = &self.active_travel;
+        let cycling_before = network.router(self, ActiveMode::Cycling, false);
+        let cycling_after = network.router(self, ActiveMode::Cycling, true);
+        let walking = network.router(self, ActiveMode::Walking, true);
+        let driving_after = self.router_after.as_ref().unwrap();
+
+        let midpoints: Vec<Point> = roads
+            .iter()
+            .map(|r| {
+                self.get_r(*r)
+                    .linestring
+                    .line_interpolate_point(0.5)
+                    .unwrap()
+            })
+            .collect();
+        let num_pairs = roads.len() * roads.len().saturating_sub(1) / 2;
+        let step = (num_pairs / MAX_PAIRS).max(1);
+
+        let mut per_road: Vec<[Directness; 5]> = roads.iter().map(|_| Default::default()).collect();
+        let mut total: [Directness; 5] = Default::default();
+        let mut pair_index = 0;
+        for i1 in 0..roads.len() {
+            for i2 in (i1 + 1)..roads.len() {
+                pair_index += 1;
+                if pair_index % step != 0 {
+                    continue;
+                }
+                let straight_line = Euclidean.distance(midpoints[i1], midpoints[i2]);
+                if straight_line < 1.0 {
+                    continue;
+                }
+
+                let drive = |router: &Router| {
+                    router
+                        .route_from_roads(roads[i1], roads[i2])
+                        .map(|route| route.get_distance_and_time(self).0)
+                };
+                let active = |router: &Router| {
+                    let from = network.closest_road(midpoints[i1])?;
+                    let to = network.closest_road(midpoints[i2])?;
+                    router
+                        .route_from_roads(from, to)
+                        .map(|route| network.route_length(&route))
+                };
+                let lengths = [
+                    drive(&self.router_before),
+                    drive(driving_after),
+                    active(&cycling_before),
+                    active(&cycling_after),
+                    active(&walking),
+                ];
+
+                for (metric, length) in lengths.into_iter().enumerate() {
+                    total[metric].add(length, straight_line);
+                    per_road[i1][metric].add(length, straight_line);
+                    per_road[i2][metric].add(length, straight_line);
+                }
+            }
+        }
+
+        let mut features = Vec::new();
+        for (r, directness) in roads.iter().zip(per_road) {
+            let mut f = self.mercator.to_wgs84_gj(&self.get_r(*r).linestring);
+            f.set_property("id", r.0);
+            for (metric, value) in METRICS.iter().zip(directness) {
+                f.set_property(format!("{metric}_directness"), value.mean());
+            }
+            features.push(f);
+        }
+
+        let mut foreign_members = serde_json::Map::new();
+        for (metric, value) in METRICS.iter().zip(total) {
+            foreign_members.insert(format!("{metric}_directness"), value.mean().into());
+        }
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(foreign_members),
+        }
+    }
+}
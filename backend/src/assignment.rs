@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::route::{OverrideCosts, RouterInput};
+use crate::route_choice::RouteChoice;
+use crate::{Road, RoadID, Router, TravelFlow};
+
+/// How to assign OD requests to roads. With no iterations, every request takes its free-flow
+/// shortest path, no matter how many others do ("all-or-nothing"). Otherwise, the method of
+/// successive averages repeatedly slows down busy roads using a BPR volume-delay function,
+/// reroutes everyone, and blends the new counts in, until the counts settle.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AssignmentSettings {
+    /// The most rounds of rerouting. 0 is all-or-nothing.
+    pub iterations: usize,
+    /// Stop early once the counts change by less than this fraction of the total in one round
+    pub convergence: f64,
+    /// BPR travel time is `free_flow * (1 + alpha * (volume / capacity) ^ beta)`
+    pub bpr_alpha: f64,
+    pub bpr_beta: f64,
+    /// How many hours the OD requests are spread over, to compare counts with hourly capacity
+    pub period_hours: f64,
+}
+
+impl AssignmentSettings {
+    pub const ALL_OR_NOTHING: AssignmentSettings = AssignmentSettings {
+        iterations: 0,
+        convergence: 0.01,
+        bpr_alpha: 0.15,
+        bpr_beta: 4.0,
+        period_hours: 1.0,
+    };
+
+    /// The standard BPR parameters, assuming the requests happen within one hour
+    pub fn congested() -> Self {
+        Self {
+            iterations: 10,
+            ..Self::ALL_OR_NOTHING
+        }
+    }
+
+    fn travel_time(&self, free_flow: f64, volume: f64, capacity: f64) -> f64 {
+        let ratio = volume / (capacity * self.period_hours);
+        free_flow * (1.0 + self.bpr_alpha * ratio.powf(self.bpr_beta))
+    }
+}
+
+impl Default for AssignmentSettings {
+    fn default() -> Self {
+        Self::ALL_OR_NOTHING
+    }
+}
+
+/// Vehicles per hour a road can carry in both directions, from its type and number of lanes. When
+/// `lanes` isn't tagged, one-way roads have one lane and others have two, except for motorways and
+/// trunk roads, which have twice that.
+pub fn road_capacity(road: &Road) -> f64 {
+    let highway = road
+        .tags
+        .get("highway")
+        .map(|x| x.trim_end_matches("_link"))
+        .unwrap_or("");
+    let per_lane = match highway {
+        "motorway" => 2000.0,
+        "trunk" => 1800.0,
+        "primary" => 1500.0,
+        "secondary" => 1200.0,
+        "tertiary" => 1000.0,
+        "unclassified" | "residential" => 600.0,
+        _ => 300.0,
+    };
+    let lanes = road
+        .tags
+        .get("lanes")
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| *x >= 1.0)
+        .unwrap_or_else(|| {
+            let per_direction = if matches!(highway, "motorway" | "trunk") {
+                2.0
+            } else {
+                1.0
+            };
+            if TravelFlow::from_osm(&road.tags) == TravelFlow::BothWays {
+                2.0 * per_direction
+            } else {
+                per_direction
+            }
+        });
+    per_lane * lanes
+}
+
+/// Assigns `requests` to roads, returning counts per road. `router` must be built from
+/// `router_input` with no main road penalty; it's used for the all-or-nothing round. Each round
+/// picks routes using `route_choice`.
+pub fn assign(
+    router_input: &impl RouterInput,
+    router: &Router,
+    requests: &[(RoadID, RoadID, usize)],
+    settings: &AssignmentSettings,
+    route_choice: &RouteChoice,
+) -> HashMap<RoadID, usize> {
+    let counts = route_choice.od_to_counts(router_input, Some(router), requests);
+    if settings.iterations == 0 {
+        return counts;
+    }
+
+    let mut volumes: HashMap<RoadID, f64> = counts
+        .into_iter()
+        .map(|(r, count)| (r, count as f64))
+        .collect();
+    let capacities: HashMap<RoadID, f64> = router_input
+        .roads_iter()
+        .map(|road| (road.id, road_capacity(road)))
+        .collect();
+
+    for iteration in 1..=settings.iterations {
+        let seconds: HashMap<RoadID, f64> = router_input
+            .roads_iter()
+            .map(|road| {
+                let volume = volumes.get(&road.id).cloned().unwrap_or(0.0);
+                let free_flow = router_input.cost_seconds(road);
+                (
+                    road.id,
+                    settings.travel_time(free_flow, volume, capacities[&road.id]),
+                )
+            })
+            .collect();
+        let congested = OverrideCosts {
+            inner: router_input,
+            seconds,
+        };
+        let auxiliary = route_choice.od_to_counts(&congested, None, requests);
+
+        // Move part of the way from the current volumes to the new all-or-nothing counts
+        let step = 1.0 / (iteration as f64 + 1.0);
+        let mut change = 0.0;
+        let mut total = 0.0;
+        for r in capacities.keys() {
+            let current = volumes.get(r).cloned().unwrap_or(0.0);
+            let target = auxiliary.get(r).cloned().unwrap_or(0) as f64;
+            let next = current + step * (target - current);
+            change += (next - current).abs();
+            total += next;
+            if next > 0.0 {
+                volumes.insert(*r, next);
+            } else {
+                volumes.remove(r);
+            }
+        }
+        info!("Assignment round {iteration}: counts changed by {change:.0} of {total:.0}");
+        if total == 0.0 || change / total < settings.convergence {
+            break;
+        }
+    }
+
+    volumes
+        .into_iter()
+        .map(|(r, volume)| (r, volume.round() as usize))
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bpr() {
+        let settings = AssignmentSettings::congested();
+        assert_eq!(settings.travel_time(10.0, 0.0, 1000.0), 10.0);
+        // At capacity, travel takes 15% longer
+        assert!((settings.travel_time(10.0, 1000.0, 1000.0) - 11.5).abs() < 1e-9);
+        assert!(settings.travel_time(10.0, 2000.0, 1000.0) > 20.0);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use geo::LineInterpolatePoint;
+use geojson::{Feature, FeatureCollection};
+use osm_reader::{RelationID, WayID};
+
+use crate::access::{AccessQuery, VehicleClass};
+use crate::route::RouterInput;
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// A bus route relation from OSM
+pub struct BusRoute {
+    pub relation: RelationID,
+    pub name: String,
+    /// The member ways, in order
+    pub ways: Vec<WayID>,
+}
+
+/// Something stopping a bus from following its route
+#[derive(Clone, Debug, PartialEq)]
+enum Breakage {
+    /// A filter on a road along the route blocks buses
+    ModalFilter(RoadID),
+    /// Buses can't move between two roads along the route, because of a diagonal filter, turn
+    /// restriction, or travel flow
+    Movement(IntersectionID, RoadID, RoadID),
+}
+
+impl MapModel {
+    /// Routes buses with the current edits. Filters letting buses through, like bus gates, are
+    /// passable.
+    pub fn router_input_bus(&self) -> impl RouterInput + use<'_> {
+        AccessQuery::vehicle(VehicleClass::Bus).router_input(self.router_input_after())
+    }
+
+    /// Checks that buses can still follow every OSM bus route continuously. Returns GJ with a
+    /// feature per problem, saying which named route is broken, where, and by what. Problems also
+    /// present without any edits have `existing` set. The `routes` foreign member lists every
+    /// route and whether it's broken.
+    pub fn check_bus_routes(&self) -> FeatureCollection {
+        let after = AccessQuery::vehicle(VehicleClass::Bus).router_input(self.router_input_after());
+        let before =
+            AccessQuery::vehicle(VehicleClass::Bus).router_input(self.router_input_before());
+
+        let mut roads_per_way: BTreeMap<WayID, Vec<RoadID>> = BTreeMap::new();
+        for road in &self.roads {
+            roads_per_way.entry(road.way).or_default().push(road.id);
+        }
+
+        let mut features = Vec::new();
+        let mut routes = Vec::new();
+        for route in &self.bus_routes {
+            let roads: Vec<RoadID> = route
+                .ways
+                .iter()
+                .flat_map(|w| roads_per_way.get(w).cloned().unwrap_or_default())
+                .collect();
+            // Routes entirely outside the study area
+            if roads.is_empty() {
+                continue;
+            }
+
+            let existing = self.bus_route_breakages(&roads, &before);
+            let breakages = self.bus_route_breakages(&roads, &after);
+            routes.push(serde_json::json!({
+                "name": route.name,
+                "relation": route.relation.0,
+                "broken": !breakages.is_empty(),
+            }));
+
+            for breakage in breakages {
+                let mut f = self.breakage_to_gj(&breakage);
+                f.set_property("route", route.name.clone());
+                f.set_property("relation", route.relation.0);
+                f.set_property("existing", existing.contains(&breakage));
+                features.push(f);
+            }
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "routes": routes,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        }
+    }
+
+    /// Where the route along `roads` is broken. Where more than two of its roads meet, the route
+    /// loops or branches, and movements there aren't checked.
+    fn bus_route_breakages(
+        &self,
+        roads: &[RoadID],
+        router_input: &impl RouterInput,
+    ) -> Vec<Breakage> {
+        let mut breakages = Vec::new();
+        for r in roads {
+            if router_input.has_modal_filter(*r) {
+                breakages.push(Breakage::ModalFilter(*r));
+            }
+        }
+
+        let route_roads: BTreeSet<RoadID> = roads.iter().cloned().collect();
+        let mut intersections: BTreeSet<IntersectionID> = BTreeSet::new();
+        for r in roads {
+            let road = self.get_r(*r);
+            intersections.insert(road.src_i);
+            intersections.insert(road.dst_i);
+        }
+        for i in intersections {
+            let intersection = self.get_i(i);
+            let here: Vec<RoadID> = intersection
+                .roads
+                .iter()
+                .filter(|r| route_roads.contains(r))
+                .cloned()
+                .collect();
+            let [r1, r2] = here[..] else {
+                continue;
+            };
+            let can_move = |from: RoadID, to: RoadID| {
+                intersection
+                    .allowed_movements_from(from, router_input)
+                    .any(|(r, _)| r == to)
+            };
+            if !can_move(r1, r2) && !can_move(r2, r1) {
+                breakages.push(Breakage::Movement(i, r1, r2));
+            }
+        }
+        breakages
+    }
+
+    fn breakage_to_gj(&self, breakage: &Breakage) -> Feature {
+        match breakage {
+            Breakage::ModalFilter(r) => {
+                let road = self.get_r(*r);
+                let filter = &self.modal_filters[r];
+                let pt = road
+                    .linestring
+                    .line_interpolate_point(filter.percent_along)
+                    .unwrap();
+                let mut f = self.mercator.to_wgs84_gj(&pt);
+                f.set_property("kind", "modal_filter");
+                f.set_property("filter_kind", filter.kind.to_string());
+                f.set_property("road", r.0);
+                f
+            }
+            Breakage::Movement(i, r1, r2) => {
+                let mut f = self.mercator.to_wgs84_gj(&self.get_i(*i).point);
+                f.set_property("kind", "movement");
+                f.set_property("intersection", i.0);
+                f.set_property("has_diagonal_filter", self.diagonal_filters.contains_key(i));
+                f.set_property("roads", vec![r1.0, r2.0]);
+                f
+            }
+        }
+    }
+}
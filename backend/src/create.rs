@@ -1,28 +1,40 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use anyhow::Result;
-use geo::{Coord, LineInterpolatePoint, LineString, MultiPolygon};
+use geo::{Coord, LineInterpolatePoint, LineString, MultiPolygon, Point};
 use osm_reader::{NodeID, OsmID, RelationID, WayID};
 use petgraph::graphmap::UnGraphMap;
 use rstar::{primitives::GeomWithData, RTree};
 use utils::{
     osm2graph::{EdgeID, Graph, OsmReader},
-    Tags,
+    Mercator, Tags,
 };
 
+use crate::active_travel::{ActiveTravelNetwork, ActiveTravelRoadID};
+use crate::bus_routes::BusRoute;
+use crate::intersection_control::{apply_intersection_control, IntersectionDelays, OsmControl};
+use crate::route_choice::RouteChoice;
+use crate::savefile::DEFAULT_SCENARIO;
+use crate::speed_profiles::{SpeedProfiles, TimePeriod};
+use crate::speeds::{infer_speed_limit, SpeedRegion};
+use crate::turn_restrictions::{apply_turn_restrictions, OsmTurnRestriction};
+use crate::turns::TurnPenalties;
 use crate::{
-    impact::Impact, od::DemandModel, FilterKind, Intersection, IntersectionID, MapModel, Road,
-    RoadID, Router, TravelFlow,
+    impact::Impact, od::DemandModel, Error, FilterKind, Intersection, IntersectionID, MapModel,
+    Road, RoadID, Router, TravelFlow,
 };
 
 #[derive(Default)]
 struct Osm {
     bus_routes_on_roads: HashMap<WayID, Vec<String>>,
+    bus_routes: Vec<BusRoute>,
     railways: Vec<LineString>,
     waterways: Vec<LineString>,
     barrier_nodes: BTreeSet<NodeID>,
-    // Only represent one case of restricted turns (from, to) on a particular node
-    turn_restrictions: HashMap<NodeID, Vec<(WayID, WayID)>>,
+    control_nodes: Vec<(NodeID, OsmControl)>,
+    turn_restrictions: Vec<OsmTurnRestriction>,
+    // Every way people can walk or cycle along, including the vehicle roads
+    active_travel_ways: Vec<(WayID, Vec<NodeID>, Tags)>,
+    active_travel_nodes: HashMap<NodeID, Coord>,
 }
 
 impl OsmReader for Osm {
@@ -35,11 +47,14 @@ impl OsmReader for Osm {
                 self.barrier_nodes.insert(id);
             }
         }
+        if let Some(control) = OsmControl::parse(&tags) {
+            self.control_nodes.push((id, control));
+        }
     }
 
     fn way(
         &mut self,
-        _: WayID,
+        id: WayID,
         node_ids: &Vec<NodeID>,
         node_mapping: &HashMap<NodeID, Coord>,
         tags: &Tags,
@@ -47,6 +62,13 @@ impl OsmReader for Osm {
         if node_ids.len() < 2 {
             return;
         }
+        if is_active_travel_way(tags) {
+            for n in node_ids {
+                self.active_travel_nodes.insert(*n, node_mapping[n]);
+            }
+            self.active_travel_ways
+                .push((id, node_ids.clone(), tags.clone()));
+        }
         if tags.has("railway") && (!tags.has("layer") || tags.is("layer", "0")) {
             self.railways.push(LineString(
                 node_ids.into_iter().map(|n| node_mapping[&n]).collect(),
@@ -61,9 +83,10 @@ impl OsmReader for Osm {
         }
     }
 
-    fn relation(&mut self, _: RelationID, members: &Vec<(String, OsmID)>, tags: &Tags) {
+    fn relation(&mut self, id: RelationID, members: &Vec<(String, OsmID)>, tags: &Tags) {
         if tags.is("type", "route") && tags.is("route", "bus") {
             if let Some(name) = tags.get("name") {
+                let mut ways = Vec::new();
                 for (role, member) in members {
                     if let OsmID::Way(w) = member {
                         if role.is_empty() {
@@ -71,60 +94,20 @@ impl OsmReader for Osm {
                                 .entry(*w)
                                 .or_insert_with(Vec::new)
                                 .push(name.to_string());
+                            ways.push(*w);
                         }
                     }
                 }
+                self.bus_routes.push(BusRoute {
+                    relation: id,
+                    name: name.to_string(),
+                    ways,
+                });
             }
         }
 
-        // https://wiki.openstreetmap.org/wiki/Relation:restriction describes many cases. Only handle
-        // the simplest: a banned turn involving exactly 2 ways and 1 node.
-        if tags.is("type", "restriction")
-            && tags.is_any(
-                "restriction",
-                vec![
-                    "no_right_turn",
-                    "no_left_turn",
-                    "no_u_turn",
-                    "no_straight_on",
-                ],
-            )
-        {
-            let mut from = None;
-            let mut via = None;
-            let mut to = None;
-            for (role, member) in members {
-                match member {
-                    OsmID::Way(w) => {
-                        if role == "from" && from.is_none() {
-                            from = Some(*w);
-                        } else if role == "to" && to.is_none() {
-                            to = Some(*w);
-                        } else {
-                            // Some other case, bail out
-                            return;
-                        }
-                    }
-                    OsmID::Node(n) => {
-                        if role == "via" && via.is_none() {
-                            via = Some(*n);
-                        } else {
-                            return;
-                        }
-                    }
-                    OsmID::Relation(_) => {
-                        return;
-                    }
-                }
-            }
-
-            if let (Some(from), Some(via), Some(to)) = (from, via, to) {
-                self.turn_restrictions
-                    .entry(via)
-                    .or_insert_with(Vec::new)
-                    .push((from, to));
-            }
-        }
+        self.turn_restrictions
+            .extend(OsmTurnRestriction::parse(members, tags));
     }
 }
 
@@ -133,24 +116,32 @@ pub fn create_from_osm(
     boundary_wgs84: MultiPolygon,
     study_area_name: Option<String>,
     demand: Option<DemandModel>,
-) -> Result<MapModel> {
+) -> Result<MapModel, Error> {
     let mut osm = Osm::default();
-    let mut graph = Graph::new(input_bytes, is_road, &mut osm)?;
+    let mut graph = Graph::new(input_bytes, is_road, &mut osm)
+        .map_err(|err| Error::InvalidOsmInput(err.to_string()))?;
     remove_disconnected_components(&mut graph);
     graph.compact_ids();
 
+    let speed_region = SpeedRegion::from_location(graph.mercator.wgs84_bounds.center());
+
     // Add in a bit
     let roads: Vec<Road> = graph
         .edges
         .into_values()
-        .map(|e| Road {
-            id: RoadID(e.id.0),
-            src_i: IntersectionID(e.src.0),
-            dst_i: IntersectionID(e.dst.0),
-            way: e.osm_way,
-            linestring: e.linestring,
-            speed_mph: parse_maxspeed_mph(&e.osm_tags),
-            tags: e.osm_tags,
+        .map(|e| {
+            let (speed_mph, speed_source) = infer_speed_limit(&e.osm_tags, speed_region);
+            Road {
+                id: RoadID(e.id.0),
+                src_i: IntersectionID(e.src.0),
+                dst_i: IntersectionID(e.dst.0),
+                way: e.osm_way,
+                linestring: e.linestring,
+                speed_mph,
+                speed_source,
+                motorised: true,
+                tags: e.osm_tags,
+            }
         })
         .collect();
 
@@ -182,6 +173,16 @@ pub fn create_from_osm(
             .collect(),
     );
 
+    info!("Building the walking and cycling network");
+    let active_travel = create_active_travel_network(
+        std::mem::take(&mut osm.active_travel_ways),
+        std::mem::take(&mut osm.active_travel_nodes),
+        &graph.mercator,
+        &roads,
+        &intersections,
+        speed_region,
+    );
+
     info!("Finalizing the map model");
 
     let mut travel_flows = BTreeMap::new();
@@ -193,11 +194,13 @@ pub fn create_from_osm(
         roads,
         intersections,
         bus_routes_on_roads: osm.bus_routes_on_roads,
+        bus_routes: osm.bus_routes,
         mercator: graph.mercator,
         boundary_wgs84,
         study_area_name,
         closest_road,
         closest_intersection,
+        active_travel,
 
         railways: osm.railways,
         waterways: osm.waterways,
@@ -211,12 +214,26 @@ pub fn create_from_osm(
         diagonal_filters: BTreeMap::new(),
 
         travel_flows,
+        banned_turns: BTreeSet::new(),
+        turn_penalties: TurnPenalties::default(),
+        intersection_delays: IntersectionDelays::default(),
+        speed_region,
+        speed_profiles: SpeedProfiles::default(),
+        time_period: TimePeriod::FreeFlow,
+        route_choice: RouteChoice::default(),
+
+        active_scenario: DEFAULT_SCENARIO.to_string(),
+        other_scenarios: BTreeMap::new(),
 
         impact: None,
         demand: None,
 
         undo_stack: Vec::new(),
         redo_queue: Vec::new(),
+        history_neighbourhood: None,
+        other_histories: BTreeMap::new(),
+        edit_log: Vec::new(),
+        edit_author: None,
         boundaries: BTreeMap::new(),
     };
     if let Some(mut demand) = demand {
@@ -234,6 +251,13 @@ pub fn create_from_osm(
 
     apply_existing_filters(&mut map, osm.barrier_nodes, &graph);
     apply_turn_restrictions(&mut map, osm.turn_restrictions);
+    apply_intersection_control(
+        &mut map,
+        osm.control_nodes,
+        &graph.node_to_edge,
+        &graph.node_to_pt,
+    );
+    map.banned_turns = map.osm_banned_turns();
 
     let router_before = Router::new(&map.router_input_before(), 1.0);
     map.router_before = router_before;
@@ -241,6 +265,149 @@ pub fn create_from_osm(
     Ok(map)
 }
 
+/// Splits every way people can walk or cycle along into roads, the same way the vehicle graph
+/// does: at both ends, and wherever ways share a node. Vehicle roads are split there too, so each
+/// of their pieces is matched to the vehicle road it's part of.
+fn create_active_travel_network(
+    ways: Vec<(WayID, Vec<NodeID>, Tags)>,
+    node_pts: HashMap<NodeID, Coord>,
+    mercator: &Mercator,
+    vehicle_roads: &[Road],
+    vehicle_intersections: &[Intersection],
+    speed_region: Option<SpeedRegion>,
+) -> ActiveTravelNetwork {
+    let mut node_uses: HashMap<NodeID, usize> = HashMap::new();
+    for (_, nodes, _) in &ways {
+        for n in nodes {
+            *node_uses.entry(*n).or_insert(0) += 1;
+        }
+        // Always split at the ends
+        for n in [nodes[0], *nodes.last().unwrap()] {
+            *node_uses.entry(n).or_insert(0) += 1;
+        }
+    }
+
+    // Vehicle roads follow the direction of their way, so find them by where they start
+    let vehicle_road_starts: HashMap<(WayID, NodeID), &Road> = vehicle_roads
+        .iter()
+        .map(|r| ((r.way, vehicle_intersections[r.src_i.0].node), r))
+        .collect();
+
+    // Each segment is (way, nodes, tags, vehicle road)
+    let mut segments = Vec::new();
+    for (way, nodes, tags) in ways {
+        let mut vehicle_road: Option<&Road> = None;
+        let mut segment_nodes = Vec::new();
+        for n in nodes {
+            segment_nodes.push(n);
+            if segment_nodes.len() > 1 && node_uses[&n] > 1 {
+                let done = std::mem::replace(&mut segment_nodes, vec![n]);
+                segments.push((way, done, tags.clone(), vehicle_road.map(|r| r.id)));
+            }
+            if segment_nodes.len() == 1 {
+                if vehicle_road.is_some_and(|r| vehicle_intersections[r.dst_i.0].node == n) {
+                    vehicle_road = None;
+                }
+                if let Some(r) = vehicle_road_starts.get(&(way, n)) {
+                    vehicle_road = Some(*r);
+                }
+            }
+        }
+    }
+
+    // Keep only the largest component, like the vehicle graph
+    let mut scc_graph: UnGraphMap<NodeID, ()> = UnGraphMap::new();
+    for (_, nodes, _, _) in &segments {
+        scc_graph.add_edge(nodes[0], *nodes.last().unwrap(), ());
+    }
+    let mut component_per_node = HashMap::new();
+    for (idx, nodes) in petgraph::algo::kosaraju_scc(&scc_graph)
+        .into_iter()
+        .enumerate()
+    {
+        for n in nodes {
+            component_per_node.insert(n, idx);
+        }
+    }
+    let mut roads_per_component: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, nodes, _, _) in &segments {
+        *roads_per_component
+            .entry(component_per_node[&nodes[0]])
+            .or_insert(0) += 1;
+    }
+    if let Some((largest, _)) = roads_per_component
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+    {
+        let before = segments.len();
+        segments.retain(|(_, nodes, _, _)| component_per_node[&nodes[0]] == largest);
+        info!(
+            "Removing {} disconnected walking and cycling roads",
+            before - segments.len()
+        );
+    }
+
+    let mut roads = Vec::new();
+    let mut vehicle_roads = Vec::new();
+    let mut intersection_per_node: HashMap<NodeID, IntersectionID> = HashMap::new();
+    let mut intersection_nodes = Vec::new();
+    let mut roads_per_intersection: Vec<Vec<RoadID>> = Vec::new();
+    for (way, nodes, tags, vehicle_road) in segments {
+        let id = RoadID(roads.len());
+        let [src_i, dst_i] = [nodes[0], *nodes.last().unwrap()].map(|n| {
+            *intersection_per_node.entry(n).or_insert_with(|| {
+                intersection_nodes.push(n);
+                roads_per_intersection.push(Vec::new());
+                IntersectionID(intersection_nodes.len() - 1)
+            })
+        });
+        roads_per_intersection[src_i.0].push(id);
+        if dst_i != src_i {
+            roads_per_intersection[dst_i.0].push(id);
+        }
+
+        let mut linestring = LineString(nodes.iter().map(|n| node_pts[n]).collect());
+        mercator.to_mercator_in_place(&mut linestring);
+        let (speed_mph, speed_source) = infer_speed_limit(&tags, speed_region);
+        roads.push(Road {
+            id,
+            src_i,
+            dst_i,
+            way,
+            linestring,
+            speed_mph,
+            speed_source,
+            motorised: is_road(&tags),
+            tags,
+        });
+        vehicle_roads.push(vehicle_road);
+    }
+
+    let intersections: Vec<Intersection> = intersection_nodes
+        .into_iter()
+        .zip(roads_per_intersection)
+        .enumerate()
+        .map(|(idx, (node, intersection_roads))| {
+            let point = Point::from(mercator.pt_to_mercator(node_pts[&node]));
+            Intersection::new(IntersectionID(idx), node, point, intersection_roads, &roads)
+        })
+        .collect();
+
+    let closest_road = RTree::bulk_load(
+        roads
+            .iter()
+            .map(|r| GeomWithData::new(r.linestring.clone(), ActiveTravelRoadID(r.id.0)))
+            .collect(),
+    );
+
+    ActiveTravelNetwork {
+        roads,
+        intersections,
+        closest_road,
+        vehicle_roads,
+    }
+}
+
 // Handles a partial borrow of Graph
 struct GraphSubset {
     node_to_edge: HashMap<NodeID, EdgeID>,
@@ -263,7 +430,10 @@ fn apply_existing_filters(
         };
         let pt = map.mercator.pt_to_mercator(graph.node_to_pt[&node]);
         // TODO What FilterKind?
-        map.add_modal_filter(pt, Some(vec![RoadID(edge.0)]), FilterKind::NoEntry);
+        if let Err(err) = map.add_modal_filter(pt, Some(vec![RoadID(edge.0)]), FilterKind::NoEntry)
+        {
+            warn!("Skipping barrier at {pt:?}: {err}");
+        }
     }
 
     // Look for roads tagged with access restrictions
@@ -329,42 +499,18 @@ fn apply_existing_filters(
                 .linestring
                 .line_interpolate_point(percent)
                 .unwrap();
-            map.add_modal_filter(pt.into(), Some(vec![r]), filter);
+            if let Err(err) = map.add_modal_filter(pt.into(), Some(vec![r]), filter) {
+                warn!("Skipping existing filter at {pt:?}: {err}");
+            }
         }
     }
 
-    // The commands above populate the existing modal filters and edit history. Undo that.
+    // The commands above populate the existing modal filters, edit history, and edit log. Undo
+    // that.
     map.original_modal_filters = map.modal_filters.clone();
     map.undo_stack.clear();
     map.redo_queue.clear();
-}
-
-fn apply_turn_restrictions(
-    map: &mut MapModel,
-    mut turn_restrictions: HashMap<NodeID, Vec<(WayID, WayID)>>,
-) {
-    for intersection in &mut map.intersections {
-        if let Some(list) = turn_restrictions.remove(&intersection.node) {
-            for (from_way, to_way) in list {
-                // One OSM way turns into multiple Roads. The restriction only makes sense on the
-                // Road connected to this intersection. So search only this intersection's roads.
-                let mut from = None;
-                let mut to = None;
-                for r in &intersection.roads {
-                    let way = map.roads[r.0].way;
-                    if way == from_way {
-                        from = Some(*r);
-                    } else if way == to_way {
-                        to = Some(*r);
-                    }
-                }
-
-                if let (Some(from), Some(to)) = (from, to) {
-                    intersection.turn_restrictions.push((from, to));
-                }
-            }
-        }
-    }
+    map.edit_log.clear();
 }
 
 fn is_road(tags: &Tags) -> bool {
@@ -389,35 +535,12 @@ fn is_road(tags: &Tags) -> bool {
     true
 }
 
-// This uses OSM data when directly tagged, but otherwise makes some assumptions specific to
-// Scotland.
-// TODO Look at muv or https://github.com/westnordost/osm-legal-default-speeds for something more
-// rigorous
-fn parse_maxspeed_mph(tags: &Tags) -> usize {
-    if let Some(maxspeed) = tags.get("maxspeed") {
-        if let Ok(kmph) = maxspeed.parse::<f64>() {
-            return (kmph * 0.621371).round() as usize;
-        }
-        if let Some(mph) = maxspeed
-            .strip_suffix(" mph")
-            .and_then(|x| x.parse::<f64>().ok())
-        {
-            return mph.round() as usize;
-        }
-    }
-
-    // TODO Check these against osmactive
-    match tags.get("highway").unwrap().as_str() {
-        "motorway" | "motorway_link" => 70,
-        "trunk" | "trunk_link" => 60,
-        "primary" | "primary_link" => 40,
-        "secondary" | "secondary_link" | "tertiary" | "tertiary_link" => 30,
-        "residential" | "service" | "unclassified" => 20,
-        "living_street" => 15,
-        "pedestrian" => 10,
-        // Should look into these
-        _ => 10,
+// Everything vehicles can use, plus anything people can walk or cycle along
+fn is_active_travel_way(tags: &Tags) -> bool {
+    if !tags.has("highway") || tags.is("area", "yes") {
+        return false;
     }
+    !tags.is_any("highway", vec!["proposed", "construction"])
 }
 
 // TODO Consider upstreaming to osm2graph
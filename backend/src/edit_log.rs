@@ -0,0 +1,189 @@
+use geo::{Centroid, Geometry, GeometryCollection, LineInterpolatePoint};
+use geojson::{Feature, FeatureCollection};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::map_model::Command;
+use crate::{Error, MapModel};
+
+/// One edit, recorded for an audit trail of why the map was changed. Entries are never removed,
+/// even when the edit is undone; undoing is logged as another entry.
+#[derive(Clone)]
+pub struct EditLogEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub author: Option<String>,
+    /// The rationale for the edit. This can be filled in after the edit.
+    pub comment: Option<String>,
+    pub scenario: String,
+    pub neighbourhood: Option<String>,
+    /// What the user did, like `add_modal_filter` or `undo`
+    pub action: String,
+    /// Everything changed, in WGS84
+    pub geometry: Geometry,
+}
+
+impl MapModel {
+    /// Later edits are credited to this author
+    pub fn set_edit_author(&mut self, author: Option<String>) {
+        self.edit_author = author;
+    }
+
+    /// Explains why an edit in the log was made. Only the comment of an entry can change.
+    pub fn set_edit_comment(&mut self, index: usize, comment: Option<String>) -> Result<(), Error> {
+        let Some(entry) = self.edit_log.get_mut(index) else {
+            return Err(Error::UnknownEditLogEntry(index));
+        };
+        entry.comment = comment;
+        Ok(())
+    }
+
+    /// Records a command about to be applied
+    pub(crate) fn log_edit(&mut self, action: &str, cmd: &Command) {
+        let geometry = self.mercator.to_wgs84(&self.command_geometry(cmd));
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.edit_log.push(EditLogEntry {
+            timestamp,
+            author: self.edit_author.clone(),
+            comment: None,
+            scenario: self.active_scenario.clone(),
+            neighbourhood: self.history_neighbourhood.clone(),
+            action: action.to_string(),
+            geometry,
+        });
+    }
+
+    /// Filters are points and travel flows are roads. Deleted filters are located before the
+    /// command is applied.
+    fn command_geometry(&self, cmd: &Command) -> Geometry {
+        match cmd {
+            Command::SetModalFilter(r, filter) => {
+                let linestring = &self.get_r(*r).linestring;
+                match filter.as_ref().or(self.modal_filters.get(r)) {
+                    Some(f) => linestring
+                        .line_interpolate_point(f.percent_along)
+                        .map(Geometry::Point)
+                        .unwrap_or_else(|| Geometry::LineString(linestring.clone())),
+                    None => Geometry::LineString(linestring.clone()),
+                }
+            }
+            Command::SetDiagonalFilter(i, _) => Geometry::Point(self.get_i(*i).point),
+            Command::SetTravelFlow(r, _) => Geometry::LineString(self.get_r(*r).linestring.clone()),
+            Command::SetTurnBanned(i, (from, to), _) => {
+                Geometry::LineString(self.movement_linestring(*i, *from, *to))
+            }
+            Command::Multiple(list) => Geometry::GeometryCollection(GeometryCollection::from(
+                list.iter()
+                    .map(|cmd| self.command_geometry(cmd))
+                    .collect::<Vec<_>>(),
+            )),
+        }
+    }
+
+    /// A feature per entry, oldest first, with the index to use for `set_edit_comment`
+    pub fn edit_log_to_gj(&self) -> FeatureCollection {
+        let features = self
+            .edit_log
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| entry.to_gj(index))
+            .collect();
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+
+    /// A row per entry, oldest first. Geometry is summarized by its centroid.
+    pub fn edit_log_to_csv(&self) -> String {
+        let mut out =
+            "index,timestamp,author,comment,scenario,neighbourhood,action,longitude,latitude\n"
+                .to_string();
+        for (index, entry) in self.edit_log.iter().enumerate() {
+            let (longitude, latitude) = entry
+                .geometry
+                .centroid()
+                .map(|pt| (pt.x().to_string(), pt.y().to_string()))
+                .unwrap_or_default();
+            let row = [
+                index.to_string(),
+                entry.timestamp.to_string(),
+                csv_field(entry.author.as_deref().unwrap_or("")),
+                csv_field(entry.comment.as_deref().unwrap_or("")),
+                csv_field(&entry.scenario),
+                csv_field(entry.neighbourhood.as_deref().unwrap_or("")),
+                csv_field(&entry.action),
+                longitude,
+                latitude,
+            ];
+            out.push_str(&row.join(","));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl EditLogEntry {
+    pub(crate) fn to_gj(&self, index: usize) -> Feature {
+        let mut f = Feature::from(geojson::Geometry::from(&self.geometry));
+        f.set_property("index", index);
+        f.set_property("timestamp", self.timestamp);
+        f.set_property("author", self.author.clone());
+        f.set_property("comment", self.comment.clone());
+        f.set_property("scenario", self.scenario.clone());
+        f.set_property("neighbourhood", self.neighbourhood.clone());
+        f.set_property("action", self.action.clone());
+        f
+    }
+
+    /// Parses one feature from `edit_log_to_gj`
+    pub(crate) fn from_gj(f: &Feature) -> Result<Self, String> {
+        let string = |key: &str| -> Result<Option<String>, String> {
+            match f.property(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(serde_json::Value::String(x)) => Ok(Some(x.clone())),
+                Some(x) => Err(format!("{key} {x} isn't a string")),
+            }
+        };
+        let Some(timestamp) = f.property("timestamp").and_then(|x| x.as_u64()) else {
+            return Err("timestamp isn't a non-negative integer".to_string());
+        };
+        let Some(ref geometry) = f.geometry else {
+            return Err("Feature doesn't have a geometry".to_string());
+        };
+        Ok(Self {
+            timestamp,
+            author: string("author")?,
+            comment: string("comment")?,
+            scenario: string("scenario")?.ok_or("missing scenario")?,
+            neighbourhood: string("neighbourhood")?,
+            action: string("action")?.ok_or("missing action")?,
+            geometry: geometry
+                .try_into()
+                .map_err(|err: geojson::Error| err.to_string())?,
+        })
+    }
+}
+
+fn csv_field(x: &str) -> String {
+    if x.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", x.replace('"', "\"\""))
+    } else {
+        x.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
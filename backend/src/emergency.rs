@@ -0,0 +1,94 @@
+use std::collections::BTreeSet;
+
+use fast_paths::PathCalculator;
+use geo::Coord;
+use geojson::FeatureCollection;
+
+use crate::access::AccessQuery;
+use crate::{FilterKind, MapModel, RoadID, Router};
+
+impl MapModel {
+    /// How quickly can emergency vehicles from the nearest depot reach each road, before and
+    /// after edits? Emergency vehicles can pass filters of `passable_kinds`. Returns GJ with a
+    /// LineString per road with `time_before` and `time_after` in seconds, or null when
+    /// unreachable. Roads where the response time worsens by more than `threshold_seconds`, or
+    /// become unreachable, are `flagged`.
+    ///
+    /// `depots` are in Mercator.
+    pub fn response_times(
+        &self,
+        depots: &[Coord],
+        roads: Vec<RoadID>,
+        passable_kinds: BTreeSet<FilterKind>,
+        threshold_seconds: f64,
+    ) -> FeatureCollection {
+        let query = AccessQuery::emergency(passable_kinds);
+        let router_before =
+            Router::new(&query.clone().router_input(self.router_input_before()), 1.0);
+        let router_after = Router::new(&query.router_input(self.router_input_after()), 1.0);
+
+        let depot_roads: Vec<RoadID> = depots
+            .iter()
+            .filter_map(|pt| self.closest_road.nearest_neighbor(&(*pt).into()))
+            .map(|obj| obj.data)
+            .collect();
+
+        let mut calc_before = router_before.create_calculator();
+        let mut calc_after = router_after.create_calculator();
+        let mut features = Vec::new();
+        let mut num_flagged = 0;
+        for r in roads {
+            let time_before = self.response_time(&router_before, &mut calc_before, &depot_roads, r);
+            let time_after = self.response_time(&router_after, &mut calc_after, &depot_roads, r);
+            let flagged = match (time_before, time_after) {
+                (Some(before), Some(after)) => after - before > threshold_seconds,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if flagged {
+                num_flagged += 1;
+            }
+
+            let mut f = self.mercator.to_wgs84_gj(&self.get_r(r).linestring);
+            f.set_property("id", r.0);
+            f.set_property("time_before", time_before);
+            f.set_property("time_after", time_after);
+            f.set_property("flagged", flagged);
+            features.push(f);
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "threshold_seconds": threshold_seconds,
+                    "num_flagged": num_flagged,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        }
+    }
+
+    /// Seconds from the quickest depot to the road, including driving along the road itself
+    fn response_time(
+        &self,
+        router: &Router,
+        path_calc: &mut PathCalculator,
+        depot_roads: &[RoadID],
+        r: RoadID,
+    ) -> Option<f64> {
+        depot_roads
+            .iter()
+            .filter_map(|depot| {
+                if *depot == r {
+                    return Some(self.road_cost_seconds(self.get_r(r)));
+                }
+                let route = router.route_with_calculator(path_calc, *depot, r)?;
+                Some(route.get_distance_and_time(self).1)
+            })
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use crate::{IntersectionID, RoadID, SavefileProblem};
+
+/// Everything that can go wrong using this crate, so callers can react to specific cases.
+#[derive(Debug)]
+pub enum Error {
+    /// The osm.pbf or osm.xml input couldn't be turned into a road network
+    InvalidOsmInput(String),
+    /// The feature at this index in a savefile is malformed or can't be matched to the map
+    InvalidSavefile {
+        feature_index: usize,
+        reason: String,
+    },
+    /// A savefile didn't pass validation. Every problem found is listed.
+    SavefileValidation(Vec<SavefileProblem>),
+    /// A savefile was written by a newer version of this crate
+    UnsupportedSavefileVersion(u64),
+    UnknownFilterKind(String),
+    UnknownTravelFlow(String),
+    UnknownVehicleClass(String),
+    /// There's no suitable road near the point to edit
+    NoRoadNearPoint,
+    /// The road doesn't have a modal filter to change
+    NoModalFilter(RoadID),
+    /// A neighbourhood boundary doesn't contain any roads
+    NoInteriorRoads,
+    /// A neighbourhood boundary doesn't have any roads along it. Without these, there are no
+    /// border intersections, and the neighbourhood can't be analysed.
+    NoPerimeterRoads,
+    /// The operation needs a current neighbourhood, but none has been set
+    NoNeighbourhoodSelected,
+    /// There's no neighbourhood boundary with this name
+    UnknownNeighbourhood(String),
+    /// There's already a neighbourhood boundary with this name
+    DuplicateNeighbourhood(String),
+    /// Input geometry is the wrong type or couldn't be parsed
+    InvalidGeometry(String),
+    /// There's no scenario with this name
+    UnknownScenario(String),
+    /// There's already a scenario with this name
+    DuplicateScenario(String),
+    /// The operation can't be done to the active scenario
+    ActiveScenario(String),
+    /// The groups of roads for a diagonal filter don't split up the intersection
+    InvalidDiagonalFilter(String),
+    /// The roads don't both meet at the intersection, so there's no turn between them
+    UnknownMovement(IntersectionID, RoadID, RoadID),
+    /// No entry in the edit log has this index
+    UnknownEditLogEntry(usize),
+    /// The project was created without a demand model
+    NoDemandModel,
+    /// A demand model `.bin` couldn't be decoded
+    DemandDecode(String),
+    /// A CSV of observed speeds per road couldn't be parsed
+    InvalidSpeedProfile(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidOsmInput(reason) => write!(f, "Invalid OSM input: {reason}"),
+            Self::InvalidSavefile {
+                feature_index,
+                reason,
+            } => write!(f, "Invalid savefile feature #{feature_index}: {reason}"),
+            Self::SavefileValidation(problems) => {
+                write!(f, "Invalid savefile:")?;
+                for problem in problems {
+                    write!(f, "\n- {problem}")?;
+                }
+                Ok(())
+            }
+            Self::UnsupportedSavefileVersion(version) => write!(
+                f,
+                "Savefile version {version} is newer than supported ({})",
+                crate::savefile::CURRENT_VERSION
+            ),
+            Self::UnknownFilterKind(x) => write!(f, "Invalid FilterKind: {x}"),
+            Self::UnknownTravelFlow(x) => write!(f, "Invalid Direction: {x}"),
+            Self::UnknownVehicleClass(x) => write!(f, "Unknown vehicle class: {x}"),
+            Self::NoRoadNearPoint => write!(f, "There's no road near this point"),
+            Self::NoModalFilter(r) => write!(f, "{r} doesn't have a modal filter"),
+            Self::NoInteriorRoads => write!(f, "No roads inside the boundary"),
+            Self::NoPerimeterRoads => write!(f, "No perimeter roads"),
+            Self::NoNeighbourhoodSelected => write!(f, "No neighbourhood is currently selected"),
+            Self::UnknownNeighbourhood(name) => write!(f, "No neighbourhood named {name}"),
+            Self::DuplicateNeighbourhood(name) => {
+                write!(f, "A neighbourhood named {name} already exists")
+            }
+            Self::UnknownScenario(name) => write!(f, "No scenario named {name}"),
+            Self::DuplicateScenario(name) => write!(f, "A scenario named {name} already exists"),
+            Self::ActiveScenario(name) => write!(f, "{name} is the active scenario"),
+            Self::InvalidDiagonalFilter(reason) => write!(f, "Invalid diagonal filter: {reason}"),
+            Self::UnknownMovement(i, from, to) => {
+                write!(f, "There's no turn from {from} to {to} at {i}")
+            }
+            Self::UnknownEditLogEntry(index) => write!(f, "No edit log entry #{index}"),
+            Self::InvalidGeometry(reason) => write!(f, "Invalid geometry: {reason}"),
+            Self::NoDemandModel => write!(f, "No demand model"),
+            Self::DemandDecode(reason) => write!(f, "Couldn't decode demand model: {reason}"),
+            Self::InvalidSpeedProfile(reason) => write!(f, "Invalid speed profile: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<geojson::Error> for Error {
+    fn from(err: geojson::Error) -> Self {
+        Self::InvalidGeometry(err.to_string())
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use crate::access::AccessPolicy;
+use crate::map_model::{Command, DiagonalFilter};
+use crate::savefile::{SavedCommand, SavedHistory, SavedModalFilter};
+use crate::{MapModel, ModalFilter, OsmAnchor, RoadID};
+
+/// Undo and redo for the edits made in one neighbourhood. Only the current neighbourhood's history
+/// lives in `MapModel::undo_stack` and `MapModel::redo_queue`; the others are stashed, so
+/// switching neighbourhoods doesn't lose them.
+#[derive(Default)]
+pub struct EditHistory {
+    pub undo_stack: Vec<Command>,
+    pub redo_queue: Vec<Command>,
+}
+
+impl MapModel {
+    /// Stashes the current undo/redo history, then makes a neighbourhood's history current. With
+    /// `None`, edits aren't tracked by neighbourhood, and their history is discarded when
+    /// switching to one.
+    pub fn switch_edit_history(&mut self, to: Option<&str>) {
+        if self.history_neighbourhood.as_deref() == to {
+            return;
+        }
+        let next = to
+            .and_then(|name| self.other_histories.remove(name))
+            .unwrap_or_default();
+        let prev = EditHistory {
+            undo_stack: std::mem::replace(&mut self.undo_stack, next.undo_stack),
+            redo_queue: std::mem::replace(&mut self.redo_queue, next.redo_queue),
+        };
+        if let Some(name) = self.history_neighbourhood.take() {
+            self.other_histories.insert(name, prev);
+        }
+        self.history_neighbourhood = to.map(|name| name.to_string());
+    }
+
+    pub fn rename_edit_history(&mut self, old_name: &str, new_name: &str) {
+        if self.history_neighbourhood.as_deref() == Some(old_name) {
+            self.history_neighbourhood = Some(new_name.to_string());
+        }
+        if let Some(history) = self.other_histories.remove(old_name) {
+            self.other_histories.insert(new_name.to_string(), history);
+        }
+    }
+
+    /// Forgets the history of a deleted neighbourhood. The edits themselves stay.
+    pub fn delete_edit_history(&mut self, name: &str) {
+        if self.history_neighbourhood.as_deref() == Some(name) {
+            self.undo_stack.clear();
+            self.redo_queue.clear();
+            self.history_neighbourhood = None;
+        }
+        self.other_histories.remove(name);
+    }
+
+    /// Forgets the history of every neighbourhood, when the edits they apply to are replaced
+    pub(crate) fn clear_edit_histories(&mut self) {
+        self.undo_stack.clear();
+        self.redo_queue.clear();
+        self.other_histories.clear();
+    }
+
+    /// The history of every neighbourhood with any, for savefiles
+    pub(crate) fn save_edit_histories(&self) -> BTreeMap<String, SavedHistory> {
+        let mut saved = BTreeMap::new();
+        let current = self.history_neighbourhood.as_ref().map(|name| {
+            (
+                name,
+                EditHistory::saved(&self.undo_stack, &self.redo_queue, self),
+            )
+        });
+        for (name, history) in self
+            .other_histories
+            .iter()
+            .map(|(name, h)| (name, EditHistory::saved(&h.undo_stack, &h.redo_queue, self)))
+            .chain(current)
+        {
+            if !history.undo.is_empty() || !history.redo.is_empty() {
+                saved.insert(name.clone(), history);
+            }
+        }
+        saved
+    }
+
+    /// Stashes saved history for each neighbourhood. A neighbourhood's history is skipped if any
+    /// command doesn't apply to the same roads and intersections as when it was saved.
+    pub(crate) fn restore_edit_histories(&mut self, saved: &BTreeMap<String, SavedHistory>) {
+        for (name, history) in saved {
+            if !self.boundaries.contains_key(name) {
+                warn!("Not restoring edit history for unknown neighbourhood {name}");
+                continue;
+            }
+            let undo_stack: Option<Vec<Command>> = history
+                .undo
+                .iter()
+                .map(|cmd| self.saved_to_command(cmd))
+                .collect();
+            let redo_queue: Option<Vec<Command>> = history
+                .redo
+                .iter()
+                .map(|cmd| self.saved_to_command(cmd))
+                .collect();
+            let (Some(undo_stack), Some(redo_queue)) = (undo_stack, redo_queue) else {
+                warn!("Not restoring edit history for {name}, because the map has changed");
+                continue;
+            };
+            self.other_histories.insert(
+                name.clone(),
+                EditHistory {
+                    undo_stack,
+                    redo_queue,
+                },
+            );
+        }
+    }
+
+    fn command_to_saved(&self, cmd: &Command) -> SavedCommand {
+        match cmd {
+            Command::SetModalFilter(r, filter) => SavedCommand::SetModalFilter {
+                road: r.0,
+                osm: self.osm_anchor(*r, None),
+                filter: filter.as_ref().map(|f| SavedModalFilter {
+                    filter_kind: f.kind,
+                    percent_along: f.percent_along,
+                    access: Some(f.access.clone()),
+                }),
+            },
+            Command::SetDiagonalFilter(i, filter) => SavedCommand::SetDiagonalFilter {
+                intersection: i.0,
+                osm_node: self.get_i(*i).node.0,
+                is_rotated: filter.as_ref().map(|f| f.is_rotated),
+                groups: filter.as_ref().filter(|f| f.custom).map(|f| {
+                    f.groups
+                        .iter()
+                        .map(|group| group.iter().map(|r| r.0).collect())
+                        .collect()
+                }),
+            },
+            Command::SetTravelFlow(r, travel_flow) => SavedCommand::SetTravelFlow {
+                road: r.0,
+                osm: self.osm_anchor(*r, None),
+                travel_flow: *travel_flow,
+            },
+            Command::SetTurnBanned(i, (from, to), banned) => SavedCommand::SetTurnBanned {
+                intersection: i.0,
+                osm: self.turn_anchor(*i, *from, *to),
+                from: from.0,
+                to: to.0,
+                banned: *banned,
+            },
+            Command::Multiple(list) => SavedCommand::Multiple {
+                commands: list.iter().map(|cmd| self.command_to_saved(cmd)).collect(),
+            },
+        }
+    }
+
+    /// `None` if the command's IDs don't refer to the same OSM objects anymore
+    fn saved_to_command(&self, cmd: &SavedCommand) -> Option<Command> {
+        match cmd {
+            SavedCommand::SetModalFilter { road, osm, filter } => {
+                let r = self.same_road(*road, osm)?;
+                Some(Command::SetModalFilter(
+                    r,
+                    filter.as_ref().map(|f| ModalFilter {
+                        kind: f.filter_kind,
+                        percent_along: f.percent_along,
+                        access: f
+                            .access
+                            .clone()
+                            .unwrap_or_else(|| AccessPolicy::default_for(f.filter_kind)),
+                    }),
+                ))
+            }
+            SavedCommand::SetDiagonalFilter {
+                intersection,
+                osm_node,
+                is_rotated,
+                groups: Some(groups),
+            } => {
+                let intersection = self.intersections.get(*intersection)?;
+                if intersection.node.0 != *osm_node || is_rotated.is_none() {
+                    return None;
+                }
+                let groups = groups
+                    .iter()
+                    .map(|group| group.iter().map(|r| RoadID(*r)).collect())
+                    .collect();
+                let filter = DiagonalFilter::with_groups(intersection, groups, self).ok()?;
+                Some(Command::SetDiagonalFilter(intersection.id, Some(filter)))
+            }
+            SavedCommand::SetDiagonalFilter {
+                intersection,
+                osm_node,
+                is_rotated,
+                groups: None,
+            } => {
+                let intersection = self.intersections.get(*intersection)?;
+                // Removing a filter with custom groups can happen at any intersection
+                if intersection.node.0 != *osm_node
+                    || (is_rotated.is_some() && intersection.roads.len() != 4)
+                {
+                    return None;
+                }
+                Some(Command::SetDiagonalFilter(
+                    intersection.id,
+                    is_rotated
+                        .map(|is_rotated| DiagonalFilter::new(intersection, is_rotated, self)),
+                ))
+            }
+            SavedCommand::SetTravelFlow {
+                road,
+                osm,
+                travel_flow,
+            } => {
+                let r = self.same_road(*road, osm)?;
+                Some(Command::SetTravelFlow(r, *travel_flow))
+            }
+            SavedCommand::SetTurnBanned {
+                intersection,
+                osm,
+                from,
+                to,
+                banned,
+            } => {
+                let i = self.intersections.get(*intersection)?.id;
+                let from = self.roads.get(*from)?.id;
+                let to = self.roads.get(*to)?.id;
+                if !self.get_i(i).roads.contains(&from)
+                    || !self.get_i(i).roads.contains(&to)
+                    || self.turn_anchor(i, from, to) != *osm
+                {
+                    return None;
+                }
+                Some(Command::SetTurnBanned(i, (from, to), *banned))
+            }
+            SavedCommand::Multiple { commands } => commands
+                .iter()
+                .map(|cmd| self.saved_to_command(cmd))
+                .collect::<Option<Vec<_>>>()
+                .map(Command::Multiple),
+        }
+    }
+
+    fn same_road(&self, r: usize, osm: &OsmAnchor) -> Option<RoadID> {
+        let r = self.roads.get(r)?.id;
+        (self.osm_anchor(r, None) == *osm).then_some(r)
+    }
+}
+
+impl EditHistory {
+    fn saved(undo_stack: &[Command], redo_queue: &[Command], map: &MapModel) -> SavedHistory {
+        SavedHistory {
+            undo: undo_stack
+                .iter()
+                .map(|cmd| map.command_to_saved(cmd))
+                .collect(),
+            redo: redo_queue
+                .iter()
+                .map(|cmd| map.command_to_saved(cmd))
+                .collect(),
+        }
+    }
+}
@@ -2,16 +2,19 @@ use std::collections::HashMap;
 
 use geojson::{Feature, FeatureCollection};
 
-use crate::{od, MapModel, RoadID};
+use crate::assignment::{assign, AssignmentSettings};
+use crate::{od, MapModel, RoadID, Router, Scenario};
 
 // TODO Rename?
 /// Besides just studying the impact on shortcuts within one neighbourhood boundary, the user can
 /// see how traffic changes across roads in the whole map. This works by finding the best route
 /// before and after changes for every origin/destination "OD" pairs, then counting routes per
-/// road.
+/// road. Optionally, congestion on busy roads can push some routes elsewhere, and trips can be
+/// spread over several similar routes, following `MapModel::route_choice`.
 pub struct Impact {
     // (r1, r2, count) -- `count` identical trips from `r1` to `r2`
     requests: Vec<(RoadID, RoadID, usize)>,
+    assignment: AssignmentSettings,
 
     // TODO Can use Vec for perf
     counts_before: HashMap<RoadID, usize>,
@@ -26,30 +29,55 @@ impl Impact {
                 Some(demand) => demand.make_requests(map),
                 None => od::synthetic_od_requests(map),
             },
+            assignment: AssignmentSettings::default(),
             counts_before: HashMap::new(),
             counts_after: HashMap::new(),
         }
     }
 
+    pub fn assignment(&self) -> &AssignmentSettings {
+        &self.assignment
+    }
+
+    /// Changes how requests are assigned to roads, so all counts must be calculated again
+    pub fn set_assignment(&mut self, assignment: AssignmentSettings) {
+        self.assignment = assignment;
+        self.invalidate();
+    }
+
     pub fn invalidate_after_edits(&mut self) {
         self.counts_after.clear();
     }
 
+    /// When routing changes before edits too
+    pub fn invalidate(&mut self) {
+        self.counts_before.clear();
+        self.counts_after.clear();
+    }
+
     /// Returns a feature per road, with `before` and `after` counts, and a `max_count` foreign
     /// member
     pub fn recalculate(&mut self, map: &MapModel) -> FeatureCollection {
         if self.counts_before.is_empty() {
             info!("Calculating impacts before edits");
-            self.counts_before = map.router_before.od_to_counts(&self.requests);
+            self.counts_before = assign(
+                &map.router_input_before(),
+                &map.router_before,
+                &self.requests,
+                &self.assignment,
+                &map.route_choice,
+            );
         }
 
         if self.counts_after.is_empty() {
             info!("Calculating impacts after edits");
-            self.counts_after = map
-                .router_after
-                .as_ref()
-                .expect("need to rebuild_router")
-                .od_to_counts(&self.requests);
+            self.counts_after = assign(
+                &map.router_input_after(),
+                map.router_after.as_ref().expect("need to rebuild_router"),
+                &self.requests,
+                &self.assignment,
+                &map.route_choice,
+            );
         }
 
         let mut features = Vec::new();
@@ -83,6 +111,63 @@ impl Impact {
         }
     }
 
+    /// Routes the same requests through two scenarios. Returns a feature per road where counts
+    /// differ, with `a`, `b`, and `difference` (`b - a`) counts, plus `scenario_a`, `scenario_b`,
+    /// and `max_count` foreign members.
+    pub fn compare_scenarios(
+        &self,
+        map: &MapModel,
+        (name_a, a): (&str, &Scenario),
+        (name_b, b): (&str, &Scenario),
+    ) -> FeatureCollection {
+        info!("Calculating impacts for scenarios {name_a} and {name_b}");
+        let count = |scenario: &Scenario| {
+            let router_input = scenario.router_input(map);
+            let router = Router::new(&router_input, 1.0);
+            assign(
+                &router_input,
+                &router,
+                &self.requests,
+                &self.assignment,
+                &map.route_choice,
+            )
+        };
+        let (counts_a, counts_b) = (count(a), count(b));
+
+        let mut features = Vec::new();
+        let mut max_count = 0;
+        for road in &map.roads {
+            let count_a = counts_a.get(&road.id).cloned().unwrap_or(0);
+            let count_b = counts_b.get(&road.id).cloned().unwrap_or(0);
+            max_count = max_count.max(count_a.max(count_b));
+            if count_a != count_b {
+                let mut f = map.mercator.to_wgs84_gj(&road.linestring);
+                f.set_property("id", road.id.0);
+                f.set_property("a", count_a);
+                f.set_property("b", count_b);
+                f.set_property("difference", count_b as i64 - count_a as i64);
+                features.push(f);
+            }
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "scenario_a": name_a,
+                    "scenario_b": name_b,
+                    "max_count": max_count,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        }
+    }
+
+    /// Finds requests whose free-flow route crosses the road before or after edits, but not both.
+    /// Congestion isn't considered.
     pub fn get_impacts_on_road(&self, map: &MapModel, road: RoadID) -> Vec<(Feature, Feature)> {
         let mut changed_paths = Vec::new();
 
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use geo::{Coord, Distance, Euclidean, Point};
+use osm_reader::NodeID;
+use serde::{Deserialize, Serialize};
+use utils::{osm2graph::EdgeID, Tags};
+
+use crate::{Intersection, IntersectionID, MapModel, Road, RoadID};
+
+/// Signals, stop signs, and give way signs tagged on a road this far from an intersection still
+/// control it
+const MAX_DISTANCE_TO_INTERSECTION: f64 = 20.0;
+
+/// How traffic through an intersection is controlled, inferred from OSM
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntersectionControl {
+    Uncontrolled,
+    Signals,
+    /// Traffic entering from a road not part of the roundabout must give way
+    Roundabout,
+    /// Traffic from some roads must stop or give way to the others
+    Priority {
+        stop: Vec<RoadID>,
+        give_way: Vec<RoadID>,
+    },
+}
+
+impl IntersectionControl {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Uncontrolled => "uncontrolled",
+            Self::Signals => "signals",
+            Self::Roundabout => "roundabout",
+            Self::Priority { .. } => "priority",
+        }
+    }
+
+    /// Signals take precedence over everything else. Stopping takes precedence over giving way
+    /// on the same road.
+    fn add(&mut self, kind: OsmControl, approaches: Vec<RoadID>) {
+        if *self == Self::Signals {
+            return;
+        }
+        if kind == OsmControl::Signals {
+            *self = Self::Signals;
+            return;
+        }
+        if !matches!(self, Self::Priority { .. }) {
+            *self = Self::Priority {
+                stop: Vec::new(),
+                give_way: Vec::new(),
+            };
+        }
+        let Self::Priority { stop, give_way } = self else {
+            unreachable!()
+        };
+        for r in approaches {
+            if kind == OsmControl::Stop {
+                give_way.retain(|x| *x != r);
+                if !stop.contains(&r) {
+                    stop.push(r);
+                }
+            } else if !stop.contains(&r) && !give_way.contains(&r) {
+                give_way.push(r);
+            }
+        }
+    }
+}
+
+/// Seconds added to routes passing through controlled intersections, on average
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntersectionDelays {
+    pub signals: f64,
+    pub stop: f64,
+    pub give_way: f64,
+    pub roundabout: f64,
+}
+
+impl IntersectionDelays {
+    /// Intersections are free
+    pub const NONE: IntersectionDelays = IntersectionDelays {
+        signals: 0.0,
+        stop: 0.0,
+        give_way: 0.0,
+        roundabout: 0.0,
+    };
+
+    /// Rough averages for a car, including waiting for a red light or a gap in traffic
+    pub fn typical() -> Self {
+        Self {
+            signals: 15.0,
+            stop: 8.0,
+            give_way: 4.0,
+            roundabout: 4.0,
+        }
+    }
+
+    /// In seconds, for traffic arriving from the `from` road. Signals delay every approach.
+    /// Otherwise, traffic already on the main road or roundabout isn't delayed.
+    pub fn cost(&self, intersection: &Intersection, from: &Road) -> f64 {
+        match &intersection.control {
+            IntersectionControl::Uncontrolled => 0.0,
+            IntersectionControl::Signals => self.signals,
+            IntersectionControl::Roundabout => {
+                if is_roundabout(from) {
+                    0.0
+                } else {
+                    self.roundabout
+                }
+            }
+            IntersectionControl::Priority { stop, give_way } => {
+                if stop.contains(&from.id) {
+                    self.stop
+                } else if give_way.contains(&from.id) {
+                    self.give_way
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl Default for IntersectionDelays {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// A node controlling traffic as tagged in OSM, before matching to intersections
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OsmControl {
+    Signals,
+    Stop,
+    GiveWay,
+}
+
+impl OsmControl {
+    /// Signals only for a pedestrian crossing aren't at a junction, so they're ignored
+    pub(crate) fn parse(tags: &Tags) -> Option<Self> {
+        if tags.is("highway", "traffic_signals") {
+            if tags.is("traffic_signals", "crossing_only") {
+                return None;
+            }
+            return Some(Self::Signals);
+        }
+        if tags.is("highway", "stop") {
+            return Some(Self::Stop);
+        }
+        if tags.is("highway", "give_way") {
+            return Some(Self::GiveWay);
+        }
+        None
+    }
+}
+
+/// Matches each control node to an intersection. A node at the intersection applies to every
+/// road below the most important one there. A node along a road applies only to traffic
+/// approaching the nearest end of that road. Afterwards, any remaining uncontrolled intersection
+/// joining a roundabout to another road is a roundabout entry.
+pub(crate) fn apply_intersection_control(
+    map: &mut MapModel,
+    control_nodes: Vec<(NodeID, OsmControl)>,
+    node_to_edge: &HashMap<NodeID, EdgeID>,
+    node_to_pt: &HashMap<NodeID, Coord>,
+) {
+    let intersection_per_node: HashMap<NodeID, IntersectionID> =
+        map.intersections.iter().map(|i| (i.node, i.id)).collect();
+
+    for (node, kind) in control_nodes {
+        let (i, approaches) = if let Some(i) = intersection_per_node.get(&node) {
+            (*i, minor_roads(map, *i))
+        } else if let Some(edge) = node_to_edge.get(&node) {
+            // RoadID and osm2graph::EdgeID are the same
+            let road = map.get_r(RoadID(edge.0));
+            let pt = Point::from(map.mercator.pt_to_mercator(node_to_pt[&node]));
+            let Some((i, dist)) = [road.src_i, road.dst_i]
+                .into_iter()
+                .map(|i| (i, Euclidean.distance(map.get_i(i).point, pt)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+            else {
+                continue;
+            };
+            if dist > MAX_DISTANCE_TO_INTERSECTION {
+                continue;
+            }
+            (i, vec![road.id])
+        } else {
+            continue;
+        };
+        map.intersections[i.0].control.add(kind, approaches);
+    }
+
+    for intersection in &mut map.intersections {
+        if intersection.control != IntersectionControl::Uncontrolled {
+            continue;
+        }
+        let roundabout_roads = intersection
+            .roads
+            .iter()
+            .filter(|r| is_roundabout(&map.roads[r.0]))
+            .count();
+        if roundabout_roads > 0 && roundabout_roads < intersection.roads.len() {
+            intersection.control = IntersectionControl::Roundabout;
+        }
+    }
+}
+
+fn is_roundabout(road: &Road) -> bool {
+    road.tags.is_any("junction", vec!["roundabout", "circular"])
+}
+
+/// The roads at an intersection that aren't the most important kind there. If they're all the
+/// same kind, like a 4-way stop, that's every road.
+fn minor_roads(map: &MapModel, i: IntersectionID) -> Vec<RoadID> {
+    let roads = &map.get_i(i).roads;
+    let best = roads
+        .iter()
+        .map(|r| road_rank(&map.get_r(*r).tags))
+        .max()
+        .unwrap_or(0);
+    let minor: Vec<RoadID> = roads
+        .iter()
+        .filter(|r| road_rank(&map.get_r(**r).tags) < best)
+        .cloned()
+        .collect();
+    if minor.is_empty() {
+        roads.clone()
+    } else {
+        minor
+    }
+}
+
+/// Higher is more important
+fn road_rank(tags: &Tags) -> usize {
+    let Some(highway) = tags.get("highway") else {
+        return 0;
+    };
+    match highway.trim_end_matches("_link") {
+        "motorway" => 7,
+        "trunk" => 6,
+        "primary" => 5,
+        "secondary" => 4,
+        "tertiary" => 3,
+        "unclassified" | "residential" => 2,
+        "living_street" | "service" => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_precedence() {
+        let mut control = IntersectionControl::Uncontrolled;
+        control.add(OsmControl::GiveWay, vec![RoadID(0), RoadID(1)]);
+        control.add(OsmControl::Stop, vec![RoadID(1)]);
+        assert_eq!(
+            control,
+            IntersectionControl::Priority {
+                stop: vec![RoadID(1)],
+                give_way: vec![RoadID(0)],
+            }
+        );
+        control.add(OsmControl::Signals, Vec::new());
+        control.add(OsmControl::Stop, vec![RoadID(2)]);
+        assert_eq!(control, IntersectionControl::Signals);
+    }
+}
@@ -1,51 +1,81 @@
 #[macro_use]
-extern crate anyhow;
-#[macro_use]
 extern crate log;
 
 use std::sync::Once;
 
-use geo::{Coord, LineString, Polygon};
+use geo::{Coord, LineString};
 use geojson::{Feature, FeatureCollection, GeoJson, Geometry};
 use serde::Deserialize;
 use wasm_bindgen::prelude::*;
 
+pub use self::access::{AccessPolicy, AccessQuery, TimeWindow, VehicleClass};
+pub use self::assignment::AssignmentSettings;
 use self::cells::Cell;
+pub use self::error::Error;
+pub use self::intersection_control::{IntersectionControl, IntersectionDelays};
 pub use self::map_model::{
     FilterKind, Intersection, IntersectionID, MapModel, ModalFilter, Road, RoadID, TravelFlow,
 };
 pub use self::neighbourhood::Neighbourhood;
+use self::od::DemandModel;
+pub use self::project::Project;
 use self::render_cells::RenderCells;
 pub use self::route::Router;
+pub use self::route_choice::RouteChoice;
+pub use self::savefile::{
+    EditMatch, LoadReport, MatchStatus, MatchedBy, OsmAnchor, SavedCommand, SavedHistory,
+    SavedModalFilter, Savefile, SavefileFeature, SavefileProblem,
+};
+pub use self::scenarios::Scenario;
 pub use self::shortcuts::Shortcuts;
-
+pub use self::speed_profiles::{SpeedProfiles, TimePeriod};
+pub use self::speeds::{SpeedRegion, SpeedSource};
+pub use self::turn_restrictions::TurnRestriction;
+pub use self::turns::{DrivingSide, TurnPenalties, TurnType};
+
+mod access;
+mod active_travel;
+mod assignment;
 mod auto_boundaries;
+mod bus_routes;
 mod cells;
 mod create;
+mod edit_log;
+mod emergency;
+mod error;
 mod geo_helpers;
+mod history;
 mod impact;
+mod intersection_control;
 mod map_model;
 mod movements;
 mod neighbourhood;
 pub mod od;
 #[cfg(test)]
 mod osm_tests;
+mod project;
+mod reanchor;
 mod render_cells;
 mod route;
+mod route_choice;
 mod route_snapper;
+mod savefile;
+mod scenarios;
 mod shortcuts;
+mod speed_profiles;
+mod speeds;
 // TODO: We could hide this behind a feature flag - it's used by both tests and benches
 pub mod test_fixtures;
 #[cfg(test)]
 mod tests;
+mod turn_restrictions;
+mod turns;
 
 static START: Once = Once::new();
 
 #[wasm_bindgen]
 pub struct LTN {
-    map: MapModel,
-    // TODO Stateful, synced with the UI. Weird?
-    neighbourhood: Option<Neighbourhood>,
+    project: Project,
 }
 
 #[wasm_bindgen]
@@ -77,38 +107,37 @@ impl LTN {
 
         let mut demand = None;
         if demand_bytes.len() > 0 {
-            demand = Some(bincode::deserialize(demand_bytes).map_err(err_to_js)?);
+            demand = Some(DemandModel::from_bincode(demand_bytes).map_err(err_to_js)?);
         }
-        let map = MapModel::new(input_bytes, multi_polygon, study_area_name, demand)
-            .map_err(err_to_js)?;
-        Ok(LTN {
-            map,
-            neighbourhood: None,
-        })
+        let project =
+            Project::new(input_bytes, multi_polygon, study_area_name, demand).map_err(err_to_js)?;
+        Ok(LTN { project })
     }
 
     #[wasm_bindgen(js_name = getInvertedBoundary)]
     pub fn get_inverted_boundary(&self) -> Result<String, JsValue> {
-        let f = Feature::from(Geometry::from(&self.map.invert_study_area_boundary()));
+        let f = Feature::from(Geometry::from(
+            &self.project.map.invert_study_area_boundary(),
+        ));
         let out = serde_json::to_string(&f).map_err(err_to_js)?;
         Ok(out)
     }
 
     #[wasm_bindgen(js_name = getBounds)]
     pub fn get_bounds(&self) -> Vec<f64> {
-        let b = &self.map.mercator.wgs84_bounds;
+        let b = &self.project.map.mercator.wgs84_bounds;
         vec![b.min().x, b.min().y, b.max().x, b.max().y]
     }
 
     #[wasm_bindgen(js_name = toRouteSnapper)]
     pub fn to_route_snapper(&self) -> Vec<u8> {
-        let graph = self.map.to_route_snapper_graph();
+        let graph = self.project.map.to_route_snapper_graph();
         bincode::serialize(&graph).unwrap()
     }
 
     #[wasm_bindgen(js_name = toRouteSnapperGj)]
     pub fn to_route_snapper_gj(&self) -> Result<String, JsValue> {
-        let graph = self.map.to_route_snapper_graph();
+        let graph = self.project.map.to_route_snapper_graph();
 
         let mut features = Vec::new();
         for (idx, edge) in graph.edges.iter().enumerate() {
@@ -132,20 +161,18 @@ impl LTN {
 
     #[wasm_bindgen(js_name = renderModalFilters)]
     pub fn render_modal_filters(&self) -> Result<String, JsValue> {
-        Ok(serde_json::to_string(&self.map.filters_to_gj()).map_err(err_to_js)?)
+        Ok(serde_json::to_string(&self.project.map.filters_to_gj()).map_err(err_to_js)?)
     }
 
     #[wasm_bindgen(js_name = renderNeighbourhood)]
     pub fn render_neighbourhood(&self) -> Result<String, JsValue> {
-        Ok(
-            serde_json::to_string(&self.neighbourhood.as_ref().unwrap().to_gj(&self.map))
-                .map_err(err_to_js)?,
-        )
+        let gj = self.project.render_neighbourhood().map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
     }
 
     #[wasm_bindgen(js_name = renderAutoBoundaries)]
     pub fn render_auto_boundaries(&self) -> Result<String, JsValue> {
-        Ok(serde_json::to_string(&self.map.render_auto_boundaries()).map_err(err_to_js)?)
+        Ok(serde_json::to_string(&self.project.map.render_auto_boundaries()).map_err(err_to_js)?)
     }
 
     /// Takes a name and boundary GJ polygon
@@ -155,23 +182,25 @@ impl LTN {
         name: String,
         input: JsValue,
     ) -> Result<(), JsValue> {
-        let mut boundary_gj: Feature = serde_wasm_bindgen::from_value(input)?;
-        boundary_gj.set_property("kind", "boundary");
-        boundary_gj.set_property("name", name.clone());
-        self.map.boundaries.insert(name, boundary_gj);
+        let boundary_gj: Feature = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_neighbourhood_boundary(name, boundary_gj);
         Ok(())
     }
 
     #[wasm_bindgen(js_name = deleteNeighbourhoodBoundary)]
     pub fn delete_neighbourhood_boundary(&mut self, name: String) {
-        self.map.boundaries.remove(&name);
+        self.project.delete_neighbourhood_boundary(&name);
     }
 
     #[wasm_bindgen(js_name = renameNeighbourhoodBoundary)]
-    pub fn rename_neighbourhood_boundary(&mut self, old_name: String, new_name: String) {
-        let mut boundary_gj = self.map.boundaries.remove(&old_name).unwrap();
-        boundary_gj.set_property("name", new_name.clone());
-        self.map.boundaries.insert(new_name, boundary_gj);
+    pub fn rename_neighbourhood_boundary(
+        &mut self,
+        old_name: String,
+        new_name: String,
+    ) -> Result<(), JsValue> {
+        self.project
+            .rename_neighbourhood_boundary(&old_name, new_name)
+            .map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = setCurrentNeighbourhood)]
@@ -180,117 +209,251 @@ impl LTN {
         name: String,
         edit_perimeter_roads: bool,
     ) -> Result<(), JsValue> {
-        let boundary_gj = self.map.boundaries.get(&name).cloned().unwrap();
-        let mut boundary_geo: Polygon = boundary_gj.try_into().map_err(err_to_js)?;
-        self.map.mercator.to_mercator_in_place(&mut boundary_geo);
-
-        // Are we still editing the same neighbourhood, just switching edit_perimeter_roads?
-        let editing_same = self
-            .neighbourhood
-            .as_ref()
-            .map(|n| n.name == name)
-            .unwrap_or(false);
-        self.neighbourhood = Some(
-            Neighbourhood::new(&self.map, name, boundary_geo, edit_perimeter_roads)
-                .map_err(err_to_js)?,
-        );
-
-        // Undoing edits in another neighbourhood doesn't make sense
-        if !editing_same {
-            self.map.undo_stack.clear();
-            self.map.redo_queue.clear();
-        }
-
-        Ok(())
+        self.project
+            .set_current_neighbourhood(&name, edit_perimeter_roads)
+            .map_err(err_to_js)
     }
 
     /// Takes a LngLat
     #[wasm_bindgen(js_name = addModalFilter)]
     pub fn add_modal_filter(&mut self, input: JsValue, kind: String) -> Result<(), JsValue> {
         let pos: LngLat = serde_wasm_bindgen::from_value(input)?;
-        self.map.add_modal_filter(
-            self.map.mercator.pt_to_mercator(Coord {
-                x: pos.lng,
-                y: pos.lat,
-            }),
-            Some(self.neighbourhood.as_ref().unwrap().editable_roads()),
-            FilterKind::from_string(&kind).unwrap(),
-        );
-        self.after_edit();
-        Ok(())
+        let kind = FilterKind::from_string(&kind).map_err(err_to_js)?;
+        self.project
+            .add_modal_filter(
+                Coord {
+                    x: pos.lng,
+                    y: pos.lat,
+                },
+                kind,
+            )
+            .map_err(err_to_js)
     }
 
     /// Takes a LineString feature
     #[wasm_bindgen(js_name = addManyModalFilters)]
     pub fn add_many_modal_filters(&mut self, input: JsValue, kind: String) -> Result<(), JsValue> {
         let gj: Feature = serde_wasm_bindgen::from_value(input)?;
-        let mut linestring: LineString = gj.try_into().map_err(err_to_js)?;
-        self.map.mercator.to_mercator_in_place(&mut linestring);
-
-        self.map.add_many_modal_filters(
-            linestring,
-            &self.neighbourhood.as_ref().unwrap().interior_roads,
-            FilterKind::from_string(&kind).unwrap(),
-        );
-        self.after_edit();
-        Ok(())
+        let linestring: LineString = gj.try_into().map_err(err_to_js)?;
+        let kind = FilterKind::from_string(&kind).map_err(err_to_js)?;
+        self.project
+            .add_many_modal_filters(linestring, kind)
+            .map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = deleteModalFilter)]
     pub fn delete_modal_filter(&mut self, road: usize) {
-        self.map.delete_modal_filter(RoadID(road));
-        self.after_edit();
+        self.project.delete_modal_filter(RoadID(road));
     }
 
     /// Takes an IntersectionID
     #[wasm_bindgen(js_name = addDiagonalFilter)]
     pub fn add_diagonal_filter(&mut self, intersection_id: usize) -> Result<(), JsValue> {
-        self.map
+        self.project
             .add_diagonal_filter(IntersectionID(intersection_id));
-        self.after_edit();
         Ok(())
     }
 
     /// Takes an IntersectionID
     #[wasm_bindgen(js_name = rotateDiagonalFilter)]
     pub fn rotate_diagonal_filter(&mut self, intersection_id: usize) -> Result<(), JsValue> {
-        self.map
+        self.project
             .rotate_diagonal_filter(IntersectionID(intersection_id));
-        self.after_edit();
         Ok(())
     }
 
+    /// Takes an IntersectionID and a list of groups, each a list of RoadIDs. Every road at the
+    /// intersection must be in exactly one group.
+    #[wasm_bindgen(js_name = setDiagonalFilterGroups)]
+    pub fn set_diagonal_filter_groups(
+        &mut self,
+        intersection_id: usize,
+        groups: JsValue,
+    ) -> Result<(), JsValue> {
+        let groups: Vec<Vec<usize>> = serde_wasm_bindgen::from_value(groups)?;
+        self.project
+            .set_diagonal_filter_groups(
+                IntersectionID(intersection_id),
+                groups
+                    .into_iter()
+                    .map(|group| group.into_iter().map(RoadID).collect())
+                    .collect(),
+            )
+            .map_err(err_to_js)
+    }
+
     /// Takes an IntersectionID
     #[wasm_bindgen(js_name = deleteDiagonalFilter)]
     pub fn delete_diagonal_filter(&mut self, intersection_id: usize) -> Result<(), JsValue> {
-        self.map
+        self.project
             .delete_diagonal_filter(IntersectionID(intersection_id));
-        self.after_edit();
         Ok(())
     }
 
     #[wasm_bindgen(js_name = toggleTravelFlow)]
     pub fn toggle_travel_flow(&mut self, road: usize) {
-        self.map.toggle_travel_flow(RoadID(road));
-        self.after_edit();
+        self.project.toggle_travel_flow(RoadID(road));
+    }
+
+    /// Takes an IntersectionID and two RoadIDs
+    #[wasm_bindgen(js_name = setTurnBanned)]
+    pub fn set_turn_banned(
+        &mut self,
+        intersection: usize,
+        from: usize,
+        to: usize,
+        banned: bool,
+    ) -> Result<(), JsValue> {
+        self.project
+            .set_turn_banned(
+                IntersectionID(intersection),
+                RoadID(from),
+                RoadID(to),
+                banned,
+            )
+            .map_err(err_to_js)
+    }
+
+    /// Takes JSON with the `driving_side` (`left` or `right`) and the seconds added for going
+    /// `straight`, turning `with_traffic` or `across_traffic`, and making a `u_turn`
+    #[wasm_bindgen(js_name = setTurnPenalties)]
+    pub fn set_turn_penalties(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let turn_penalties: TurnPenalties = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_turn_penalties(turn_penalties);
+        Ok(())
+    }
+
+    /// Takes JSON with the seconds added for passing through `signals`, a `stop` sign, a
+    /// `give_way` sign, or entering a `roundabout`. There are no delays until this is called;
+    /// rough typical values are 15, 8, 4, and 4.
+    #[wasm_bindgen(js_name = setIntersectionDelays)]
+    pub fn set_intersection_delays(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let intersection_delays: IntersectionDelays = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_intersection_delays(intersection_delays);
+        Ok(())
+    }
+
+    /// Takes the region whose legal default speed limits apply to roads without `maxspeed`, like
+    /// `england` or `france`, or null to use rough guesses
+    #[wasm_bindgen(js_name = setSpeedRegion)]
+    pub fn set_speed_region(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let speed_region: Option<SpeedRegion> = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_speed_region(speed_region);
+        Ok(())
+    }
+
+    /// Takes CSV with observed speeds per OSM way for peak and off-peak periods. See
+    /// `SpeedProfiles::from_csv` for the format. Returns how many roads have an observed speed.
+    #[wasm_bindgen(js_name = setSpeedProfiles)]
+    pub fn set_speed_profiles(&mut self, csv: String) -> Result<usize, JsValue> {
+        self.project.set_speed_profiles(&csv).map_err(err_to_js)
+    }
+
+    /// Takes `free_flow`, `peak`, or `off_peak`
+    #[wasm_bindgen(js_name = setTimePeriod)]
+    pub fn set_time_period(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let time_period: TimePeriod = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_time_period(time_period);
+        Ok(())
+    }
+
+    /// Returns the region whose legal default speed limits apply, or null
+    #[wasm_bindgen(js_name = getSpeedRegion)]
+    pub fn get_speed_region(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.project.map.speed_region).map_err(err_to_js)
+    }
+
+    /// Returns JSON with all scenario names and the active one
+    #[wasm_bindgen(js_name = getScenarios)]
+    pub fn get_scenarios(&self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&serde_json::json!({
+            "scenarios": self.project.map.scenario_names(),
+            "active": self.project.map.active_scenario,
+        }))
+        .map_err(err_to_js)?)
+    }
+
+    #[wasm_bindgen(js_name = switchScenario)]
+    pub fn switch_scenario(&mut self, name: String) -> Result<(), JsValue> {
+        self.project.switch_scenario(&name).map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = newScenario)]
+    pub fn new_scenario(&mut self, name: String) -> Result<(), JsValue> {
+        self.project.new_scenario(name).map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = cloneScenario)]
+    pub fn clone_scenario(&mut self, from: String, name: String) -> Result<(), JsValue> {
+        self.project.clone_scenario(&from, name).map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = deleteScenario)]
+    pub fn delete_scenario(&mut self, name: String) -> Result<(), JsValue> {
+        self.project.delete_scenario(&name).map_err(err_to_js)
+    }
+
+    /// Returns GJ with every edit that differs between two scenarios
+    #[wasm_bindgen(js_name = diffScenarios)]
+    pub fn diff_scenarios(&self, a: String, b: String) -> Result<String, JsValue> {
+        let gj = self.project.diff_scenarios(&a, &b).map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Changes who can pass through the filter on a road, and when. `access` is JSON with
+    /// `allowed` vehicle classes and `active` time windows.
+    #[wasm_bindgen(js_name = setModalFilterAccess)]
+    pub fn set_modal_filter_access(&mut self, road: usize, access: JsValue) -> Result<(), JsValue> {
+        let access: AccessPolicy = serde_wasm_bindgen::from_value(access)?;
+        self.project
+            .set_modal_filter_access(RoadID(road), access)
+            .map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = setEditAuthor)]
+    pub fn set_edit_author(&mut self, author: Option<String>) {
+        self.project.set_edit_author(author);
+    }
+
+    #[wasm_bindgen(js_name = setEditComment)]
+    pub fn set_edit_comment(
+        &mut self,
+        index: usize,
+        comment: Option<String>,
+    ) -> Result<(), JsValue> {
+        self.project
+            .set_edit_comment(index, comment)
+            .map_err(err_to_js)
+    }
+
+    /// Returns GJ with a feature per edit ever made, with timestamps, authors, and comments
+    #[wasm_bindgen(js_name = getEditLog)]
+    pub fn get_edit_log(&self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&self.project.get_edit_log()).map_err(err_to_js)?)
+    }
+
+    #[wasm_bindgen(js_name = getEditLogCsv)]
+    pub fn get_edit_log_csv(&self) -> String {
+        self.project.get_edit_log_csv()
     }
 
     pub fn undo(&mut self) {
-        self.map.undo();
-        self.after_edit();
+        self.project.undo();
     }
     pub fn redo(&mut self) {
-        self.map.redo();
-        self.after_edit();
+        self.project.redo();
     }
 
     #[wasm_bindgen(js_name = getShortcutsCrossingRoad)]
     pub fn get_shortcuts_crossing_road(&self, road: usize) -> Result<String, JsValue> {
+        let map = &self.project.map;
         Ok(serde_json::to_string(&GeoJson::from(
-            Shortcuts::new(&self.map, self.neighbourhood.as_ref().unwrap())
+            self.project
+                .shortcuts()
+                .map_err(err_to_js)?
                 .subset(RoadID(road))
                 .into_iter()
-                .map(|path| path.to_gj(&self.map))
+                .map(|path| path.to_gj(map))
                 .collect::<Vec<_>>(),
         ))
         .map_err(err_to_js)?)
@@ -298,30 +461,34 @@ impl LTN {
 
     #[wasm_bindgen(js_name = getAllShortcuts)]
     pub fn get_all_shortcuts(&self) -> Result<String, JsValue> {
+        let map = &self.project.map;
         Ok(serde_json::to_string(&GeoJson::from(
-            Shortcuts::new(&self.map, self.neighbourhood.as_ref().unwrap())
+            self.project
+                .shortcuts()
+                .map_err(err_to_js)?
                 .paths
                 .into_iter()
-                .map(|path| path.to_gj(&self.map))
+                .map(|path| path.to_gj(map))
                 .collect::<Vec<_>>(),
         ))
         .map_err(err_to_js)?)
     }
 
     /// GJ with modal filters and named boundaries. This is meant for savefiles, so existing
-    /// filters aren't included (and deletions of existing are included)
+    /// filters aren't included (and deletions of existing are included). Each neighbourhood's
+    /// undo/redo history is only included if `include_history` is set.
     #[wasm_bindgen(js_name = toSavefile)]
-    pub fn to_savefile(&self) -> Result<String, JsValue> {
+    pub fn to_savefile(&self, include_history: bool) -> Result<String, JsValue> {
         // TODO Trim coordinates... in mercator?
-        Ok(serde_json::to_string(&self.map.to_savefile()).map_err(err_to_js)?)
+        Ok(serde_json::to_string(&self.project.to_savefile(include_history)).map_err(err_to_js)?)
     }
 
+    /// Returns GJ with a feature per saved edit, describing how it matched the current map
     #[wasm_bindgen(js_name = loadSavefile)]
-    pub fn load_savefile(&mut self, input: JsValue) -> Result<(), JsValue> {
+    pub fn load_savefile(&mut self, input: JsValue) -> Result<String, JsValue> {
         let gj: FeatureCollection = serde_wasm_bindgen::from_value(input)?;
-        self.map.load_savefile(gj).map_err(err_to_js)?;
-        self.neighbourhood = None;
-        Ok(())
+        let report = self.project.load_savefile(gj).map_err(err_to_js)?;
+        Ok(serde_json::to_string(&report.to_gj()).map_err(err_to_js)?)
     }
 
     /// Returns GJ with two LineStrings, before and after
@@ -333,63 +500,132 @@ impl LTN {
         x2: f64,
         y2: f64,
         main_road_penalty: f64,
+        vehicle: String,
+        minute_of_day: Option<u16>,
     ) -> Result<String, JsValue> {
-        let pt1 = self.map.mercator.pt_to_mercator(Coord { x: x1, y: y1 });
-        let pt2 = self.map.mercator.pt_to_mercator(Coord { x: x2, y: y2 });
-        Ok(
-            serde_json::to_string(&self.map.compare_route(pt1, pt2, main_road_penalty))
-                .map_err(err_to_js)?,
-        )
+        let access = AccessQuery {
+            vehicle: VehicleClass::from_string(&vehicle).map_err(err_to_js)?,
+            minute_of_day,
+            ..Default::default()
+        };
+        Ok(serde_json::to_string(&self.project.compare_route(
+            Coord { x: x1, y: y1 },
+            Coord { x: x2, y: y2 },
+            main_road_penalty,
+            access,
+        ))
+        .map_err(err_to_js)?)
     }
 
     /// Returns GJ with a LineString per interior road
     #[wasm_bindgen(js_name = impactToOneDestination)]
     pub fn impact_to_one_destination(&mut self, x: f64, y: f64) -> Result<String, JsValue> {
-        let pt = self.map.mercator.pt_to_mercator(Coord { x, y });
-        Ok(serde_json::to_string(
-            &self.map.impact_to_one_destination(
-                pt,
-                self.neighbourhood.as_ref().unwrap().editable_roads(),
-            ),
-        )
-        .map_err(err_to_js)?)
+        let gj = self
+            .project
+            .impact_to_one_destination(Coord { x, y })
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Takes JSON with the max `iterations` of rerouting (0 ignores congestion), the
+    /// `convergence` threshold, `bpr_alpha` and `bpr_beta` parameters, and `period_hours` the OD
+    /// requests span
+    #[wasm_bindgen(js_name = setAssignmentSettings)]
+    pub fn set_assignment_settings(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let settings: AssignmentSettings = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_assignment_settings(settings);
+        Ok(())
+    }
+
+    /// Takes JSON with how many route `samples` to take per trip (1 always picks the fastest
+    /// route), the `spread` of random travel times between samples, and a `seed`
+    #[wasm_bindgen(js_name = setRouteChoice)]
+    pub fn set_route_choice(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let route_choice: RouteChoice = serde_wasm_bindgen::from_value(input)?;
+        self.project.set_route_choice(route_choice);
+        Ok(())
     }
 
     /// Returns GJ with a LineString per road, with before/after counts
     #[wasm_bindgen(js_name = predictImpact)]
     pub fn predict_impact(&mut self) -> Result<String, JsValue> {
-        self.map.rebuild_router(1.0);
-        let mut impact = self.map.impact.take().unwrap();
-        let out = impact.recalculate(&self.map);
-        self.map.impact = Some(impact);
-        Ok(serde_json::to_string(&out).map_err(err_to_js)?)
+        Ok(serde_json::to_string(&self.project.predict_impact()).map_err(err_to_js)?)
+    }
+
+    /// Returns GJ with emergency response times to every interior road of the current
+    /// neighbourhood. `depots` is a list of `[x, y]` WGS84 points.
+    #[wasm_bindgen(js_name = responseTimes)]
+    pub fn response_times(
+        &self,
+        depots: JsValue,
+        passable_kinds: Vec<String>,
+        threshold_seconds: f64,
+    ) -> Result<String, JsValue> {
+        let depots: Vec<[f64; 2]> = serde_wasm_bindgen::from_value(depots)?;
+        let passable_kinds = passable_kinds
+            .iter()
+            .map(|x| FilterKind::from_string(x))
+            .collect::<Result<_, _>>()
+            .map_err(err_to_js)?;
+        let gj = self
+            .project
+            .response_times(
+                depots.into_iter().map(|[x, y]| Coord { x, y }).collect(),
+                passable_kinds,
+                threshold_seconds,
+            )
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Returns GJ with walking, cycling, and driving directness between interior roads of the
+    /// current neighbourhood
+    #[wasm_bindgen(js_name = getPermeability)]
+    pub fn get_permeability(&mut self) -> Result<String, JsValue> {
+        let gj = self.project.permeability().map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Returns GJ with a feature per place an OSM bus route is broken, and a `routes` foreign
+    /// member summarizing every route
+    #[wasm_bindgen(js_name = checkBusRoutes)]
+    pub fn check_bus_routes(&self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&self.project.check_bus_routes()).map_err(err_to_js)?)
+    }
+
+    /// Returns GJ with per-road traffic counts that differ between two scenarios
+    #[wasm_bindgen(js_name = compareScenarioImpact)]
+    pub fn compare_scenario_impact(&self, a: String, b: String) -> Result<String, JsValue> {
+        let gj = self
+            .project
+            .compare_scenario_impact(&a, &b)
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
     }
 
     /// Returns a JSON blob [{before, after}], with before and after being LineStrings
     #[wasm_bindgen(js_name = getImpactsOnRoad)]
-    pub fn get_impacts_on_road(&self, road: usize) -> Result<String, JsValue> {
-        // Shouldn't need to recalculate impact
-        Ok(serde_json::to_string(
-            &self
-                .map
-                .impact
-                .as_ref()
-                .unwrap()
-                .get_impacts_on_road(&self.map, RoadID(road)),
+    pub fn get_impacts_on_road(&mut self, road: usize) -> Result<String, JsValue> {
+        Ok(
+            serde_json::to_string(&self.project.get_impacts_on_road(RoadID(road)))
+                .map_err(err_to_js)?,
         )
-        .map_err(err_to_js)?)
     }
 
     #[wasm_bindgen(js_name = getAllIntersections)]
     pub fn get_all_intersections(&self) -> Result<String, JsValue> {
+        let map = &self.project.map;
         Ok(serde_json::to_string(&GeoJson::from(
-            self.map
-                .intersections
+            map.intersections
                 .iter()
                 .map(|i| {
-                    let mut f = self.map.mercator.to_wgs84_gj(&i.point);
-                    f.set_property("has_turn_restrictions", !i.turn_restrictions.is_empty());
+                    let mut f = map.mercator.to_wgs84_gj(&i.point);
+                    f.set_property(
+                        "has_turn_restrictions",
+                        map.banned_turns_at(i.id).next().is_some() || !i.restrictions.is_empty(),
+                    );
                     f.set_property("intersection_id", i.id.0);
+                    f.set_property("control", i.control.name());
                     f
                 })
                 .collect::<Vec<_>>(),
@@ -400,25 +636,15 @@ impl LTN {
     #[wasm_bindgen(js_name = getMovements)]
     pub fn get_movements(&self, intersection: usize) -> Result<String, JsValue> {
         Ok(
-            serde_json::to_string(&self.map.get_movements(IntersectionID(intersection)))
+            serde_json::to_string(&self.project.map.get_movements(IntersectionID(intersection)))
                 .map_err(err_to_js)?,
         )
     }
 
     #[wasm_bindgen(js_name = getDemandModel)]
     pub fn get_demand_model(&self) -> Result<String, JsValue> {
-        let Some(ref demand) = self.map.demand else {
-            return Err(JsValue::from_str("no demand model"));
-        };
-        Ok(serde_json::to_string(&demand.to_gj(&self.map)).map_err(err_to_js)?)
-    }
-
-    // TODO This is also internal to MapModel. But not sure who should own Neighbourhood or how to
-    // plumb, so duplicting here.
-    fn after_edit(&mut self) {
-        if let Some(ref mut n) = self.neighbourhood {
-            n.after_edit(&self.map);
-        }
+        let gj = self.project.get_demand_model().map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
     }
 }
 
@@ -1,16 +1,29 @@
+use crate::access::{AccessPolicy, AccessQuery};
+use crate::active_travel::ActiveTravelNetwork;
+use crate::bus_routes::BusRoute;
+use crate::edit_log::EditLogEntry;
 use crate::geo_helpers::{
     angle_of_pt_on_line, bearing_from_endpoint, buffer_aabb, diagonal_bearing,
     invert_multi_polygon, limit_angle, linestring_intersection,
 };
+use crate::history::EditHistory;
 use crate::impact::Impact;
+use crate::intersection_control::{IntersectionControl, IntersectionDelays};
+use crate::movements::pt_near_intersection;
+use crate::reanchor::OsmIndex;
 use crate::route::RouterInput;
-use crate::{od::DemandModel, Router};
-use anyhow::Result;
+use crate::route_choice::RouteChoice;
+use crate::savefile::{EditMatch, LoadReport, MatchStatus, SavedGroupRoad};
+use crate::speed_profiles::{SpeedProfiles, TimePeriod};
+use crate::speeds::{infer_speed_limit, SpeedRegion, SpeedSource};
+use crate::turn_restrictions::TurnRestriction;
+use crate::turns::TurnPenalties;
+use crate::{od::DemandModel, Error, Router, Savefile, SavefileFeature, Scenario};
 use geo::{
     Closest, ClosestPoint, Coord, Distance, Euclidean, Length, LineInterpolatePoint,
     LineLocatePoint, LineString, MultiPolygon, Point, Polygon,
 };
-use geojson::{Feature, FeatureCollection, GeoJson, Geometry, JsonValue};
+use geojson::{Feature, FeatureCollection, GeoJson, JsonValue};
 use rstar::{primitives::GeomWithData, RTree, AABB};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
@@ -21,12 +34,15 @@ pub struct MapModel {
     pub roads: Vec<Road>,
     pub intersections: Vec<Intersection>,
     pub bus_routes_on_roads: HashMap<osm_reader::WayID, Vec<String>>,
+    pub bus_routes: Vec<BusRoute>,
     // All geometry stored in worldspace, including rtrees
     pub mercator: Mercator,
     pub study_area_name: Option<String>,
     pub boundary_wgs84: MultiPolygon,
     pub closest_road: RTree<GeomWithData<LineString, RoadID>>,
     pub closest_intersection: RTree<GeomWithData<Point, IntersectionID>>,
+    // Footways, cycleways, and paths too, only for walking and cycling
+    pub active_travel: ActiveTravelNetwork,
 
     // Only those acting as severances; above or belowground don't count
     pub railways: Vec<LineString>,
@@ -46,6 +62,24 @@ pub struct MapModel {
 
     // Every road is filled out
     pub travel_flows: BTreeMap<RoadID, TravelFlow>,
+    // (intersection, from, to) movements that aren't allowed. Starts with the simple turn
+    // restrictions from OSM.
+    pub banned_turns: BTreeSet<(IntersectionID, RoadID, RoadID)>,
+    // Applies before and after edits
+    pub turn_penalties: TurnPenalties,
+    pub intersection_delays: IntersectionDelays,
+    /// Picks the default speed limits for roads without `maxspeed`. None if the study area isn't
+    /// in a supported region.
+    pub speed_region: Option<SpeedRegion>,
+    /// Observed speeds used instead of speed limits, depending on the `time_period`
+    pub speed_profiles: SpeedProfiles,
+    pub time_period: TimePeriod,
+    /// Whether trips for impact prediction and shortcuts spread over several routes
+    pub route_choice: RouteChoice,
+
+    // The edits above belong to this scenario
+    pub active_scenario: String,
+    pub other_scenarios: BTreeMap<String, Scenario>,
 
     pub impact: Option<Impact>,
     pub demand: Option<DemandModel>,
@@ -53,6 +87,14 @@ pub struct MapModel {
     // TODO Keep edits / state here or not?
     pub undo_stack: Vec<Command>,
     pub redo_queue: Vec<Command>,
+    // The undo/redo history above belongs to this neighbourhood. Other neighbourhoods in the
+    // active scenario have their history stashed.
+    pub history_neighbourhood: Option<String>,
+    pub other_histories: BTreeMap<String, EditHistory>,
+    // Every edit ever applied, across all scenarios, for an audit trail
+    pub edit_log: Vec<EditLogEntry>,
+    // Credited in the edit log
+    pub edit_author: Option<String>,
     // Stores boundary polygons in WGS84, with ALL of their GeoJSON props.
     // TODO Reconsider
     pub boundaries: BTreeMap<String, Feature>,
@@ -86,6 +128,9 @@ pub struct Road {
     pub linestring: LineString,
     pub tags: Tags,
     pub speed_mph: usize,
+    pub speed_source: SpeedSource,
+    /// False for footways, cycleways, and paths, which only exist in the `ActiveTravelNetwork`
+    pub motorised: bool,
 }
 
 impl fmt::Debug for Road {
@@ -100,6 +145,7 @@ impl fmt::Debug for Road {
         .field("linestring", &self.linestring)
         .field("tags", &self.tags)
         .field("speed_mph", &self.speed_mph)
+        .field("speed_source", &self.speed_source)
         .finish()
     }
 }
@@ -114,14 +160,34 @@ pub struct Intersection {
     pub roads: Vec<RoadID>,
     /// (from, to) is not allowed. May be redundant with the road TravelFlow.
     pub turn_restrictions: Vec<(RoadID, RoadID)>,
+    /// More complex restrictions starting here, from one of this intersection's roads
+    pub restrictions: Vec<TurnRestriction>,
+    /// Signals, stop or give way signs, or a roundabout, inferred from OSM
+    pub control: IntersectionControl,
 }
 
 impl Intersection {
-    pub(crate) fn from_graph(mut value: osm2graph::Intersection, roads: &[Road]) -> Self {
+    pub(crate) fn from_graph(value: osm2graph::Intersection, roads: &[Road]) -> Self {
+        Self::new(
+            IntersectionID(value.id.0),
+            value.osm_node,
+            value.point,
+            value.edges.into_iter().map(|e| RoadID(e.0)).collect(),
+            roads,
+        )
+    }
+
+    pub(crate) fn new(
+        id: IntersectionID,
+        node: osm_reader::NodeID,
+        point: Point,
+        mut intersection_roads: Vec<RoadID>,
+        roads: &[Road],
+    ) -> Self {
         // Sort intersection roads clockwise, starting from North
-        value.edges.sort_by_cached_key(|road_id| {
+        intersection_roads.sort_by_cached_key(|road_id| {
             let road = &roads[road_id.0];
-            let bearing = bearing_from_endpoint(value.point, &road.linestring);
+            let bearing = bearing_from_endpoint(point, &road.linestring);
             // work around that f64 is not Ord
             debug_assert!(
                 bearing.is_finite(),
@@ -131,11 +197,13 @@ impl Intersection {
         });
 
         Intersection {
-            id: IntersectionID(value.id.0),
-            point: value.point,
-            node: value.osm_node,
-            roads: value.edges.into_iter().map(|e| RoadID(e.0)).collect(),
+            id,
+            point,
+            node,
+            roads: intersection_roads,
             turn_restrictions: Vec::new(),
+            restrictions: Vec::new(),
+            control: IntersectionControl::Uncontrolled,
         }
     }
 
@@ -154,7 +222,16 @@ impl Intersection {
             if to_road.id == from_road.id {
                 return None;
             }
-            if self.turn_restrictions.contains(&(from_r, to_road.id)) {
+            if router_input.turn_banned(self.id, from_r, to_road.id) {
+                return None;
+            }
+            // Restrictions through other roads are handled by the Router
+            if self.restrictions.iter().any(|restriction| {
+                restriction.from == from_r
+                    && restriction.via.is_empty()
+                    && restriction.bans(to_road.id)
+                    && router_input.turn_restriction_applies(restriction)
+            }) {
                 return None;
             }
             if let Some(diagonal_filter) = router_input.diagonal_filter(self.id) {
@@ -191,7 +268,7 @@ impl MapModel {
         boundary_wgs84: MultiPolygon,
         study_area_name: Option<String>,
         demand: Option<DemandModel>,
-    ) -> Result<MapModel> {
+    ) -> Result<MapModel, Error> {
         crate::create::create_from_osm(input_bytes, boundary_wgs84, study_area_name, demand)
     }
 
@@ -203,6 +280,18 @@ impl MapModel {
         &self.intersections[i.0]
     }
 
+    /// The simple turn restrictions from OSM, before any edits
+    pub fn osm_banned_turns(&self) -> BTreeSet<(IntersectionID, RoadID, RoadID)> {
+        self.intersections
+            .iter()
+            .flat_map(|i| {
+                i.turn_restrictions
+                    .iter()
+                    .map(move |(from, to)| (i.id, *from, *to))
+            })
+            .collect()
+    }
+
     pub fn find_edge(&self, i1: IntersectionID, i2: IntersectionID) -> &Road {
         // TODO Store lookup table
         for r in &self.get_i(i1).roads {
@@ -219,20 +308,32 @@ impl MapModel {
         pt: Coord,
         candidate_roads: Option<Vec<RoadID>>,
         kind: FilterKind,
-    ) {
-        let cmd = self.do_edit(self.add_modal_filter_cmd(pt, candidate_roads, kind));
-        self.undo_stack.push(cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+    ) -> Result<(), Error> {
+        let cmd = self
+            .add_modal_filter_cmd(pt, candidate_roads, kind)
+            .ok_or(Error::NoRoadNearPoint)?;
+        self.apply_edit("add_modal_filter", cmd);
+        Ok(())
     }
 
     fn add_modal_filter_cmd(
         &self,
         pt: Coord,
         candidate_roads: Option<Vec<RoadID>>,
+        kind: FilterKind,
+    ) -> Option<Command> {
+        let (r, percent_along) = self.closest_point_on_road(pt, candidate_roads)?;
+        Some(self.modal_filter_cmd(r, percent_along, kind, None))
+    }
+
+    /// Without an `access` policy, the default for the kind of filter is used
+    pub(crate) fn modal_filter_cmd(
+        &self,
+        r: RoadID,
+        percent_along: f64,
         mut kind: FilterKind,
+        access: Option<AccessPolicy>,
     ) -> Command {
-        let (r, percent_along) = self.closest_point_on_road(pt, candidate_roads).unwrap();
         if self.get_bus_routes_on_road(r).is_some() && kind != FilterKind::BusGate {
             info!("Using a BusGate instead of {kind:?} for a road");
             kind = FilterKind::BusGate;
@@ -242,15 +343,48 @@ impl MapModel {
             Some(ModalFilter {
                 percent_along,
                 kind,
+                access: access.unwrap_or_else(|| AccessPolicy::default_for(kind)),
             }),
         )
     }
 
+    /// Changes who can pass through an existing filter, and when
+    pub fn set_modal_filter_access(
+        &mut self,
+        r: RoadID,
+        access: AccessPolicy,
+    ) -> Result<(), Error> {
+        let Some(filter) = self.modal_filters.get(&r) else {
+            return Err(Error::NoModalFilter(r));
+        };
+        let filter = ModalFilter {
+            access,
+            ..filter.clone()
+        };
+        self.apply_edit(
+            "set_modal_filter_access",
+            Command::SetModalFilter(r, Some(filter)),
+        );
+        Ok(())
+    }
+
     fn closest_point_on_road(
         &self,
         click_pt: Coord,
         candidate_roads: Option<Vec<RoadID>>,
     ) -> Option<(RoadID, f64)> {
+        self.roads_near_point(click_pt, candidate_roads)
+            .first()
+            .map(|(_, r, percent_along)| (*r, *percent_along))
+    }
+
+    /// Returns (distance in meters, road, percent along the road) for candidate roads, closest
+    /// first
+    pub(crate) fn roads_near_point(
+        &self,
+        click_pt: Coord,
+        candidate_roads: Option<Vec<RoadID>>,
+    ) -> Vec<(f64, RoadID, f64)> {
         // If candidate_roads is not specified, search around the point with a generous buffer
         let roads = candidate_roads.unwrap_or_else(|| {
             let bbox = buffer_aabb(AABB::from_point(click_pt.into()), 50.0);
@@ -260,7 +394,7 @@ impl MapModel {
                 .collect()
         });
 
-        roads
+        let mut results: Vec<(usize, RoadID, f64)> = roads
             .into_iter()
             .filter_map(|r| {
                 let road = self.get_r(r);
@@ -276,16 +410,23 @@ impl MapModel {
                     None
                 }
             })
-            .min_by_key(|pair| pair.0)
-            .map(|pair| (pair.1, pair.2))
+            .collect();
+        results.sort_by_key(|pair| pair.0);
+        results
+            .into_iter()
+            .map(|(score, r, percent_along)| (score as f64 / 100.0, r, percent_along))
+            .collect()
     }
 
-    fn most_similar_linestring(&self, linestring: &LineString) -> RoadID {
+    /// Returns (mean distance in meters between endpoints, road) for all roads, most similar
+    /// first
+    pub(crate) fn similar_linestrings(&self, linestring: &LineString) -> Vec<(f64, RoadID)> {
         // TODO Detect many possible cases of OSM data changing. Could at least compare the length
         // of the candidate. Decide how to handle possible splits/merges.
-        self.roads
+        let mut results: Vec<(usize, RoadID)> = self
+            .roads
             .iter()
-            .min_by_key(|r| {
+            .map(|r| {
                 let diff1 = Euclidean.distance(
                     r.linestring.points().next().unwrap(),
                     linestring.points().next().unwrap(),
@@ -294,13 +435,17 @@ impl MapModel {
                     r.linestring.points().last().unwrap(),
                     linestring.points().last().unwrap(),
                 );
-                ((diff1 + diff2) * 100.0) as usize
+                (((diff1 + diff2) * 100.0) as usize, r.id)
             })
-            .unwrap()
-            .id
+            .collect();
+        results.sort_by_key(|pair| pair.0);
+        results
+            .into_iter()
+            .map(|(score, r)| (score as f64 / 200.0, r))
+            .collect()
     }
 
-    fn after_edited(&mut self) {
+    pub(crate) fn after_edited(&mut self) {
         self.router_after = None;
         self.impact.as_mut().unwrap().invalidate_after_edits();
     }
@@ -326,49 +471,47 @@ impl MapModel {
                     Some(ModalFilter {
                         percent_along,
                         kind: use_kind,
+                        access: AccessPolicy::default_for(use_kind),
                     }),
                 ));
             }
         }
-        let cmd = self.do_edit(Command::Multiple(edits));
-        self.undo_stack.push(cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+        self.apply_edit("add_many_modal_filters", Command::Multiple(edits));
     }
 
     pub fn delete_modal_filter(&mut self, r: RoadID) {
-        let cmd = self.do_edit(Command::SetModalFilter(r, None));
-        self.undo_stack.push(cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+        self.apply_edit("delete_modal_filter", Command::SetModalFilter(r, None));
     }
 
     pub fn add_diagonal_filter(&mut self, i: IntersectionID) {
         let intersection = self.get_i(i);
         let diagonal_filter = DiagonalFilter::new(intersection, false, self);
         let cmd = Command::SetDiagonalFilter(i, Some(diagonal_filter));
-        let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+        self.apply_edit("add_diagonal_filter", cmd);
     }
 
     pub fn rotate_diagonal_filter(&mut self, i: IntersectionID) {
         let intersection = self.get_i(i);
         let diagonal_filter = DiagonalFilter::new(intersection, true, self);
         let cmd = Command::SetDiagonalFilter(i, Some(diagonal_filter));
-        let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+        self.apply_edit("rotate_diagonal_filter", cmd);
+    }
+
+    /// Splits the roads at any intersection into groups that traffic can't travel between
+    pub fn set_diagonal_filter_groups(
+        &mut self,
+        i: IntersectionID,
+        groups: Vec<Vec<RoadID>>,
+    ) -> Result<(), Error> {
+        let diagonal_filter = DiagonalFilter::with_groups(self.get_i(i), groups, self)?;
+        let cmd = Command::SetDiagonalFilter(i, Some(diagonal_filter));
+        self.apply_edit("set_diagonal_filter_groups", cmd);
+        Ok(())
     }
 
     pub fn delete_diagonal_filter(&mut self, i: IntersectionID) {
         let cmd = Command::SetDiagonalFilter(i, None);
-        let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_queue.clear();
-        self.after_edited();
+        self.apply_edit("delete_diagonal_filter", cmd);
     }
 
     pub fn toggle_travel_flow(&mut self, r: RoadID) {
@@ -377,8 +520,33 @@ impl MapModel {
             TravelFlow::BACKWARDS => TravelFlow::BothWays,
             TravelFlow::BothWays => TravelFlow::FORWARDS,
         };
-        let cmd = self.do_edit(Command::SetTravelFlow(r, dir));
-        self.undo_stack.push(cmd);
+        self.apply_edit("toggle_travel_flow", Command::SetTravelFlow(r, dir));
+    }
+
+    /// Bans or allows turning from one road to another at an intersection, overriding OSM
+    pub fn set_turn_banned(
+        &mut self,
+        i: IntersectionID,
+        from: RoadID,
+        to: RoadID,
+        banned: bool,
+    ) -> Result<(), Error> {
+        let intersection = self.get_i(i);
+        if from == to || !intersection.roads.contains(&from) || !intersection.roads.contains(&to) {
+            return Err(Error::UnknownMovement(i, from, to));
+        }
+        self.apply_edit(
+            "set_turn_banned",
+            Command::SetTurnBanned(i, (from, to), banned),
+        );
+        Ok(())
+    }
+
+    /// Applies a new edit, tracking it for undo and in the edit log
+    fn apply_edit(&mut self, action: &str, cmd: Command) {
+        self.log_edit(action, &cmd);
+        let undo_cmd = self.do_edit(cmd);
+        self.undo_stack.push(undo_cmd);
         self.redo_queue.clear();
         self.after_edited();
     }
@@ -414,6 +582,15 @@ impl MapModel {
                 self.travel_flows.insert(r, dir);
                 Command::SetTravelFlow(r, prev)
             }
+            Command::SetTurnBanned(i, (from, to), banned) => {
+                info!("changed whether turning from {from} to {to} at {i} is banned to {banned}");
+                let prev = if banned {
+                    !self.banned_turns.insert((i, from, to))
+                } else {
+                    self.banned_turns.remove(&(i, from, to))
+                };
+                Command::SetTurnBanned(i, (from, to), prev)
+            }
             Command::Multiple(list) => {
                 let undo_list = list.into_iter().map(|cmd| self.do_edit(cmd)).collect();
                 Command::Multiple(undo_list)
@@ -425,6 +602,7 @@ impl MapModel {
         // The UI shouldn't call this when the stack is empty, but when holding down the redo key,
         // it doesn't update fast enough
         if let Some(cmd) = self.undo_stack.pop() {
+            self.log_edit("undo", &cmd);
             let cmd = self.do_edit(cmd);
             self.redo_queue.push(cmd);
             self.after_edited();
@@ -436,6 +614,7 @@ impl MapModel {
             return;
         }
         let cmd = self.redo_queue.remove(0);
+        self.log_edit("redo", &cmd);
         let cmd = self.do_edit(cmd);
         self.undo_stack.push(cmd);
         self.after_edited();
@@ -455,6 +634,7 @@ impl MapModel {
             let angle = limit_angle(angle_of_pt_on_line(&road.linestring, pt.into()) + 90.0);
             let mut f = self.mercator.to_wgs84_gj(&pt);
             f.set_property("filter_kind", filter.kind.to_string());
+            f.set_property("access", serde_json::to_value(&filter.access).unwrap());
             f.set_property("road", r.0);
             f.set_property("angle", angle);
             f.set_property("edited", Some(filter) != self.original_modal_filters.get(r));
@@ -469,6 +649,16 @@ impl MapModel {
             // part of being a "filter"
             f.set_property("edited", true);
             features.push(f);
+
+            // The 4-way icon doesn't fit other groups, so draw each barrier
+            if filter.custom {
+                for line in filter.barrier_lines(intersection) {
+                    let mut f = self.mercator.to_wgs84_gj(&line);
+                    f.set_property("filter_kind", "diagonal_filter_barrier");
+                    f.set_property("intersection_id", i.0);
+                    features.push(f);
+                }
+            }
         }
         FeatureCollection {
             features,
@@ -477,21 +667,100 @@ impl MapModel {
         }
     }
 
-    /// Because ids like RoadID and IntersectionID aren't guaranteed to be stable across loads,
-    /// we use more permanent markers like GPS points to map to features.
-    pub fn to_savefile(&self) -> FeatureCollection {
+    /// Modal filters and named boundaries, for every scenario. This is meant for savefiles, so
+    /// existing filters aren't included (and deletions of existing are included)
+    /// Undo/redo history is only included if `include_history` is set
+    pub fn to_savefile(&self, include_history: bool) -> Savefile {
+        let mut features = Vec::new();
+
+        let scenarios = self.scenario_names();
+        for name in &scenarios {
+            let scenario = self.get_scenario(name).unwrap();
+            self.scenario_to_savefile(name, &scenario, &mut features);
+        }
+
+        for (name, feature) in &self.boundaries {
+            features.push(SavefileFeature::Boundary {
+                name: name.clone(),
+                feature: feature.clone(),
+            });
+        }
+
+        features.push(SavefileFeature::StudyAreaBoundary {
+            boundary: self.boundary_wgs84.clone(),
+        });
+
+        Savefile {
+            study_area_name: self.study_area_name.clone(),
+            scenarios,
+            active_scenario: self.active_scenario.clone(),
+            features,
+            edit_history: if include_history {
+                self.save_edit_histories()
+            } else {
+                BTreeMap::new()
+            },
+            edit_log: self.edit_log.clone(),
+        }
+    }
+
+    fn scenario_to_savefile(
+        &self,
+        name: &str,
+        scenario: &Scenario,
+        features: &mut Vec<SavefileFeature>,
+    ) {
         // Edited filters only
-        let mut gj = self.filters_to_gj();
-        gj.features
-            .retain(|f| f.property("edited").unwrap().as_bool().unwrap());
-        for f in &mut gj.features {
-            f.set_property("kind", "modal_filter");
-            f.remove_property("road");
+        for (r, filter) in &scenario.modal_filters {
+            if Some(filter) == self.original_modal_filters.get(r) {
+                continue;
+            }
+            let pt = self
+                .get_r(*r)
+                .linestring
+                .line_interpolate_point(filter.percent_along)
+                .unwrap();
+            features.push(SavefileFeature::ModalFilter {
+                scenario: name.to_string(),
+                pt: self.mercator.to_wgs84(&pt),
+                kind: filter.kind,
+                access: (filter.access != AccessPolicy::default_for(filter.kind))
+                    .then(|| filter.access.clone()),
+                anchor: Some(self.osm_anchor(*r, Some(filter.percent_along))),
+            });
+        }
+        for (i, filter) in &scenario.diagonal_filters {
+            features.push(SavefileFeature::DiagonalFilter {
+                scenario: name.to_string(),
+                pt: self.mercator.to_wgs84(&self.get_i(*i).point),
+                is_rotated: filter.is_rotated,
+                osm_node: Some(self.get_i(*i).node.0),
+                groups: filter.custom.then(|| {
+                    filter
+                        .groups
+                        .iter()
+                        .map(|group| {
+                            group
+                                .iter()
+                                .map(|r| {
+                                    let road = self.get_r(*r);
+                                    let pt =
+                                        self.mercator.to_wgs84(&pt_near_intersection(*i, road));
+                                    SavedGroupRoad {
+                                        way: road.way.0,
+                                        pt: (pt.x(), pt.y()),
+                                    }
+                                })
+                                .collect()
+                        })
+                        .collect()
+                }),
+            });
         }
 
         // Look for any basemap filters that were deleted entirely
         for (r, filter) in &self.original_modal_filters {
-            if self.modal_filters.contains_key(r) {
+            if scenario.modal_filters.contains_key(r) {
                 continue;
             }
             let pt = self
@@ -499,127 +768,291 @@ impl MapModel {
                 .linestring
                 .line_interpolate_point(filter.percent_along)
                 .unwrap();
-            let mut f = self.mercator.to_wgs84_gj(&pt);
-            f.set_property("kind", "deleted_existing_modal_filter");
-            gj.features.push(f);
+            features.push(SavefileFeature::DeletedExistingModalFilter {
+                scenario: name.to_string(),
+                pt: self.mercator.to_wgs84(&pt),
+                anchor: Some(self.osm_anchor(*r, Some(filter.percent_along))),
+            });
+        }
+
+        // Turns banned or allowed, compared to OSM
+        for i in &self.intersections {
+            for from in &i.roads {
+                for to in &i.roads {
+                    let banned = scenario.banned_turns.contains(&(i.id, *from, *to));
+                    if from == to || banned == i.turn_restrictions.contains(&(*from, *to)) {
+                        continue;
+                    }
+                    features.push(SavefileFeature::TurnBan {
+                        scenario: name.to_string(),
+                        linestring: self
+                            .mercator
+                            .to_wgs84(&self.movement_linestring(i.id, *from, *to)),
+                        banned,
+                        anchor: Some(self.turn_anchor(i.id, *from, *to)),
+                    });
+                }
+            }
         }
 
         // Any travel flow edits
         for r in &self.roads {
-            if self.travel_flows[&r.id] != TravelFlow::from_osm(&r.tags) {
-                let mut f = self.mercator.to_wgs84_gj(&r.linestring);
-                f.set_property("kind", "travel_flow");
-                f.set_property("travel_flow", self.travel_flows[&r.id].to_string());
-                gj.features.push(f);
+            if scenario.travel_flows[&r.id] != TravelFlow::from_osm(&r.tags) {
+                features.push(SavefileFeature::TravelFlow {
+                    scenario: name.to_string(),
+                    linestring: self.mercator.to_wgs84(&r.linestring),
+                    travel_flow: scenario.travel_flows[&r.id],
+                    anchor: Some(self.osm_anchor(r.id, None)),
+                });
             }
         }
-
-        gj.features.extend(self.boundaries.values().cloned());
-
-        let mut f = Feature::from(Geometry::from(&self.boundary_wgs84));
-        f.set_property("kind", "study_area_boundary");
-        gj.features.push(f);
-
-        gj.foreign_members = Some(
-            serde_json::json!({
-                "study_area_name": self.study_area_name,
-            })
-            .as_object()
-            .unwrap()
-            .clone(),
-        );
-        gj
     }
 
-    pub fn load_savefile(&mut self, gj: FeatureCollection) -> Result<()> {
+    /// Replaces all scenarios and boundaries. The savefile is validated and migrated from older
+    /// versions first. Saved edits are re-anchored to the current map by OSM IDs when possible,
+    /// or by geometry otherwise, and the report describes how well each one matched.
+    pub fn load_savefile(&mut self, gj: FeatureCollection) -> Result<LoadReport, Error> {
+        let savefile = Savefile::parse(gj)?;
+
         // Clear previous state
         self.boundaries.clear();
-        self.modal_filters = self.original_modal_filters.clone();
-        for (r, dir) in &mut self.travel_flows {
-            *dir = TravelFlow::from_osm(&self.roads[r.0].tags);
+        self.other_scenarios.clear();
+        self.clear_edit_histories();
+        self.history_neighbourhood = None;
+
+        let mut report = LoadReport::default();
+        let index = OsmIndex::new(self);
+
+        // Build up each scenario in turn as the active one, then stash it
+        for name in &savefile.scenarios {
+            self.replace_edits(Scenario::from_basemap(self));
+
+            // Filters could be defined for multiple neighbourhoods, not just the one
+            // in the savefile
+            let mut cmds = Vec::new();
+            for (feature_index, f) in savefile.features.iter().enumerate() {
+                if f.scenario() == Some(name.as_str()) {
+                    self.load_edit(&index, feature_index, f, &mut cmds, &mut report);
+                }
+            }
+            // Keep the undo stack empty. A user shouldn't be able to undo and clear the whole
+            // savefile.
+            self.do_edit(Command::Multiple(cmds));
+
+            let scenario = self.replace_edits(Scenario::from_basemap(self));
+            self.other_scenarios.insert(name.clone(), scenario);
+        }
+        let active = self
+            .other_scenarios
+            .remove(&savefile.active_scenario)
+            .unwrap();
+        self.replace_edits(active);
+        self.active_scenario = savefile.active_scenario;
+
+        for f in savefile.features {
+            if let SavefileFeature::Boundary { name, feature } = f {
+                self.boundaries.insert(name, feature);
+            }
+            // TODO Detect if the study area boundary is close enough to boundary_polygon?
+            // Overwrite?
+        }
+        self.restore_edit_histories(&savefile.edit_history);
+        // Loading isn't itself logged
+        self.edit_log = savefile.edit_log;
+
+        report.edits.sort_by_key(|e| e.feature_index);
+        for e in report.problems() {
+            warn!(
+                "Savefile {} #{} matched with status {:?}",
+                e.kind, e.feature_index, e.status
+            );
         }
-        self.undo_stack.clear();
-        self.redo_queue.clear();
 
-        // Filters could be defined for multiple neighbourhoods, not just the one
-        // in the savefile
-        let mut cmds = Vec::new();
+        self.after_edited();
 
-        for f in gj.features {
-            match f
-                .property("kind")
-                .expect("savefile feature missing `kind`")
-                .as_str()
-                .unwrap()
-            {
-                "modal_filter" => {
-                    let kind = FilterKind::from_string(get_str_prop(&f, "filter_kind")?)?;
-                    let gj_pt: Point = f.geometry.as_ref().unwrap().try_into()?;
-                    match kind {
-                        FilterKind::DiagonalFilter => {
-                            let i = {
-                                let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                                self.closest_intersection
-                                    .nearest_neighbor(&Point(pt))
-                                    .expect("intersection near saved editable intersection")
-                                    .data
-                            };
-                            let intersection = self.get_i(i);
-                            let filter = f.property("filter").unwrap().as_object().unwrap();
-                            let is_rotated = filter
-                                .get("is_rotated")
-                                .expect("missing is_rotated")
-                                .as_bool()
-                                .expect("expected a bool");
-
-                            let diagonal_filter =
-                                DiagonalFilter::new(intersection, is_rotated, self);
-                            self.diagonal_filters
-                                .insert(intersection.id, diagonal_filter);
+        Ok(report)
+    }
+
+    /// Re-anchors one saved edit to the active scenario. Most edits are added to `cmds`.
+    fn load_edit(
+        &mut self,
+        index: &OsmIndex,
+        feature_index: usize,
+        f: &SavefileFeature,
+        cmds: &mut Vec<Command>,
+        report: &mut LoadReport,
+    ) {
+        match f {
+            SavefileFeature::ModalFilter {
+                pt,
+                kind,
+                access,
+                anchor,
+                ..
+            } => {
+                let anchored = self.anchor_point(
+                    index,
+                    self.mercator.pt_to_mercator((*pt).into()),
+                    anchor.as_ref(),
+                    |_| true,
+                );
+                for (r, percent_along) in &anchored.matches {
+                    cmds.push(self.modal_filter_cmd(*r, *percent_along, *kind, access.clone()));
+                }
+                report.edits.push(EditMatch {
+                    feature_index,
+                    kind: "modal_filter",
+                    saved: (*pt).into(),
+                    status: anchored.status,
+                    matched_by: anchored.matched_by,
+                    roads: anchored.matches.iter().map(|(r, _)| *r).collect(),
+                    intersection: None,
+                    distance: anchored.distance,
+                });
+            }
+            SavefileFeature::DiagonalFilter {
+                pt,
+                is_rotated,
+                osm_node,
+                groups,
+                ..
+            } => {
+                let mut anchored = self.anchor_intersection(
+                    index,
+                    self.mercator.pt_to_mercator((*pt).into()),
+                    *osm_node,
+                );
+                let diagonal_filter = anchored.matches.first().map(|i| {
+                    let intersection = self.get_i(*i);
+                    match groups {
+                        Some(groups) => {
+                            let groups = groups
+                                .iter()
+                                .map(|group| {
+                                    group
+                                        .iter()
+                                        .filter_map(|saved| {
+                                            let pt = self.mercator.pt_to_mercator(saved.pt.into());
+                                            self.closest_road_at(*i, pt, Some(saved.way))
+                                        })
+                                        .collect()
+                                })
+                                .collect();
+                            DiagonalFilter::with_groups(intersection, groups, self)
+                                .map_err(|err| err.to_string())
                         }
-                        _ => {
-                            cmds.push(self.add_modal_filter_cmd(
-                                self.mercator.pt_to_mercator(gj_pt.into()),
-                                None,
-                                kind,
-                            ));
+                        None if intersection.roads.len() == 4 => {
+                            Ok(DiagonalFilter::new(intersection, *is_rotated, self))
                         }
+                        None => Err("intersection isn't a 4-way".to_string()),
                     }
+                });
+                match diagonal_filter {
+                    Some(Ok(diagonal_filter)) => {
+                        self.diagonal_filters
+                            .insert(anchored.matches[0], diagonal_filter);
+                    }
+                    Some(Err(reason)) => {
+                        anchored.status = MatchStatus::Dropped { reason };
+                        anchored.matched_by = None;
+                        anchored.matches.clear();
+                    }
+                    None => {}
                 }
-                "deleted_existing_modal_filter" => {
-                    let gj_pt: Point = f.geometry.unwrap().try_into()?;
-                    let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                    // TODO Better error handling if we don't match
-                    let (r, _) = self.closest_point_on_road(pt, None).unwrap();
-                    cmds.push(Command::SetModalFilter(r, None));
-                }
-                "travel_flow" => {
-                    let dir = TravelFlow::from_string(get_str_prop(&f, "travel_flow")?)?;
-                    let mut linestring: LineString = f.geometry.unwrap().try_into()?;
-                    self.mercator.to_mercator_in_place(&mut linestring);
-                    let r = self.most_similar_linestring(&linestring);
-                    cmds.push(Command::SetTravelFlow(r, dir));
+                report.edits.push(EditMatch {
+                    feature_index,
+                    kind: "modal_filter",
+                    saved: (*pt).into(),
+                    status: anchored.status,
+                    matched_by: anchored.matched_by,
+                    roads: Vec::new(),
+                    intersection: anchored.matches.first().cloned(),
+                    distance: anchored.distance,
+                });
+            }
+            SavefileFeature::DeletedExistingModalFilter { pt, anchor, .. } => {
+                // Only roads that had a filter originally are relevant
+                let anchored = self.anchor_point(
+                    index,
+                    self.mercator.pt_to_mercator((*pt).into()),
+                    anchor.as_ref(),
+                    |r| self.original_modal_filters.contains_key(&r),
+                );
+                for (r, _) in &anchored.matches {
+                    cmds.push(Command::SetModalFilter(*r, None));
                 }
-                "boundary" => {
-                    let name = get_str_prop(&f, "name")?;
-                    if self.boundaries.contains_key(name) {
-                        bail!("Multiple boundaries named {name} in savefile");
-                    }
-                    self.boundaries.insert(name.to_string(), f);
+                report.edits.push(EditMatch {
+                    feature_index,
+                    kind: "deleted_existing_modal_filter",
+                    saved: (*pt).into(),
+                    status: anchored.status,
+                    matched_by: anchored.matched_by,
+                    roads: anchored.matches.iter().map(|(r, _)| *r).collect(),
+                    intersection: None,
+                    distance: anchored.distance,
+                });
+            }
+            SavefileFeature::TravelFlow {
+                linestring,
+                travel_flow,
+                anchor,
+                ..
+            } => {
+                let anchored = self.anchor_linestring(
+                    index,
+                    &self.mercator.to_mercator(linestring),
+                    anchor.as_ref(),
+                );
+                for (r, reversed) in &anchored.matches {
+                    let dir = if *reversed {
+                        travel_flow.reversed()
+                    } else {
+                        *travel_flow
+                    };
+                    cmds.push(Command::SetTravelFlow(*r, dir));
                 }
-                "study_area_boundary" => {
-                    // TODO Detect if it's close enough to boundary_polygon? Overwrite?
+                report.edits.push(EditMatch {
+                    feature_index,
+                    kind: "travel_flow",
+                    saved: linestring.clone().into(),
+                    status: anchored.status,
+                    matched_by: anchored.matched_by,
+                    roads: anchored.matches.iter().map(|(r, _)| *r).collect(),
+                    intersection: None,
+                    distance: anchored.distance,
+                });
+            }
+            SavefileFeature::TurnBan {
+                linestring,
+                banned,
+                anchor,
+                ..
+            } => {
+                let anchored = self.anchor_turn(
+                    index,
+                    &self.mercator.to_mercator(linestring),
+                    anchor.as_ref(),
+                );
+                for (i, from, to) in &anchored.matches {
+                    cmds.push(Command::SetTurnBanned(*i, (*from, *to), *banned));
                 }
-                x => bail!("Unknown kind in savefile: {x}"),
+                report.edits.push(EditMatch {
+                    feature_index,
+                    kind: "turn_ban",
+                    saved: linestring.clone().into(),
+                    status: anchored.status,
+                    matched_by: anchored.matched_by,
+                    roads: anchored
+                        .matches
+                        .iter()
+                        .flat_map(|(_, from, to)| [*from, *to])
+                        .collect(),
+                    intersection: anchored.matches.first().map(|(i, _, _)| *i),
+                    distance: anchored.distance,
+                });
             }
+            SavefileFeature::Boundary { .. } | SavefileFeature::StudyAreaBoundary { .. } => {}
         }
-
-        // Keep the undo stack empty. A user shouldn't be able to undo and clear the whole
-        // savefile.
-        self.do_edit(Command::Multiple(cmds));
-        self.after_edited();
-
-        Ok(())
     }
 
     pub fn router_input_before(&self) -> impl RouterInput + use<'_> {
@@ -653,6 +1086,18 @@ impl MapModel {
                 // As there isn't a well known tagging / topological structure that we can easily identify.
                 None
             }
+
+            fn turn_penalties(&self) -> &TurnPenalties {
+                &self.map.turn_penalties
+            }
+
+            fn intersection_delays(&self) -> &IntersectionDelays {
+                &self.map.intersection_delays
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                self.map.road_cost_seconds(road)
+            }
         }
 
         RouterInputBefore { map: self }
@@ -686,10 +1131,92 @@ impl MapModel {
             fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
                 self.map.diagonal_filters.get(&i)
             }
+
+            fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+                self.map.banned_turns.contains(&(i, from, to))
+            }
+
+            fn turn_penalties(&self) -> &TurnPenalties {
+                &self.map.turn_penalties
+            }
+
+            fn intersection_delays(&self) -> &IntersectionDelays {
+                &self.map.intersection_delays
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                self.map.road_cost_seconds(road)
+            }
         }
         RouterInputAfter { map: self }
     }
 
+    /// Changes the extra time for turning, rebuilding routes before and after edits
+    pub fn set_turn_penalties(&mut self, turn_penalties: TurnPenalties) {
+        self.turn_penalties = turn_penalties;
+        self.rebuild_all_routers();
+    }
+
+    /// Changes the delay at signals, stop and give way signs, and roundabouts, rebuilding routes
+    /// before and after edits
+    pub fn set_intersection_delays(&mut self, intersection_delays: IntersectionDelays) {
+        self.intersection_delays = intersection_delays;
+        self.rebuild_all_routers();
+    }
+
+    /// Changes which legal default speed limits apply, inferring speeds for every road again and
+    /// rebuilding routes before and after edits
+    pub fn set_speed_region(&mut self, speed_region: Option<SpeedRegion>) {
+        self.speed_region = speed_region;
+        for road in self
+            .roads
+            .iter_mut()
+            .chain(self.active_travel.roads.iter_mut())
+        {
+            (road.speed_mph, road.speed_source) = infer_speed_limit(&road.tags, speed_region);
+        }
+        self.rebuild_all_routers();
+    }
+
+    /// Replaces the observed speeds per road, returning how many roads have one. Routes before
+    /// and after edits are rebuilt.
+    pub fn set_speed_profiles(&mut self, speed_profiles: SpeedProfiles) -> usize {
+        self.speed_profiles = speed_profiles;
+        self.rebuild_all_routers();
+        self.roads
+            .iter()
+            .filter(|r| self.speed_profiles.has_way(r.way))
+            .count()
+    }
+
+    /// Changes which observed speeds are used, rebuilding routes before and after edits
+    pub fn set_time_period(&mut self, time_period: TimePeriod) {
+        self.time_period = time_period;
+        self.rebuild_all_routers();
+    }
+
+    /// Changes how drivers pick between routes, for impact prediction and shortcuts
+    pub fn set_route_choice(&mut self, route_choice: RouteChoice) {
+        self.route_choice = route_choice;
+        self.impact.as_mut().unwrap().invalidate();
+    }
+
+    /// How long it takes a car to cross this road in the current time period. Without an observed
+    /// speed, this is at the speed limit.
+    pub fn road_cost_seconds(&self, road: &Road) -> f64 {
+        match self.speed_profiles.speed_mph(road.way, self.time_period) {
+            Some(mph) => road.cost_seconds_at(mph),
+            None => road.cost_seconds(),
+        }
+    }
+
+    fn rebuild_all_routers(&mut self) {
+        self.router_before = Router::new(&self.router_input_before(), 1.0);
+        self.router_before_with_penalty = None;
+        self.router_after = None;
+        self.impact.as_mut().unwrap().invalidate();
+    }
+
     // Lazily builds the router if needed.
     pub fn rebuild_router(&mut self, main_road_penalty: f64) {
         if self
@@ -714,16 +1241,37 @@ impl MapModel {
         }
     }
 
-    pub fn compare_route(&mut self, pt1: Coord, pt2: Coord, main_road_penalty: f64) -> GeoJson {
+    /// Routers for anything besides the default `AccessQuery` aren't cached
+    pub fn compare_route(
+        &mut self,
+        pt1: Coord,
+        pt2: Coord,
+        main_road_penalty: f64,
+        access: AccessQuery,
+    ) -> GeoJson {
         self.rebuild_router(main_road_penalty);
+        let uncached;
+        let (router_before, router_after) = if access == AccessQuery::default() {
+            (
+                self.router_before_with_penalty.as_ref().unwrap(),
+                self.router_after.as_ref().unwrap(),
+            )
+        } else {
+            uncached = (
+                Router::new(
+                    &access.clone().router_input(self.router_input_before()),
+                    main_road_penalty,
+                ),
+                Router::new(
+                    &access.router_input(self.router_input_after()),
+                    main_road_penalty,
+                ),
+            );
+            (&uncached.0, &uncached.1)
+        };
 
         let mut features = Vec::new();
-        if let Some(route) = self
-            .router_before_with_penalty
-            .as_ref()
-            .unwrap()
-            .route(self, pt1, pt2)
-        {
+        if let Some(route) = router_before.route(self, pt1, pt2) {
             let (distance, time) = route.get_distance_and_time(self);
             let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
             f.set_property("kind", "before");
@@ -731,7 +1279,7 @@ impl MapModel {
             f.set_property("time", time);
             features.push(f);
         }
-        if let Some(route) = self.router_after.as_ref().unwrap().route(self, pt1, pt2) {
+        if let Some(route) = router_after.route(self, pt1, pt2) {
             let (distance, time) = route.get_distance_and_time(self);
             let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
             f.set_property("kind", "after");
@@ -811,8 +1359,13 @@ impl MapModel {
 impl Road {
     // How long does it take for a car following the speed limit to cross this road?
     pub fn cost_seconds(&self) -> f64 {
+        self.cost_seconds_at(self.speed_mph as f64)
+    }
+
+    /// How long does it take to cross this road at some speed?
+    pub fn cost_seconds_at(&self, mph: f64) -> f64 {
         let meters = Euclidean.length(&self.linestring);
-        let meters_per_second = (self.speed_mph as f64) * 0.44704;
+        let meters_per_second = mph * 0.44704;
         meters / meters_per_second
     }
 
@@ -820,6 +1373,10 @@ impl Road {
         let mut f = mercator.to_wgs84_gj(&self.linestring);
         f.set_property("id", self.id.0);
         f.set_property("speed_mph", self.speed_mph);
+        f.set_property(
+            "speed_source",
+            serde_json::to_value(self.speed_source).unwrap(),
+        );
         // TODO Debug only, reconsider
         f.set_property("way", self.way.to_string());
         for (k, v) in &self.tags.0 {
@@ -833,13 +1390,16 @@ impl Road {
 pub struct ModalFilter {
     pub kind: FilterKind,
     pub percent_along: f64,
+    /// Who can pass and when. Starts as the default for `kind`.
+    pub access: AccessPolicy,
 }
 
-/// A DiagonalFilter is placed at a 4-way intersection, and prevents traffic from going "straight"
-/// through the intersection. Traffic must turn.
+/// A DiagonalFilter is placed at an intersection, and splits its roads into groups. Traffic can
+/// travel between roads in the same group, but not to another group.
 ///
-/// The DiagonalFilter can be placed in one of two rotations to determine which way traffic is forced
-/// to turn.
+/// At a 4-way intersection, the usual filter prevents traffic from going "straight" through the
+/// intersection. Traffic must turn. It can be placed in one of two rotations to determine which way
+/// traffic is forced to turn. At other intersections, the user chooses the groups.
 ///
 /// Note: When all the roads at the intersection are 1-way roads, there is only one reasonable
 /// orientation for the diagonal filter, the other orientation would effectively block the intersection.
@@ -847,24 +1407,33 @@ pub struct ModalFilter {
 /// and leave it up to the user to manually ensure the filter is orientated reasonably.
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct DiagonalFilter {
-    /// Travel within these roads are allowed, but not to the other group.
-    pub group_a: Vec<RoadID>,
-    /// Travel within these roads are allowed, but not to the other group.
-    pub group_b: Vec<RoadID>,
-    /// The topological orientation of the filter - it determines how `intersection.roads` are split
-    /// into `group_a` and `group_b`.
+    /// Every road at the intersection is in exactly one group
+    pub groups: Vec<Vec<RoadID>>,
+    /// The topological orientation of a 4-way filter - it determines how `intersection.roads` are
+    /// split into two groups. Always false for custom groups.
     pub is_rotated: bool,
-    /// How many degrees to rotate a vertical line to split `group_a` from `group_b`
+    /// True if the groups were chosen by the user, rather than being one of the two 4-way
+    /// rotations
+    pub custom: bool,
+    /// How many degrees to rotate a vertical line to split the groups. Only meaningful for 4-way
+    /// filters.
     pub angle: f32,
+    /// The bearings from the intersection between neighbouring roads in different groups, where a
+    /// barrier is drawn
+    pub barrier_bearings: Vec<f32>,
 }
 
 impl DiagonalFilter {
     /// Precondition: Intersection must be a 4-way intersection
-    fn new(intersection: &Intersection, is_rotated: bool, map_model: &MapModel) -> DiagonalFilter {
+    pub(crate) fn new(
+        intersection: &Intersection,
+        is_rotated: bool,
+        map_model: &MapModel,
+    ) -> DiagonalFilter {
         debug_assert_eq!(
             intersection.roads.len(),
             4,
-            "diagonal filters only support 4-way intersections"
+            "this kind of diagonal filter only supports 4-way intersections"
         );
 
         let split_offset = if is_rotated { 1 } else { 0 };
@@ -883,33 +1452,124 @@ impl DiagonalFilter {
         let bearing_1 = bearing_from_endpoint(intersection.point, &road_1.linestring);
         let bearing_2 = bearing_from_endpoint(intersection.point, &road_2.linestring);
         let angle = diagonal_bearing(bearing_1, bearing_2) as f32;
+        let groups = vec![group_a, group_b];
         DiagonalFilter {
-            group_a,
-            group_b,
+            barrier_bearings: barrier_bearings(intersection, &groups, map_model),
+            groups,
             is_rotated,
+            custom: false,
             angle,
         }
     }
 
+    /// Splits the roads of any intersection into the given groups. Every road must be in exactly
+    /// one group, and there must be at least two groups.
+    pub(crate) fn with_groups(
+        intersection: &Intersection,
+        groups: Vec<Vec<RoadID>>,
+        map_model: &MapModel,
+    ) -> Result<DiagonalFilter, Error> {
+        let groups: Vec<Vec<RoadID>> = groups.into_iter().filter(|g| !g.is_empty()).collect();
+        let mut seen = BTreeSet::new();
+        for r in groups.iter().flatten() {
+            if !intersection.roads.contains(r) || !seen.insert(*r) {
+                return Err(Error::InvalidDiagonalFilter(format!(
+                    "{r} isn't at {} or is in multiple groups",
+                    intersection.id
+                )));
+            }
+        }
+        if seen.len() != intersection.roads.len() {
+            return Err(Error::InvalidDiagonalFilter(format!(
+                "every road at {} must be in a group",
+                intersection.id
+            )));
+        }
+        if groups.len() < 2 {
+            return Err(Error::InvalidDiagonalFilter(
+                "there must be at least two groups".to_string(),
+            ));
+        }
+
+        let barrier_bearings = barrier_bearings(intersection, &groups, map_model);
+        Ok(DiagonalFilter {
+            angle: barrier_bearings.first().cloned().unwrap_or(0.0),
+            barrier_bearings,
+            groups,
+            is_rotated: false,
+            custom: true,
+        })
+    }
+
     // `movement`: (from, to)
     pub fn allows_movement(&self, movement: &(RoadID, RoadID)) -> bool {
         let (from, to) = movement;
 
-        debug_assert!(self.group_a.contains(from) || self.group_b.contains(from));
-        debug_assert!(self.group_a.contains(to) || self.group_b.contains(to));
+        debug_assert!(self.groups.iter().any(|g| g.contains(from)));
+        debug_assert!(self.groups.iter().any(|g| g.contains(to)));
 
-        (self.group_a.contains(from) && self.group_a.contains(to))
-            || (self.group_b.contains(from) && self.group_b.contains(to))
+        self.groups
+            .iter()
+            .any(|g| g.contains(from) && g.contains(to))
+    }
+
+    /// Short lines from the intersection along each barrier, for drawing custom filters
+    pub fn barrier_lines(&self, intersection: &Intersection) -> Vec<LineString> {
+        // In meters
+        let length = 15.0;
+        self.barrier_bearings
+            .iter()
+            .map(|bearing| {
+                // Bearings start north and go clockwise, and y points south
+                let radians = (*bearing as f64 - 90.0).to_radians();
+                let start = intersection.point.0;
+                let end = Coord {
+                    x: start.x + length * radians.cos(),
+                    y: start.y + length * radians.sin(),
+                };
+                LineString::new(vec![start, end])
+            })
+            .collect()
     }
 }
 
+/// Goes clockwise around the intersection, finding the bearing halfway between neighbouring roads
+/// in different groups
+fn barrier_bearings(
+    intersection: &Intersection,
+    groups: &[Vec<RoadID>],
+    map_model: &MapModel,
+) -> Vec<f32> {
+    let mut roads: Vec<(f64, usize)> = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(group, roads)| {
+            roads.iter().map(move |r| {
+                let linestring = &map_model.get_r(*r).linestring;
+                (bearing_from_endpoint(intersection.point, linestring), group)
+            })
+        })
+        .collect();
+    roads.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut bearings = Vec::new();
+    for (idx, (bearing1, group1)) in roads.iter().enumerate() {
+        let (bearing2, group2) = roads[(idx + 1) % roads.len()];
+        if *group1 != group2 {
+            let gap = (bearing2 - bearing1 + 360.0) % 360.0;
+            bearings.push(((bearing1 + gap / 2.0) % 360.0) as f32);
+        }
+    }
+    bearings
+}
+
 impl From<&DiagonalFilter> for JsonValue {
     fn from(value: &DiagonalFilter) -> Self {
         serde_json::to_value(value).expect("valid JSON fields")
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum FilterKind {
     WalkCycleOnly,
     NoEntry,
@@ -930,14 +1590,14 @@ impl FilterKind {
         }
     }
 
-    pub fn from_string(x: &str) -> Result<Self> {
+    pub fn from_string(x: &str) -> Result<Self, Error> {
         match x {
             "walk_cycle_only" => Ok(Self::WalkCycleOnly),
             "no_entry" => Ok(Self::NoEntry),
             "bus_gate" => Ok(Self::BusGate),
             "school_street" => Ok(Self::SchoolStreet),
             "diagonal_filter" => Ok(Self::DiagonalFilter),
-            _ => bail!("Invalid FilterKind: {x}"),
+            _ => Err(Error::UnknownFilterKind(x.to_string())),
         }
     }
 }
@@ -957,6 +1617,15 @@ impl TravelFlow {
     pub fn flows_backwards(self) -> bool {
         matches!(self, TravelFlow::BACKWARDS | TravelFlow::BothWays)
     }
+
+    /// The same flow, relative to a road pointing the other way
+    pub fn reversed(self) -> Self {
+        match self {
+            TravelFlow::FORWARDS => TravelFlow::BACKWARDS,
+            TravelFlow::BACKWARDS => TravelFlow::FORWARDS,
+            TravelFlow::BothWays => TravelFlow::BothWays,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, PartialOrd, Ord, Eq, Serialize, Deserialize)]
@@ -990,12 +1659,12 @@ impl TravelFlow {
         }
     }
 
-    pub fn from_string(x: &str) -> Result<Self> {
+    pub fn from_string(x: &str) -> Result<Self, Error> {
         match x {
             "forwards" => Ok(Self::FORWARDS),
             "backwards" => Ok(Self::BACKWARDS),
             "both" => Ok(Self::BothWays),
-            _ => bail!("Invalid Direction: {x}"),
+            _ => Err(Error::UnknownTravelFlow(x.to_string())),
         }
     }
 }
@@ -1004,15 +1673,7 @@ pub enum Command {
     SetModalFilter(RoadID, Option<ModalFilter>),
     SetDiagonalFilter(IntersectionID, Option<DiagonalFilter>),
     SetTravelFlow(RoadID, TravelFlow),
+    /// Whether the (from, to) movement is banned
+    SetTurnBanned(IntersectionID, (RoadID, RoadID), bool),
     Multiple(Vec<Command>),
 }
-
-fn get_str_prop<'a>(f: &'a Feature, key: &str) -> Result<&'a str> {
-    let Some(value) = f.property(key) else {
-        bail!("Feature doesn't have a {key} property");
-    };
-    let Some(string) = value.as_str() else {
-        bail!("Feature's {key} property isn't a string");
-    };
-    Ok(string)
-}
@@ -1,25 +1,56 @@
-use geo::{Euclidean, Length, Line, LineInterpolatePoint, Point, Polygon};
+use geo::{Euclidean, Length, Line, LineInterpolatePoint, LineString, Point, Polygon};
 use geojson::GeoJson;
 
 use crate::{
     geo_helpers::{make_arrow, thicken_line},
-    IntersectionID, MapModel, Road,
+    IntersectionID, MapModel, Road, RoadID,
 };
 
 impl MapModel {
+    /// Returns an arrow for every allowed movement through an intersection, and for every banned
+    /// turn, with `from`, `to`, and `banned` properties.
     pub fn get_movements(&self, i: IntersectionID) -> GeoJson {
         let mut features = Vec::new();
 
         let intersection = self.get_i(i);
-        for (r1, r2) in intersection.allowed_movements(&self.router_input_after()) {
+        let allowed = intersection
+            .allowed_movements(&self.router_input_after())
+            .into_iter()
+            .map(|movement| (movement, false));
+        let banned = self.banned_turns_at(i).map(|movement| (movement, true));
+        for ((r1, r2), is_banned) in allowed.chain(banned) {
             let road1 = self.get_r(r1);
             let road2 = self.get_r(r2);
             let polygon = render_arrow(i, road1, road2);
-            features.push(self.mercator.to_wgs84_gj(&polygon));
+            let mut f = self.mercator.to_wgs84_gj(&polygon);
+            f.set_property("from", r1.0);
+            f.set_property("to", r2.0);
+            f.set_property("banned", is_banned);
+            features.push(f);
         }
 
         GeoJson::from(features)
     }
+
+    /// Every (from, to) movement banned at an intersection, from OSM or edits
+    pub fn banned_turns_at(
+        &self,
+        i: IntersectionID,
+    ) -> impl Iterator<Item = (RoadID, RoadID)> + '_ {
+        self.banned_turns
+            .range((i, RoadID(0), RoadID(0))..)
+            .take_while(move |(i2, _, _)| *i2 == i)
+            .map(|(_, from, to)| (*from, *to))
+    }
+
+    /// A short line from one road, through the intersection, to another
+    pub fn movement_linestring(&self, i: IntersectionID, from: RoadID, to: RoadID) -> LineString {
+        LineString::from(vec![
+            pt_near_intersection(i, self.get_r(from)),
+            self.get_i(i).point,
+            pt_near_intersection(i, self.get_r(to)),
+        ])
+    }
 }
 
 fn render_arrow(i: IntersectionID, road1: &Road, road2: &Road) -> Polygon {
@@ -32,7 +63,8 @@ fn render_arrow(i: IntersectionID, road1: &Road, road2: &Road) -> Polygon {
     make_arrow(line, thickness, double_ended).unwrap_or_else(|| thicken_line(line, thickness))
 }
 
-fn pt_near_intersection(i: IntersectionID, road: &Road) -> Point {
+/// A point on the road, a short distance from the intersection
+pub(crate) fn pt_near_intersection(i: IntersectionID, road: &Road) -> Point {
     // If the road is long enough, offset from the intersection this much
     let distance_away = 10.0;
     let len = Euclidean.length(&road.linestring);
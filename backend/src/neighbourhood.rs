@@ -1,19 +1,21 @@
 use std::collections::BTreeSet;
 
 use crate::geo_helpers::{make_polygon_valid, SliceNearestFrechetBoundary};
-use anyhow::Result;
 use geo::{Area, Euclidean, Length, Line, LineString, Polygon, PreparedGeometry, Relate};
 use geojson::{Feature, FeatureCollection, Geometry};
 use web_time::Instant;
 
+use crate::active_travel::ActiveTravelRoadID;
 use crate::geo_helpers::{
     aabb, angle_of_line, buffer_aabb, clip_linestring_to_polygon, euclidean_destination,
     invert_polygon, make_arrow,
 };
+use crate::intersection_control::IntersectionDelays;
 use crate::map_model::DiagonalFilter;
 use crate::route::RouterInput;
+use crate::turns::TurnPenalties;
 use crate::{
-    Cell, Intersection, IntersectionID, MapModel, ModalFilter, RenderCells, Road, RoadID,
+    Cell, Error, Intersection, IntersectionID, MapModel, ModalFilter, RenderCells, Road, RoadID,
     Shortcuts, TravelFlow,
 };
 
@@ -22,6 +24,9 @@ pub struct Neighbourhood {
     pub interior_roads: BTreeSet<RoadID>,
     // Immutable once created
     pub perimeter_roads: BTreeSet<RoadID>,
+    // Immutable once created. Footways, cycleways, and paths inside the boundary, in
+    // `MapModel::active_travel`. Only for rendering; they don't affect cells or shortcuts.
+    pub paths: BTreeSet<ActiveTravelRoadID>,
     pub editable_intersections: BTreeSet<IntersectionID>,
     pub border_intersections: BTreeSet<IntersectionID>,
     pub name: String,
@@ -47,7 +52,7 @@ impl Neighbourhood {
         name: String,
         boundary_polygon: Polygon,
         edit_perimeter_roads: bool,
-    ) -> Result<Self> {
+    ) -> Result<Self, Error> {
         // Later topology checks require a valid boundary - notably the "is perimeter" check.
         let boundary_polygon = make_polygon_valid(&boundary_polygon);
 
@@ -79,6 +84,21 @@ impl Neighbourhood {
             }
         }
 
+        let mut paths = BTreeSet::new();
+        for obj in map
+            .active_travel
+            .closest_road
+            .locate_in_envelope_intersecting(&bbox)
+        {
+            let r = map.active_travel.get_r(obj.data);
+            if !r.motorised
+                && line_in_polygon(&r.linestring, &boundary_polygon, &prepared_boundary)
+                    == LineInPolygon::Inside
+            {
+                paths.insert(obj.data);
+            }
+        }
+
         let t2 = Instant::now();
         let mut editable_intersections = BTreeSet::new();
         let mut border_intersections = BTreeSet::new();
@@ -98,7 +118,8 @@ impl Neighbourhood {
                 }
             }
 
-            if interior_connections == 4 && perimeter_connections == 0 {
+            // Diagonal filters need at least 3 roads to split up
+            if interior_connections >= 3 && perimeter_connections == 0 {
                 editable_intersections.insert(intersection.id);
             } else if interior_connections > 0 && perimeter_connections > 0 {
                 border_intersections.insert(intersection.id);
@@ -106,14 +127,14 @@ impl Neighbourhood {
         }
 
         if interior_roads.is_empty() {
-            bail!("No roads inside the boundary");
+            return Err(Error::NoInteriorRoads);
         }
 
         if perimeter_roads.is_empty() {
             // App breaks without perimeter roads: without perimeter roads, there's only one cell,
             // so it counts as disconnected (because it doesn't touch a border intersection), and
             // thus we can't calculate shortcuts through it without those intersections.
-            bail!("No perimeter roads");
+            return Err(Error::NoPerimeterRoads);
         }
 
         // Convert from m^2 to km^2. Use unsigned area to ignore polygon orientation.
@@ -127,6 +148,7 @@ impl Neighbourhood {
         let mut n = Self {
             interior_roads,
             perimeter_roads,
+            paths,
             editable_intersections,
             border_intersections,
             name,
@@ -202,6 +224,22 @@ impl Neighbourhood {
             fn diagonal_filter(&self, r: IntersectionID) -> Option<&DiagonalFilter> {
                 self.map.diagonal_filters.get(&r)
             }
+
+            fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+                self.map.banned_turns.contains(&(i, from, to))
+            }
+
+            fn turn_penalties(&self) -> &TurnPenalties {
+                &self.map.turn_penalties
+            }
+
+            fn intersection_delays(&self) -> &IntersectionDelays {
+                &self.map.intersection_delays
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                self.map.road_cost_seconds(road)
+            }
         }
 
         NeighbourhoodRouterInput {
@@ -254,6 +292,12 @@ impl Neighbourhood {
             features.push(f);
         }
 
+        for r in &self.paths {
+            let mut f = map.active_travel.get_r(*r).to_gj(&map.mercator);
+            f.set_property("kind", "path");
+            features.push(f);
+        }
+
         for intersection_id in &self.editable_intersections {
             let intersection = map.get_i(*intersection_id);
             let mut f = map.mercator.to_wgs84_gj(&intersection.point);
@@ -261,6 +305,20 @@ impl Neighbourhood {
             f.set_property("intersection_id", intersection_id.0);
             let filter = map.diagonal_filters.get(intersection_id);
             f.set_property("filter", filter);
+            // For choosing custom groups
+            f.set_property(
+                "roads",
+                intersection
+                    .roads
+                    .iter()
+                    .map(|r| {
+                        serde_json::json!({
+                            "road": r.0,
+                            "name": map.get_r(*r).tags.get("name"),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            );
             features.push(f);
         }
 
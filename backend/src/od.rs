@@ -4,7 +4,7 @@ use nanorand::{Rng, WyRand};
 use serde::{Deserialize, Serialize};
 use utils::Mercator;
 
-use crate::{MapModel, RoadID};
+use crate::{Error, MapModel, RoadID};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ZoneID(pub usize);
@@ -18,6 +18,11 @@ pub struct DemandModel {
 }
 
 impl DemandModel {
+    /// Decodes a demand model `.bin`, as produced by the `data_prep` tools
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, Error> {
+        bincode::deserialize(bytes).map_err(|err| Error::DemandDecode(err.to_string()))
+    }
+
     /// Turn all of the zones into Mercator. Don't do this when originally building and serializing
     /// them, because that process might not use exactly the same Mercator object.
     pub fn finish_loading(&mut self, mercator: &Mercator) {
@@ -1,6 +1,13 @@
-use geo::MultiPolygon;
+use std::collections::BTreeSet;
 
-use crate::MapModel;
+use geo::{LineInterpolatePoint, MultiPolygon};
+
+use crate::assignment::assign;
+use crate::{
+    AccessQuery, AssignmentSettings, DrivingSide, Error, FilterKind, IntersectionControl,
+    IntersectionDelays, MapModel, MatchStatus, MatchedBy, RouteChoice, Router, SpeedProfiles,
+    TimePeriod, TurnPenalties, TurnType, VehicleClass,
+};
 
 #[test]
 fn test_deadend_with_barrier() {
@@ -11,6 +18,21 @@ fn test_deadend_with_barrier() {
     assert!(road.tags.is("name", "deadend"));
 }
 
+#[test]
+fn test_add_modal_filter_far_from_roads() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let far_away = geo::Coord {
+        x: -10_000.0,
+        y: -10_000.0,
+    };
+    assert!(matches!(
+        map.add_modal_filter(far_away, None, FilterKind::NoEntry),
+        Err(Error::NoRoadNearPoint)
+    ));
+    assert!(map.modal_filters.is_empty());
+    assert!(map.edit_log.is_empty());
+}
+
 #[test]
 fn test_no_left_turn() {
     let map = load_osm_xml("no_left_turn");
@@ -26,6 +48,81 @@ fn test_no_left_turn() {
     assert!(map.get_r(i.turn_restrictions[0].1).tags.is("name", "west"));
 }
 
+#[test]
+fn test_complex_turn_restrictions() {
+    let map = load_osm_xml("complex_turn_restrictions");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let can_route = |router: &Router, from: &str, to: &str| {
+        router.route_from_roads(road(from), road(to)).is_some()
+    };
+    let router = &map.router_before;
+
+    // only_straight_on
+    assert!(can_route(router, "south", "north"));
+    assert!(!can_route(router, "south", "east"));
+    assert!(!can_route(router, "south", "west"));
+
+    // A via way
+    assert!(!can_route(router, "north", "spur"));
+    assert!(can_route(router, "north", "east"));
+    assert!(can_route(router, "north", "far_east"));
+    assert!(can_route(router, "west", "spur"));
+
+    // Conditional, with exceptions. Without a time, it always applies.
+    assert!(!can_route(router, "west", "north"));
+    let midday = Router::new(
+        &AccessQuery {
+            minute_of_day: Some(12 * 60),
+            ..Default::default()
+        }
+        .router_input(map.router_input_before()),
+        1.0,
+    );
+    assert!(can_route(&midday, "west", "north"));
+    let bus = Router::new(
+        &AccessQuery::vehicle(VehicleClass::Bus).router_input(map.router_input_before()),
+        1.0,
+    );
+    assert!(can_route(&bus, "west", "north"));
+}
+
+#[test]
+fn test_shared_via_way_restrictions() {
+    // Three restrictions from the same road start through the same via way. One continues
+    // through a second via way.
+    let map = load_osm_xml("shared_via_way_restrictions");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let can_route = |from: &str, to: &str| {
+        map.router_before
+            .route_from_roads(road(from), road(to))
+            .is_some()
+    };
+
+    assert!(!can_route("approach", "north"));
+    assert!(!can_route("approach", "south"));
+    assert!(!can_route("approach", "far_north"));
+    assert!(can_route("approach", "link"));
+    assert!(can_route("approach", "onward"));
+    assert!(can_route("approach", "far_east"));
+
+    // Other traffic isn't affected
+    for to in ["north", "south", "far_north", "far_east"] {
+        assert!(can_route("side", to));
+    }
+}
+
 pub fn load_osm_xml(filename: &str) -> MapModel {
     let path = format!(
         "{}/src/osm_tests/{filename}.osm.xml",
@@ -43,3 +140,710 @@ pub fn load_osm_xml(filename: &str) -> MapModel {
     )
     .unwrap()
 }
+
+#[test]
+fn test_savefile_reanchors_by_osm_ids() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let r = map.roads[0].id;
+    let pt = map.roads[0]
+        .linestring
+        .line_interpolate_point(0.3)
+        .unwrap()
+        .into();
+    map.add_modal_filter(pt, Some(vec![r]), FilterKind::NoEntry)
+        .unwrap();
+    map.toggle_travel_flow(r);
+    let mut gj = map.to_savefile(false).to_gj();
+
+    // Pretend the way was reversed in a newer OSM extract
+    for f in &mut gj.features {
+        if let Some(osm) = f
+            .properties
+            .as_mut()
+            .and_then(|props| props.get_mut("osm"))
+            .and_then(|x| x.as_object_mut())
+        {
+            let src = osm["src_node"].clone();
+            osm["src_node"] = osm["dst_node"].clone();
+            osm["dst_node"] = src;
+            if let Some(percent) = osm.get("percent_along").and_then(|x| x.as_f64()) {
+                osm["percent_along"] = (1.0 - percent).into();
+            }
+        }
+    }
+
+    let mut fresh = load_osm_xml("simple_four_way_intersection");
+    let report = fresh.load_savefile(gj).unwrap();
+    assert_eq!(report.edits.len(), 2);
+    for edit in &report.edits {
+        assert_eq!(edit.status, MatchStatus::Matched);
+        assert_eq!(edit.matched_by, Some(MatchedBy::ReversedWay));
+        assert_eq!(edit.roads, vec![r]);
+    }
+    let (before, after) = (&map.modal_filters[&r], &fresh.modal_filters[&r]);
+    assert_eq!(before.kind, after.kind);
+    assert!((before.percent_along - after.percent_along).abs() < 1e-6);
+    assert_eq!(fresh.travel_flows[&r], map.travel_flows[&r]);
+}
+
+#[test]
+fn test_scenarios() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let r = map.roads[0].id;
+    map.clone_scenario(&map.active_scenario.clone(), "Option B".to_string())
+        .unwrap();
+    map.switch_scenario("Option B").unwrap();
+    map.toggle_travel_flow(r);
+
+    let diff = map.diff_scenarios("Default", "Option B").unwrap();
+    assert_eq!(diff.features.len(), 1);
+    assert_eq!(
+        diff.features[0].property("kind").unwrap().as_str(),
+        Some("travel_flow")
+    );
+
+    // Both scenarios survive a savefile round-trip
+    let gj = map.to_savefile(false).to_gj();
+    let mut fresh = load_osm_xml("simple_four_way_intersection");
+    fresh.load_savefile(gj).unwrap();
+    assert_eq!(fresh.active_scenario, "Option B");
+    assert_eq!(fresh.scenario_names(), vec!["Default", "Option B"]);
+    assert_eq!(fresh.travel_flows[&r], map.travel_flows[&r]);
+    fresh.switch_scenario("Default").unwrap();
+    assert_ne!(fresh.travel_flows[&r], map.travel_flows[&r]);
+}
+
+#[test]
+fn test_diff_scenarios_access() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let r = map.roads[0].id;
+    let pt = map.roads[0]
+        .linestring
+        .line_interpolate_point(0.5)
+        .unwrap()
+        .into();
+    map.add_modal_filter(pt, Some(vec![r]), FilterKind::NoEntry)
+        .unwrap();
+    map.clone_scenario(&map.active_scenario.clone(), "Option B".to_string())
+        .unwrap();
+    map.switch_scenario("Option B").unwrap();
+    let mut access = map.modal_filters[&r].access.clone();
+    access.allowed.insert(VehicleClass::Bus);
+    map.set_modal_filter_access(r, access).unwrap();
+
+    // Only the access policy changed, but the diff should show it
+    let diff = map.diff_scenarios("Default", "Option B").unwrap();
+    assert_eq!(diff.features.len(), 1);
+    let f = &diff.features[0];
+    assert_eq!(f.property("a"), f.property("b"));
+    assert_ne!(f.property("a_access"), f.property("b_access"));
+    assert!(f.property("b_access").unwrap()["allowed"]
+        .as_array()
+        .unwrap()
+        .contains(&"bus".into()));
+}
+
+#[test]
+fn test_ban_turn() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let i = map
+        .intersections
+        .iter()
+        .find(|i| i.roads.len() == 4)
+        .unwrap()
+        .id;
+    let (from, to) = (map.get_i(i).roads[0], map.get_i(i).roads[1]);
+    let can_route = |map: &mut MapModel| {
+        map.rebuild_router(1.0);
+        map.router_after
+            .as_ref()
+            .unwrap()
+            .route_from_roads(from, to)
+            .is_some()
+    };
+
+    assert!(can_route(&mut map));
+    assert!(map.set_turn_banned(i, from, from, true).is_err());
+    map.set_turn_banned(i, from, to, true).unwrap();
+    assert!(!can_route(&mut map));
+    let banned_movements = map
+        .get_movements(i)
+        .to_string()
+        .matches("\"banned\":true")
+        .count();
+    assert_eq!(banned_movements, 1);
+
+    // The ban survives a savefile round-trip
+    let gj = map.to_savefile(false).to_gj();
+    let mut fresh = load_osm_xml("simple_four_way_intersection");
+    let report = fresh.load_savefile(gj).unwrap();
+    assert_eq!(report.edits.len(), 1);
+    assert_eq!(report.edits[0].status, MatchStatus::Matched);
+    assert_eq!(report.edits[0].roads, vec![from, to]);
+    assert_eq!(fresh.banned_turns, map.banned_turns);
+
+    map.undo();
+    assert!(map.banned_turns.is_empty());
+    assert!(can_route(&mut map));
+}
+
+#[test]
+fn test_turn_types() {
+    let mut map = load_osm_xml("complex_turn_restrictions");
+    let road = |map: &MapModel, name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let south = road(&map, "south");
+    let i = map
+        .intersections
+        .iter()
+        .find(|i| i.roads.len() == 4)
+        .unwrap();
+    let turn = |to: &str| TurnType::classify(i, map.get_r(south), map.get_r(road(&map, to)));
+    assert_eq!(turn("north"), TurnType::Straight);
+    assert_eq!(turn("east"), TurnType::Right);
+    assert_eq!(turn("west"), TurnType::Left);
+
+    // Penalties don't change where routes can go
+    let north = road(&map, "north");
+    map.set_turn_penalties(TurnPenalties::typical(DrivingSide::Left));
+    assert!(map.router_before.route_from_roads(south, north).is_some());
+}
+
+#[test]
+fn test_intersection_control() {
+    let mut map = load_osm_xml("signalised_junction");
+    let road = |map: &MapModel, name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (north, side) = (road(&map, "north"), road(&map, "side"));
+    let junction = |map: &MapModel, r| {
+        map.intersections
+            .iter()
+            .find(|i| i.roads.len() == 3 && i.roads.contains(&r))
+            .unwrap()
+            .clone()
+    };
+
+    // The signals are tagged on the intersection node, and the give way sign on the side road
+    // approaching it
+    let signals = junction(&map, north);
+    assert_eq!(signals.control, IntersectionControl::Signals);
+    let priority = junction(&map, side);
+    assert_eq!(
+        priority.control,
+        IntersectionControl::Priority {
+            stop: Vec::new(),
+            give_way: vec![side],
+        }
+    );
+
+    // Only traffic from the side road waits
+    let delays = IntersectionDelays::typical();
+    assert_eq!(delays.cost(&signals, map.get_r(north)), delays.signals);
+    assert_eq!(delays.cost(&priority, map.get_r(side)), delays.give_way);
+    for r in &priority.roads {
+        if *r != side {
+            assert_eq!(delays.cost(&priority, map.get_r(*r)), 0.0);
+        }
+    }
+
+    // Delays don't change where routes can go
+    map.set_intersection_delays(IntersectionDelays::typical());
+    assert!(map.router_before.route_from_roads(north, side).is_some());
+}
+
+#[test]
+fn test_intersection_delays_change_routes() {
+    let mut map = load_osm_xml("signalised_main_road");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (west, main_roads, side_street, east) = (
+        road("west"),
+        map.roads
+            .iter()
+            .filter(|r| r.tags.is("name", "main"))
+            .map(|r| r.id)
+            .collect::<Vec<_>>(),
+        road("side_street"),
+        road("east"),
+    );
+    assert!(map
+        .intersections
+        .iter()
+        .any(|i| i.control == IntersectionControl::Signals));
+    let route = |map: &MapModel| map.router_before.route_from_roads(west, east).unwrap();
+
+    // Delays are off by default, so the slightly shorter main road wins
+    assert_eq!(map.intersection_delays, IntersectionDelays::NONE);
+    assert!(main_roads.iter().all(|r| route(&map).crosses_road(*r)));
+    assert!(!route(&map).crosses_road(side_street));
+
+    // Waiting at the signals makes the side street faster
+    map.set_intersection_delays(IntersectionDelays::typical());
+    assert!(route(&map).crosses_road(side_street));
+    assert!(main_roads.iter().all(|r| !route(&map).crosses_road(*r)));
+}
+
+#[test]
+fn test_speed_profiles() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let (r1, r2) = (map.roads[0].id, map.roads[1].id);
+    let route_time = |map: &MapModel| {
+        map.router_before
+            .route_from_roads(r1, r2)
+            .unwrap()
+            .get_distance_and_time(map)
+            .1
+    };
+    let speed_limit_time = route_time(&map);
+
+    // Every road is on one of the two ways
+    let matched = map.set_speed_profiles(
+        SpeedProfiles::from_csv(
+            "way,peak_mph,off_peak_mph
+-1,5,
+-2,5,
+",
+        )
+        .unwrap(),
+    );
+    assert_eq!(matched, map.roads.len());
+    assert_eq!(route_time(&map), speed_limit_time);
+
+    map.set_time_period(TimePeriod::Peak);
+    assert!(route_time(&map) > speed_limit_time);
+
+    // There's no off-peak speed, so the speed limit applies
+    map.set_time_period(TimePeriod::OffPeak);
+    assert_eq!(route_time(&map), speed_limit_time);
+}
+
+#[test]
+fn test_congested_assignment() {
+    let map = load_osm_xml("parallel_routes");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (west, middle, east, detour) = (road("west"), road("middle"), road("east"), road("detour"));
+    // More trips than the main road can carry in an hour
+    let requests = vec![(west, east, 4000)];
+    let counts = |settings| {
+        assign(
+            &map.router_input_before(),
+            &map.router_before,
+            &requests,
+            &settings,
+            &RouteChoice::FASTEST,
+        )
+    };
+
+    // Without congestion, everyone takes the slightly shorter main road
+    let free_flow = counts(AssignmentSettings::ALL_OR_NOTHING);
+    assert_eq!(free_flow.get(&middle), Some(&4000));
+    assert_eq!(free_flow.get(&detour), None);
+
+    // With congestion, some trips detour, but they all still start and end in the same place
+    let congested = counts(AssignmentSettings::congested());
+    assert!(congested[&detour] > 0);
+    assert!(congested[&middle] < 4000);
+    assert_eq!(congested[&west], 4000);
+    assert_eq!(congested[&east], 4000);
+}
+
+#[test]
+fn test_route_choice() {
+    let map = load_osm_xml("parallel_routes");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (west, middle, east, detour) = (road("west"), road("middle"), road("east"), road("detour"));
+    let requests = vec![(west, east, 101)];
+    let router_input = map.router_input_before();
+    let counts = |route_choice: RouteChoice| {
+        route_choice.od_to_counts(&router_input, Some(&map.router_before), &requests)
+    };
+
+    // The main road is only slightly faster than the detour, so some samples pick each
+    let sampled = counts(RouteChoice::stochastic(10));
+    assert!(sampled[&middle] > 0);
+    assert!(sampled[&detour] > 0);
+    assert_eq!(sampled[&middle] + sampled[&detour], 101);
+    assert_eq!(sampled[&west], 101);
+    assert_eq!(sampled[&east], 101);
+
+    // The same seed picks the same routes
+    assert_eq!(counts(RouteChoice::stochastic(10)), sampled);
+
+    let fastest = counts(RouteChoice::FASTEST);
+    assert_eq!(fastest.get(&middle), Some(&101));
+    assert_eq!(fastest.get(&detour), None);
+}
+
+#[test]
+fn test_diagonal_filter_at_3_way() {
+    let mut map = load_osm_xml("complex_turn_restrictions");
+    let road = |map: &MapModel, name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (east, far_east, spur) = (
+        road(&map, "east"),
+        road(&map, "far_east"),
+        road(&map, "spur"),
+    );
+    let i = map
+        .intersections
+        .iter()
+        .find(|i| i.roads.len() == 3 && i.roads.contains(&spur))
+        .unwrap()
+        .id;
+    let can_route = |map: &mut MapModel, to| {
+        map.rebuild_router(1.0);
+        map.router_after
+            .as_ref()
+            .unwrap()
+            .route_from_roads(east, to)
+            .is_some()
+    };
+    assert!(can_route(&mut map, spur));
+
+    // Every road must be in one group
+    assert!(map
+        .set_diagonal_filter_groups(i, vec![vec![east], vec![spur]])
+        .is_err());
+    map.set_diagonal_filter_groups(i, vec![vec![east, far_east], vec![spur]])
+        .unwrap();
+    assert!(!can_route(&mut map, spur));
+    assert!(can_route(&mut map, far_east));
+    assert_eq!(map.diagonal_filters[&i].barrier_bearings.len(), 2);
+
+    // The groups survive a savefile round-trip
+    let gj = map.to_savefile(false).to_gj();
+    let mut fresh = load_osm_xml("complex_turn_restrictions");
+    let report = fresh.load_savefile(gj).unwrap();
+    assert_eq!(report.edits[0].status, MatchStatus::Matched);
+    assert_eq!(fresh.diagonal_filters, map.diagonal_filters);
+
+    // A scenario with different groups shows up in the diff
+    map.clone_scenario(&map.active_scenario.clone(), "Option B".to_string())
+        .unwrap();
+    map.switch_scenario("Option B").unwrap();
+    map.set_diagonal_filter_groups(i, vec![vec![east], vec![far_east, spur]])
+        .unwrap();
+    let diff = map.diff_scenarios("Default", "Option B").unwrap();
+    assert_eq!(diff.features.len(), 1);
+    let f = &diff.features[0];
+    assert_eq!(f.property("a"), f.property("b"));
+    assert_eq!(
+        f.property("b_groups").unwrap(),
+        &serde_json::json!([[east.0], [far_east.0, spur.0]])
+    );
+    assert_ne!(f.property("a_groups"), f.property("b_groups"));
+}
+
+#[test]
+fn test_diagonal_filter_groups_history() {
+    let mut map = load_osm_xml("complex_turn_restrictions");
+    let road = |name: &str| {
+        map.roads
+            .iter()
+            .find(|r| r.tags.is("name", name))
+            .unwrap()
+            .id
+    };
+    let (east, far_east, spur) = (road("east"), road("far_east"), road("spur"));
+    let i = map
+        .intersections
+        .iter()
+        .find(|i| i.roads.len() == 3 && i.roads.contains(&spur))
+        .unwrap()
+        .id;
+    let polygon = &map.boundary_wgs84.0[0];
+    map.boundaries.insert(
+        "north".to_string(),
+        geojson::Feature::from(geojson::Geometry::from(polygon)),
+    );
+    map.switch_edit_history(Some("north"));
+    map.set_diagonal_filter_groups(i, vec![vec![east, far_east], vec![spur]])
+        .unwrap();
+    let grouped = map.diagonal_filters.clone();
+    map.delete_diagonal_filter(i);
+
+    // Undoing the first edit removes a filter at a 3-way intersection
+    let gj = map.to_savefile(true).to_gj();
+    let mut fresh = load_osm_xml("complex_turn_restrictions");
+    fresh.load_savefile(gj).unwrap();
+    fresh.switch_edit_history(Some("north"));
+    assert_eq!(fresh.undo_stack.len(), 2);
+    fresh.undo();
+    assert_eq!(fresh.diagonal_filters, grouped);
+    fresh.undo();
+    assert!(fresh.diagonal_filters.is_empty());
+}
+
+#[test]
+fn test_edit_history_per_neighbourhood() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let r = map.roads[0].id;
+    map.switch_edit_history(Some("north"));
+    map.toggle_travel_flow(r);
+    map.switch_edit_history(Some("south"));
+    assert!(map.undo_stack.is_empty());
+    map.switch_edit_history(Some("north"));
+    assert_eq!(map.undo_stack.len(), 1);
+
+    // History is only saved when asked, and only restored for neighbourhoods that exist
+    for name in ["north", "south"] {
+        let polygon = &map.boundary_wgs84.0[0];
+        map.boundaries.insert(
+            name.to_string(),
+            geojson::Feature::from(geojson::Geometry::from(polygon)),
+        );
+    }
+    assert!(map.to_savefile(false).edit_history.is_empty());
+    let gj = map.to_savefile(true).to_gj();
+    let mut fresh = load_osm_xml("simple_four_way_intersection");
+    fresh.load_savefile(gj).unwrap();
+    assert!(fresh.undo_stack.is_empty());
+    fresh.switch_edit_history(Some("north"));
+    assert_eq!(fresh.undo_stack.len(), 1);
+    fresh.undo();
+    assert_eq!(
+        fresh.travel_flows[&r],
+        crate::TravelFlow::from_osm(&fresh.get_r(r).tags)
+    );
+}
+
+#[test]
+fn test_edit_log() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    assert!(map.edit_log.is_empty());
+    let r = map.roads[0].id;
+    map.set_edit_author(Some("Planner".to_string()));
+    map.toggle_travel_flow(r);
+    map.undo();
+    map.set_edit_comment(0, Some("Consultation, round 1".to_string()))
+        .unwrap();
+    assert!(map.set_edit_comment(2, None).is_err());
+
+    let actions: Vec<&str> = map.edit_log.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, vec!["toggle_travel_flow", "undo"]);
+    assert_eq!(map.edit_log[1].author.as_deref(), Some("Planner"));
+    let csv = map.edit_log_to_csv();
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.contains("\"Consultation, round 1\""));
+
+    // The log survives a savefile round-trip
+    let gj = map.to_savefile(false).to_gj();
+    let mut fresh = load_osm_xml("simple_four_way_intersection");
+    fresh.load_savefile(gj).unwrap();
+    assert_eq!(fresh.edit_log.len(), 2);
+    assert_eq!(
+        fresh.edit_log[0].comment.as_deref(),
+        Some("Consultation, round 1")
+    );
+    assert_eq!(fresh.edit_log[1].timestamp, map.edit_log[1].timestamp);
+}
+
+#[test]
+fn test_bus_routes() {
+    let mut map = load_osm_xml("bus_route");
+    let routes = |map: &MapModel| map.check_bus_routes().foreign_members.unwrap()["routes"].clone();
+    assert_eq!(routes(&map)[0]["broken"], false);
+
+    // Filters on a bus route become bus gates, which buses can pass
+    let r = map
+        .roads
+        .iter()
+        .find(|r| map.get_bus_routes_on_road(r.id).is_some())
+        .unwrap()
+        .id;
+    let pt = map.get_r(r).linestring.line_interpolate_point(0.5).unwrap();
+    map.add_modal_filter(pt.0, Some(vec![r]), FilterKind::NoEntry)
+        .unwrap();
+    assert_eq!(map.modal_filters[&r].kind, FilterKind::BusGate);
+    assert!(map.check_bus_routes().features.is_empty());
+
+    // Unless the gate is changed to block buses
+    let mut access = map.modal_filters[&r].access.clone();
+    access.allowed.remove(&crate::VehicleClass::Bus);
+    map.set_modal_filter_access(r, access).unwrap();
+    let problems = map.check_bus_routes();
+    assert_eq!(problems.features.len(), 1);
+    assert_eq!(
+        problems.features[0].property("route").unwrap().as_str(),
+        Some("Route 1")
+    );
+    assert_eq!(
+        problems.features[0].property("kind").unwrap().as_str(),
+        Some("modal_filter")
+    );
+    assert_eq!(routes(&map)[0]["broken"], true);
+    map.undo();
+
+    // Diagonal filters stop the bus going straight through
+    let i = map.get_r(r).dst_i;
+    let i = if map.get_i(i).roads.len() == 4 {
+        i
+    } else {
+        map.get_r(r).src_i
+    };
+    map.add_diagonal_filter(i);
+    let problems = map.check_bus_routes();
+    assert_eq!(
+        problems.features[0].property("kind").unwrap().as_str(),
+        Some("movement")
+    );
+    assert_eq!(problems.features[0].property("existing").unwrap(), false);
+}
+
+#[test]
+fn test_response_times() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    let depot = map.roads[0].linestring.0[0];
+    let r = map.roads[2].id;
+    let pt = map.get_r(r).linestring.line_interpolate_point(0.5).unwrap();
+    map.add_modal_filter(pt.0, Some(vec![r]), FilterKind::NoEntry)
+        .unwrap();
+    let roads: Vec<_> = map.roads.iter().map(|r| r.id).collect();
+
+    let flagged = |gj: geojson::FeatureCollection| {
+        gj.features
+            .into_iter()
+            .filter(|f| f.property("flagged").unwrap() == true)
+            .map(|f| f.property("id").unwrap().as_u64().unwrap() as usize)
+            .collect::<Vec<_>>()
+    };
+    let gj = map.response_times(&[depot], roads.clone(), BTreeSet::new(), 60.0);
+    assert_eq!(flagged(gj), vec![r.0]);
+
+    // Emergency vehicles able to pass the filter aren't affected
+    let passable = BTreeSet::from([FilterKind::NoEntry]);
+    let gj = map.response_times(&[depot], roads, passable, 60.0);
+    assert!(flagged(gj).is_empty());
+}
+
+#[test]
+fn test_paths_layer() {
+    let map = load_osm_xml("footway_shortcut");
+    assert!(map.roads.iter().all(|r| r.motorised));
+    let paths: Vec<_> = map.active_travel.paths().collect();
+    assert_eq!(paths.len(), 1);
+    assert!(paths[0].tags.is("highway", "footway"));
+    assert!(map.active_travel.vehicle_roads[paths[0].id.0].is_none());
+}
+
+#[test]
+fn test_paths_split_vehicle_roads() {
+    let map = load_osm_xml("footway_joins_road");
+    assert_eq!(map.roads.len(), 1);
+
+    // The footway splits the road in two, and the isolated footway is dropped
+    let network = &map.active_travel;
+    assert_eq!(network.roads.len(), 3);
+    assert!(network.roads.iter().all(|r| !r.tags.is("name", "isolated")));
+    let pieces: Vec<_> = network
+        .roads
+        .iter()
+        .filter(|r| r.tags.is("name", "main"))
+        .collect();
+    assert_eq!(pieces.len(), 2);
+    for r in pieces {
+        assert_eq!(network.vehicle_roads[r.id.0], Some(map.roads[0].id));
+    }
+}
+
+#[test]
+fn test_permeability() {
+    let mut map = load_osm_xml("footway_shortcut");
+    // The footway is only in the walking and cycling network
+    assert_eq!(map.roads.len(), 3);
+    assert_eq!(map.active_travel.roads.len(), 4);
+    assert_eq!(
+        map.active_travel
+            .vehicle_roads
+            .iter()
+            .filter(|r| r.is_some())
+            .count(),
+        3
+    );
+
+    // Filter the only road connecting the other two
+    let west = map
+        .roads
+        .iter()
+        .find(|r| r.tags.is("name", "west"))
+        .unwrap()
+        .id;
+    let pt = map
+        .get_r(west)
+        .linestring
+        .line_interpolate_point(0.5)
+        .unwrap();
+    map.add_modal_filter(pt.0, Some(vec![west]), FilterKind::NoEntry)
+        .unwrap();
+
+    let roads = map.roads.iter().map(|r| r.id).collect();
+    let gj = map.permeability(roads);
+    let summary = gj.foreign_members.unwrap();
+    assert!(summary["driving_before_directness"].is_number());
+    assert!(summary["driving_after_directness"].is_null());
+    assert_eq!(
+        summary["cycling_before_directness"],
+        summary["cycling_after_directness"]
+    );
+    assert!(summary["walking_directness"].is_number());
+}
+
+#[test]
+fn test_compare_scenario_impact() {
+    let mut map = load_osm_xml("simple_four_way_intersection");
+    map.new_scenario("Option A".to_string()).unwrap();
+    map.new_scenario("Option B".to_string()).unwrap();
+    map.switch_scenario("Option B").unwrap();
+    for r in map.roads.iter().map(|r| r.id).collect::<Vec<_>>() {
+        let pt = map.get_r(r).linestring.line_interpolate_point(0.5).unwrap();
+        map.add_modal_filter(pt.0, Some(vec![r]), FilterKind::NoEntry)
+            .unwrap();
+    }
+
+    let impact = map.impact.as_ref().unwrap();
+    let a = map.get_scenario("Option A").unwrap();
+    let b = map.get_scenario("Option B").unwrap();
+    let same = impact.compare_scenarios(&map, ("Option A", &a), ("Option A", &a));
+    assert!(same.features.is_empty());
+
+    // Filtering every road in B blocks all the traffic using them in A
+    let diff = impact.compare_scenarios(&map, ("Option A", &a), ("Option B", &b));
+    for f in &diff.features {
+        let count_a = f.property("a").unwrap().as_i64().unwrap();
+        let count_b = f.property("b").unwrap().as_i64().unwrap();
+        assert_eq!(count_b, 0);
+        assert_eq!(f.property("difference").unwrap().as_i64(), Some(-count_a));
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::BTreeSet;
+
+use geo::{Coord, LineString, MultiPolygon, Polygon};
+use geojson::{Feature, FeatureCollection, GeoJson};
+
+use crate::od::DemandModel;
+use crate::{
+    AccessPolicy, AccessQuery, AssignmentSettings, Error, FilterKind, IntersectionDelays,
+    IntersectionID, LoadReport, MapModel, Neighbourhood, RoadID, RouteChoice, Shortcuts,
+    SpeedProfiles, SpeedRegion, TimePeriod, TurnPenalties,
+};
+
+/// A pure-Rust session over one study area: the map with its edits, named neighbourhood
+/// boundaries, and the neighbourhood currently being edited. The WASM API is a thin adapter over
+/// this, but other crates can embed it directly.
+///
+/// All input and output geometry is in WGS84.
+pub struct Project {
+    pub map: MapModel,
+    neighbourhood: Option<Neighbourhood>,
+}
+
+impl Project {
+    /// Call with bytes of an osm.pbf or osm.xml string
+    pub fn new(
+        input_bytes: &[u8],
+        boundary_wgs84: MultiPolygon,
+        study_area_name: Option<String>,
+        demand: Option<DemandModel>,
+    ) -> Result<Self, Error> {
+        let map = MapModel::new(input_bytes, boundary_wgs84, study_area_name, demand)?;
+        Ok(Self::from_map(map))
+    }
+
+    pub fn from_map(map: MapModel) -> Self {
+        Self {
+            map,
+            neighbourhood: None,
+        }
+    }
+
+    /// The neighbourhood currently being edited
+    pub fn neighbourhood(&self) -> Result<&Neighbourhood, Error> {
+        self.neighbourhood
+            .as_ref()
+            .ok_or(Error::NoNeighbourhoodSelected)
+    }
+
+    /// Adds or replaces a named neighbourhood boundary, given as a GeoJSON polygon
+    pub fn set_neighbourhood_boundary(&mut self, name: String, mut boundary: Feature) {
+        boundary.set_property("kind", "boundary");
+        boundary.set_property("name", name.clone());
+        self.map.boundaries.insert(name, boundary);
+    }
+
+    pub fn delete_neighbourhood_boundary(&mut self, name: &str) {
+        self.map.boundaries.remove(name);
+        self.map.delete_edit_history(name);
+        if self.neighbourhood.as_ref().is_some_and(|n| n.name == name) {
+            self.neighbourhood = None;
+        }
+    }
+
+    pub fn rename_neighbourhood_boundary(
+        &mut self,
+        old_name: &str,
+        new_name: String,
+    ) -> Result<(), Error> {
+        if self.map.boundaries.contains_key(&new_name) {
+            return Err(Error::DuplicateNeighbourhood(new_name));
+        }
+        let Some(mut boundary) = self.map.boundaries.remove(old_name) else {
+            return Err(Error::UnknownNeighbourhood(old_name.to_string()));
+        };
+        boundary.set_property("name", new_name.clone());
+        self.map.boundaries.insert(new_name.clone(), boundary);
+        self.map.rename_edit_history(old_name, &new_name);
+
+        if let Some(ref mut n) = self.neighbourhood {
+            if n.name == old_name {
+                n.name = new_name;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_current_neighbourhood(
+        &mut self,
+        name: &str,
+        edit_perimeter_roads: bool,
+    ) -> Result<(), Error> {
+        let Some(boundary_gj) = self.map.boundaries.get(name).cloned() else {
+            return Err(Error::UnknownNeighbourhood(name.to_string()));
+        };
+        let mut boundary_geo: Polygon = boundary_gj.try_into()?;
+        self.map.mercator.to_mercator_in_place(&mut boundary_geo);
+
+        self.neighbourhood = Some(Neighbourhood::new(
+            &self.map,
+            name.to_string(),
+            boundary_geo,
+            edit_perimeter_roads,
+        )?);
+
+        // Undoing edits in another neighbourhood doesn't make sense, but keep that history for
+        // switching back. If we're still editing the same neighbourhood, just switching
+        // edit_perimeter_roads, this does nothing.
+        self.map.switch_edit_history(Some(name));
+
+        Ok(())
+    }
+
+    /// Adds a filter to the editable road in the current neighbourhood closest to `pt`
+    pub fn add_modal_filter(&mut self, pt: Coord, kind: FilterKind) -> Result<(), Error> {
+        let candidate_roads = self.neighbourhood()?.editable_roads();
+        self.map.add_modal_filter(
+            self.map.mercator.pt_to_mercator(pt),
+            Some(candidate_roads),
+            kind,
+        )?;
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Adds a filter everywhere `line` crosses an interior road of the current neighbourhood
+    pub fn add_many_modal_filters(
+        &mut self,
+        mut line: LineString,
+        kind: FilterKind,
+    ) -> Result<(), Error> {
+        self.map.mercator.to_mercator_in_place(&mut line);
+        let neighbourhood = self
+            .neighbourhood
+            .as_ref()
+            .ok_or(Error::NoNeighbourhoodSelected)?;
+        self.map
+            .add_many_modal_filters(line, &neighbourhood.interior_roads, kind);
+        self.after_edit();
+        Ok(())
+    }
+
+    pub fn delete_modal_filter(&mut self, r: RoadID) {
+        self.map.delete_modal_filter(r);
+        self.after_edit();
+    }
+
+    pub fn add_diagonal_filter(&mut self, i: IntersectionID) {
+        self.map.add_diagonal_filter(i);
+        self.after_edit();
+    }
+
+    pub fn rotate_diagonal_filter(&mut self, i: IntersectionID) {
+        self.map.rotate_diagonal_filter(i);
+        self.after_edit();
+    }
+
+    pub fn set_diagonal_filter_groups(
+        &mut self,
+        i: IntersectionID,
+        groups: Vec<Vec<RoadID>>,
+    ) -> Result<(), Error> {
+        self.map.set_diagonal_filter_groups(i, groups)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    pub fn delete_diagonal_filter(&mut self, i: IntersectionID) {
+        self.map.delete_diagonal_filter(i);
+        self.after_edit();
+    }
+
+    pub fn set_modal_filter_access(
+        &mut self,
+        r: RoadID,
+        access: AccessPolicy,
+    ) -> Result<(), Error> {
+        self.map.set_modal_filter_access(r, access)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    pub fn toggle_travel_flow(&mut self, r: RoadID) {
+        self.map.toggle_travel_flow(r);
+        self.after_edit();
+    }
+
+    pub fn set_turn_banned(
+        &mut self,
+        i: IntersectionID,
+        from: RoadID,
+        to: RoadID,
+        banned: bool,
+    ) -> Result<(), Error> {
+        self.map.set_turn_banned(i, from, to, banned)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Changes the extra time for turning, for routing before and after edits
+    pub fn set_turn_penalties(&mut self, turn_penalties: TurnPenalties) {
+        self.map.set_turn_penalties(turn_penalties);
+        self.after_edit();
+    }
+
+    /// Changes the delay at controlled intersections, for routing before and after edits
+    pub fn set_intersection_delays(&mut self, intersection_delays: IntersectionDelays) {
+        self.map.set_intersection_delays(intersection_delays);
+        self.after_edit();
+    }
+
+    /// Changes which legal default speed limits apply to roads without `maxspeed`
+    pub fn set_speed_region(&mut self, speed_region: Option<SpeedRegion>) {
+        self.map.set_speed_region(speed_region);
+        self.after_edit();
+    }
+
+    /// Parses a CSV of observed speeds per OSM way, replacing any previous ones. Returns how many
+    /// roads have an observed speed.
+    pub fn set_speed_profiles(&mut self, csv: &str) -> Result<usize, Error> {
+        let speed_profiles = SpeedProfiles::from_csv(csv)?;
+        let matched = self.map.set_speed_profiles(speed_profiles);
+        self.after_edit();
+        Ok(matched)
+    }
+
+    /// Changes which observed speeds are used for routing
+    pub fn set_time_period(&mut self, time_period: TimePeriod) {
+        self.map.set_time_period(time_period);
+        self.after_edit();
+    }
+
+    /// Makes another scenario active, so its edits are shown and changed
+    pub fn switch_scenario(&mut self, name: &str) -> Result<(), Error> {
+        self.map.switch_scenario(name)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    pub fn new_scenario(&mut self, name: String) -> Result<(), Error> {
+        self.map.new_scenario(name)
+    }
+
+    pub fn clone_scenario(&mut self, from: &str, name: String) -> Result<(), Error> {
+        self.map.clone_scenario(from, name)
+    }
+
+    pub fn delete_scenario(&mut self, name: &str) -> Result<(), Error> {
+        self.map.delete_scenario(name)
+    }
+
+    /// Returns GJ with every edit that differs between two scenarios
+    pub fn diff_scenarios(&self, a: &str, b: &str) -> Result<FeatureCollection, Error> {
+        self.map.diff_scenarios(a, b)
+    }
+
+    /// Later edits are credited to this author in the edit log
+    pub fn set_edit_author(&mut self, author: Option<String>) {
+        self.map.set_edit_author(author);
+    }
+
+    /// Records why an edit was made, by its index in the edit log
+    pub fn set_edit_comment(&mut self, index: usize, comment: Option<String>) -> Result<(), Error> {
+        self.map.set_edit_comment(index, comment)
+    }
+
+    /// Returns GJ with a feature per edit ever made, oldest first
+    pub fn get_edit_log(&self) -> FeatureCollection {
+        self.map.edit_log_to_gj()
+    }
+
+    /// Returns CSV with a row per edit ever made, oldest first
+    pub fn get_edit_log_csv(&self) -> String {
+        self.map.edit_log_to_csv()
+    }
+
+    pub fn undo(&mut self) {
+        self.map.undo();
+        self.after_edit();
+    }
+
+    pub fn redo(&mut self) {
+        self.map.redo();
+        self.after_edit();
+    }
+
+    /// Renders the current neighbourhood's roads, cells, and intersections
+    pub fn render_neighbourhood(&self) -> Result<FeatureCollection, Error> {
+        Ok(self.neighbourhood()?.to_gj(&self.map))
+    }
+
+    /// Calculates all shortcuts through the current neighbourhood
+    pub fn shortcuts(&self) -> Result<Shortcuts, Error> {
+        Ok(Shortcuts::new(&self.map, self.neighbourhood()?))
+    }
+
+    /// GJ with modal filters and named boundaries, in the current savefile version. Existing
+    /// filters aren't included (and deletions of existing are included)
+    pub fn to_savefile(&self, include_history: bool) -> FeatureCollection {
+        self.map.to_savefile(include_history).to_gj()
+    }
+
+    /// Replaces all edits and boundaries with those from a savefile, which may be from an older
+    /// version. The current neighbourhood is cleared. The report describes how each saved edit
+    /// matched the current map.
+    pub fn load_savefile(&mut self, gj: FeatureCollection) -> Result<LoadReport, Error> {
+        let report = self.map.load_savefile(gj)?;
+        self.neighbourhood = None;
+        Ok(report)
+    }
+
+    /// Returns GJ with two LineStrings, before and after, for one vehicle class at one time
+    pub fn compare_route(
+        &mut self,
+        pt1: Coord,
+        pt2: Coord,
+        main_road_penalty: f64,
+        access: AccessQuery,
+    ) -> GeoJson {
+        let pt1 = self.map.mercator.pt_to_mercator(pt1);
+        let pt2 = self.map.mercator.pt_to_mercator(pt2);
+        self.map.compare_route(pt1, pt2, main_road_penalty, access)
+    }
+
+    /// Returns GJ with a LineString per editable road in the current neighbourhood
+    pub fn impact_to_one_destination(&mut self, pt: Coord) -> Result<FeatureCollection, Error> {
+        let from = self.neighbourhood()?.editable_roads();
+        let pt = self.map.mercator.pt_to_mercator(pt);
+        Ok(self.map.impact_to_one_destination(pt, from))
+    }
+
+    /// Changes how OD requests are assigned to roads when predicting impact, like whether
+    /// congestion is considered
+    pub fn set_assignment_settings(&mut self, settings: AssignmentSettings) {
+        self.map.impact.as_mut().unwrap().set_assignment(settings);
+    }
+
+    /// Changes how drivers pick between routes, for impact prediction and shortcuts
+    pub fn set_route_choice(&mut self, route_choice: RouteChoice) {
+        self.map.set_route_choice(route_choice);
+        self.after_edit();
+    }
+
+    /// Returns GJ with a LineString per road, with before/after counts
+    pub fn predict_impact(&mut self) -> FeatureCollection {
+        self.map.rebuild_router(1.0);
+        let mut impact = self.map.impact.take().unwrap();
+        let out = impact.recalculate(&self.map);
+        self.map.impact = Some(impact);
+        out
+    }
+
+    /// Returns GJ with a LineString per interior road of the current neighbourhood, with emergency
+    /// response times from the nearest depot before and after edits
+    pub fn response_times(
+        &self,
+        depots: Vec<Coord>,
+        passable_kinds: BTreeSet<FilterKind>,
+        threshold_seconds: f64,
+    ) -> Result<FeatureCollection, Error> {
+        let roads = self
+            .neighbourhood()?
+            .interior_roads
+            .iter()
+            .cloned()
+            .collect();
+        let depots: Vec<Coord> = depots
+            .into_iter()
+            .map(|pt| self.map.mercator.pt_to_mercator(pt))
+            .collect();
+        Ok(self
+            .map
+            .response_times(&depots, roads, passable_kinds, threshold_seconds))
+    }
+
+    /// Returns GJ with a LineString per interior road of the current neighbourhood, describing how
+    /// direct trips between them are by car, bike, and foot, before and after edits
+    pub fn permeability(&mut self) -> Result<FeatureCollection, Error> {
+        let roads = self
+            .neighbourhood()?
+            .interior_roads
+            .iter()
+            .cloned()
+            .collect();
+        Ok(self.map.permeability(roads))
+    }
+
+    /// Returns GJ describing where edits stop buses from following OSM bus routes
+    pub fn check_bus_routes(&self) -> FeatureCollection {
+        self.map.check_bus_routes()
+    }
+
+    /// Returns GJ with a LineString per road where traffic differs between two scenarios
+    pub fn compare_scenario_impact(
+        &self,
+        name_a: &str,
+        name_b: &str,
+    ) -> Result<FeatureCollection, Error> {
+        let a = self.map.get_scenario(name_a)?;
+        let b = self.map.get_scenario(name_b)?;
+        Ok(self.map.impact.as_ref().unwrap().compare_scenarios(
+            &self.map,
+            (name_a, &a),
+            (name_b, &b),
+        ))
+    }
+
+    /// Returns pairs of before and after routes that changed whether they cross `road`
+    pub fn get_impacts_on_road(&mut self, road: RoadID) -> Vec<(Feature, Feature)> {
+        // Usually predict_impact has already built this
+        self.map.rebuild_router(1.0);
+        self.map
+            .impact
+            .as_ref()
+            .unwrap()
+            .get_impacts_on_road(&self.map, road)
+    }
+
+    pub fn get_demand_model(&self) -> Result<GeoJson, Error> {
+        let Some(ref demand) = self.map.demand else {
+            return Err(Error::NoDemandModel);
+        };
+        Ok(demand.to_gj(&self.map))
+    }
+
+    // TODO This is also internal to MapModel. But not sure who should own Neighbourhood or how to
+    // plumb, so duplicting here.
+    fn after_edit(&mut self) {
+        if let Some(ref mut n) = self.neighbourhood {
+            n.after_edit(&self.map);
+        }
+    }
+}
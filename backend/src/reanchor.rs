@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use geo::{
+    Coord, Distance, Euclidean, Length, LineInterpolatePoint, LineLocatePoint, LineString, Point,
+};
+
+use crate::savefile::{
+    MatchStatus, MatchedBy, OsmAnchor, TurnAnchor, MAX_LINESTRING_MATCH_METERS,
+    MAX_POINT_MATCH_METERS,
+};
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// Looks up roads and intersections by OSM IDs
+pub(crate) struct OsmIndex {
+    roads_per_way: HashMap<i64, Vec<RoadID>>,
+    intersection_per_node: HashMap<i64, IntersectionID>,
+}
+
+impl OsmIndex {
+    pub fn new(map: &MapModel) -> Self {
+        let mut roads_per_way: HashMap<i64, Vec<RoadID>> = HashMap::new();
+        for r in &map.roads {
+            roads_per_way.entry(r.way.0).or_default().push(r.id);
+        }
+        let intersection_per_node = map.intersections.iter().map(|i| (i.node.0, i.id)).collect();
+        Self {
+            roads_per_way,
+            intersection_per_node,
+        }
+    }
+}
+
+/// Where one saved edit landed on the current map
+pub(crate) struct Anchored<T> {
+    pub status: MatchStatus,
+    pub matched_by: Option<MatchedBy>,
+    pub distance: Option<f64>,
+    /// Empty if the edit was dropped
+    pub matches: Vec<T>,
+}
+
+impl<T> Anchored<T> {
+    fn new(status: MatchStatus, matched_by: MatchedBy, distance: f64, matches: Vec<T>) -> Self {
+        if status.is_dropped() {
+            return Self {
+                status,
+                matched_by: None,
+                distance: None,
+                matches: Vec::new(),
+            };
+        }
+        Self {
+            status,
+            matched_by: Some(matched_by),
+            distance: Some(distance),
+            matches,
+        }
+    }
+}
+
+impl MapModel {
+    /// Identifies a road by OSM IDs, for savefiles
+    pub(crate) fn osm_anchor(&self, r: RoadID, percent_along: Option<f64>) -> OsmAnchor {
+        let road = self.get_r(r);
+        OsmAnchor {
+            way: road.way.0,
+            src_node: self.get_i(road.src_i).node.0,
+            dst_node: self.get_i(road.dst_i).node.0,
+            percent_along,
+        }
+    }
+
+    /// Identifies a turn by OSM IDs, for savefiles
+    pub(crate) fn turn_anchor(&self, i: IntersectionID, from: RoadID, to: RoadID) -> TurnAnchor {
+        TurnAnchor {
+            node: self.get_i(i).node.0,
+            from_way: self.get_r(from).way.0,
+            to_way: self.get_r(to).way.0,
+        }
+    }
+
+    /// Finds the road and fraction along it for a saved point. OSM IDs are used first, then the
+    /// closest road. Only roads passing `keep` can match.
+    pub(crate) fn anchor_point(
+        &self,
+        index: &OsmIndex,
+        pt: Coord,
+        anchor: Option<&OsmAnchor>,
+        keep: impl Fn(RoadID) -> bool,
+    ) -> Anchored<(RoadID, f64)> {
+        if let Some((anchor, (matched_by, roads))) =
+            anchor.and_then(|a| Some((a, self.roads_by_osm(index, a)?)))
+        {
+            let roads = roads.into_iter().filter(|r| keep(*r)).collect();
+            let candidates = self.roads_near_point(pt, Some(roads));
+            if let Some((distance, r, projected_percent)) = candidates.first() {
+                // The road's geometry may have been tweaked, so trust the saved position on the
+                // same road
+                let (status, percent_along) = match (matched_by, anchor.percent_along) {
+                    (MatchedBy::SameWay, Some(percent)) => (MatchStatus::Matched, percent),
+                    (MatchedBy::ReversedWay, Some(percent)) => {
+                        (MatchStatus::Matched, 1.0 - percent)
+                    }
+                    _ => (
+                        MatchStatus::classify(
+                            Some(*distance),
+                            candidates.get(1).map(|c| c.0),
+                            MAX_POINT_MATCH_METERS,
+                        ),
+                        *projected_percent,
+                    ),
+                };
+                if !status.is_dropped() {
+                    return Anchored::new(status, matched_by, *distance, vec![(*r, percent_along)]);
+                }
+            }
+        }
+
+        let candidates: Vec<_> = self
+            .roads_near_point(pt, None)
+            .into_iter()
+            .filter(|c| keep(c.1))
+            .collect();
+        let status = MatchStatus::classify(
+            candidates.first().map(|c| c.0),
+            candidates.get(1).map(|c| c.0),
+            MAX_POINT_MATCH_METERS,
+        );
+        match candidates.first() {
+            Some((distance, r, percent_along)) => Anchored::new(
+                status,
+                MatchedBy::Geometry,
+                *distance,
+                vec![(*r, *percent_along)],
+            ),
+            None => Anchored::new(status, MatchedBy::Geometry, 0.0, Vec::new()),
+        }
+    }
+
+    /// Finds the roads covered by a saved linestring. A road that was split may have several
+    /// parts. Each match says if the road points the opposite direction of `linestring`.
+    pub(crate) fn anchor_linestring(
+        &self,
+        index: &OsmIndex,
+        linestring: &LineString,
+        anchor: Option<&OsmAnchor>,
+    ) -> Anchored<(RoadID, bool)> {
+        if let Some((matched_by, roads)) = anchor.and_then(|a| self.roads_by_osm(index, a)) {
+            let mut matches = Vec::new();
+            let mut max_distance: f64 = 0.0;
+            for r in roads {
+                if let Some((distance, reversed)) = self.overlap_linestring(r, linestring) {
+                    matches.push((r, reversed));
+                    max_distance = max_distance.max(distance);
+                }
+            }
+            if !matches.is_empty() {
+                return Anchored::new(MatchStatus::Matched, matched_by, max_distance, matches);
+            }
+        }
+
+        let candidates = self.similar_linestrings(linestring);
+        let status = MatchStatus::classify(
+            candidates.first().map(|c| c.0),
+            candidates.get(1).map(|c| c.0),
+            MAX_LINESTRING_MATCH_METERS,
+        );
+        match candidates.first() {
+            Some((distance, r)) => {
+                Anchored::new(status, MatchedBy::Geometry, *distance, vec![(*r, false)])
+            }
+            None => Anchored::new(status, MatchedBy::Geometry, 0.0, Vec::new()),
+        }
+    }
+
+    /// Finds the intersection at the saved OSM node, or else the closest one
+    pub(crate) fn anchor_intersection(
+        &self,
+        index: &OsmIndex,
+        pt: Coord,
+        osm_node: Option<i64>,
+    ) -> Anchored<IntersectionID> {
+        let pt = Point(pt);
+        if let Some(i) = osm_node.and_then(|node| index.intersection_per_node.get(&node)) {
+            return Anchored::new(
+                MatchStatus::Matched,
+                MatchedBy::SameNode,
+                Euclidean.distance(pt, self.get_i(*i).point),
+                vec![*i],
+            );
+        }
+
+        let candidates: Vec<(f64, IntersectionID)> = self
+            .closest_intersection
+            .nearest_neighbor_iter(&pt)
+            .take(2)
+            .map(|obj| (Euclidean.distance(pt, *obj.geom()), obj.data))
+            .collect();
+        let status = MatchStatus::classify(
+            candidates.first().map(|c| c.0),
+            candidates.get(1).map(|c| c.0),
+            MAX_POINT_MATCH_METERS,
+        );
+        match candidates.first() {
+            Some((distance, i)) => Anchored::new(status, MatchedBy::Geometry, *distance, vec![*i]),
+            None => Anchored::new(status, MatchedBy::Geometry, 0.0, Vec::new()),
+        }
+    }
+
+    /// Finds the intersection and (from, to) roads for a saved turn, which goes through the middle
+    /// point of `linestring` (in Mercator). The intersection is matched like
+    /// `anchor_intersection`. Each road is the one on the saved OSM way closest to the saved
+    /// endpoint, or any road there if the way is gone.
+    pub(crate) fn anchor_turn(
+        &self,
+        index: &OsmIndex,
+        linestring: &LineString,
+        anchor: Option<&TurnAnchor>,
+    ) -> Anchored<(IntersectionID, RoadID, RoadID)> {
+        let pts = &linestring.0;
+        let anchored = self.anchor_intersection(index, pts[1], anchor.map(|a| a.node));
+        let mut result = Anchored {
+            status: anchored.status,
+            matched_by: anchored.matched_by,
+            distance: anchored.distance,
+            matches: Vec::new(),
+        };
+        let Some(i) = anchored.matches.first() else {
+            return result;
+        };
+
+        let from = self.closest_road_at(*i, pts[0], anchor.map(|a| a.from_way));
+        let to = self.closest_road_at(*i, pts[2], anchor.map(|a| a.to_way));
+        match (from, to) {
+            (Some(from), Some(to)) if from != to => {
+                result.matches.push((*i, from, to));
+            }
+            _ => {
+                result.status = MatchStatus::Dropped {
+                    reason: "no matching turn at the intersection".to_string(),
+                };
+                result.matched_by = None;
+                result.distance = None;
+            }
+        }
+        result
+    }
+
+    /// The road at an intersection closest to `pt`, preferring roads on the OSM `way`
+    pub(crate) fn closest_road_at(
+        &self,
+        i: IntersectionID,
+        pt: Coord,
+        way: Option<i64>,
+    ) -> Option<RoadID> {
+        let roads = &self.get_i(i).roads;
+        let on_way: Vec<RoadID> = roads
+            .iter()
+            .filter(|r| Some(self.get_r(**r).way.0) == way)
+            .cloned()
+            .collect();
+        let candidates = if on_way.is_empty() {
+            roads.clone()
+        } else {
+            on_way
+        };
+        candidates.into_iter().min_by(|r1, r2| {
+            let d1 = Euclidean.distance(&Point(pt), &self.get_r(*r1).linestring);
+            let d2 = Euclidean.distance(&Point(pt), &self.get_r(*r2).linestring);
+            d1.total_cmp(&d2)
+        })
+    }
+
+    /// Roads sharing the anchor's OSM way, or failing that, touching its OSM nodes
+    fn roads_by_osm(
+        &self,
+        index: &OsmIndex,
+        anchor: &OsmAnchor,
+    ) -> Option<(MatchedBy, Vec<RoadID>)> {
+        let on_way = index
+            .roads_per_way
+            .get(&anchor.way)
+            .cloned()
+            .unwrap_or_default();
+        for r in &on_way {
+            let road = self.get_r(*r);
+            let src = self.get_i(road.src_i).node.0;
+            let dst = self.get_i(road.dst_i).node.0;
+            if (src, dst) == (anchor.src_node, anchor.dst_node) {
+                return Some((MatchedBy::SameWay, vec![*r]));
+            }
+            if (dst, src) == (anchor.src_node, anchor.dst_node) {
+                return Some((MatchedBy::ReversedWay, vec![*r]));
+            }
+        }
+        if !on_way.is_empty() {
+            return Some((MatchedBy::SplitWay, on_way));
+        }
+
+        let mut at_nodes = Vec::new();
+        for node in [anchor.src_node, anchor.dst_node] {
+            if let Some(i) = index.intersection_per_node.get(&node) {
+                for r in &self.get_i(*i).roads {
+                    if !at_nodes.contains(r) {
+                        at_nodes.push(*r);
+                    }
+                }
+            }
+        }
+        if at_nodes.is_empty() {
+            None
+        } else {
+            Some((MatchedBy::SameNode, at_nodes))
+        }
+    }
+
+    /// If road `r` runs along `linestring` (in Mercator), returns how far apart they are and if
+    /// the road points the opposite direction. A road split since saving is part of the
+    /// linestring, and a road merged since saving contains it.
+    fn overlap_linestring(&self, r: RoadID, linestring: &LineString) -> Option<(f64, bool)> {
+        let road = &self.get_r(r).linestring;
+        let road_mid = road.line_interpolate_point(0.5)?;
+        let saved_mid = linestring.line_interpolate_point(0.5)?;
+        let distance = Euclidean
+            .distance(&road_mid, linestring)
+            .min(Euclidean.distance(&saved_mid, road));
+        if distance > MAX_LINESTRING_MATCH_METERS {
+            return None;
+        }
+
+        // Roads crossing the linestring are close, but don't run along it
+        let start = linestring.line_locate_point(&road.points().next()?)?;
+        let end = linestring.line_locate_point(&road.points().last()?)?;
+        let overlap = (end - start).abs() * Euclidean.length(linestring);
+        if overlap < 0.5 * Euclidean.length(road).min(Euclidean.length(linestring)) {
+            return None;
+        }
+        Some((distance, start > end))
+    }
+}
@@ -1,20 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use fast_paths::{FastGraph, InputGraph};
+use fast_paths::{FastGraph, InputGraph, PathCalculator};
 use geo::{Coord, Euclidean, Length, LineString};
 use utils::NodeMap;
 
+use crate::access::VehicleClass;
+use crate::intersection_control::IntersectionDelays;
 use crate::map_model::{DiagonalFilter, Direction};
+use crate::turn_restrictions::TurnRestriction;
+use crate::turns::TurnPenalties;
 use crate::{Intersection, IntersectionID, MapModel, ModalFilter, Road, RoadID, TravelFlow};
 
 // For vehicles only
 #[derive(Debug, Clone)]
 pub struct Router {
     ch: FastGraph,
-    node_map: NodeMap<(RoadID, Direction)>,
+    node_map: NodeMap<RoutingNode>,
+    // Copies of via roads for turn restrictions, so routes can end there
+    restricted_nodes: HashMap<RoadID, Vec<usize>>,
     pub main_road_penalty: f64,
 }
 
+/// Travelling along a road in some direction. To honour turn restrictions through other roads,
+/// traffic that entered a via road from the restricted road is on a separate copy of it. One copy
+/// tracks every restriction from the same road through the same via roads so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RoutingNode {
+    road: RoadID,
+    direction: Direction,
+    /// An index into the sets of restrictions in effect, and the step along their `via`
+    restriction: Option<(usize, usize)>,
+}
+
+impl RoutingNode {
+    fn new(road: RoadID, direction: Direction) -> Self {
+        Self {
+            road,
+            direction,
+            restriction: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Route {
     pub steps: Vec<(RoadID, Direction)>,
@@ -35,6 +62,83 @@ pub trait RouterInput {
     }
     fn travel_flow(&self, r: RoadID) -> TravelFlow;
     fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter>;
+    /// How long it takes to travel along the road. Non-vehicle modes override this.
+    fn cost_seconds(&self, road: &Road) -> f64 {
+        road.cost_seconds()
+    }
+    /// Is turning from one road to another banned outright? By default, only OSM's simple turn
+    /// restrictions apply.
+    fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+        self.get_i(i).turn_restrictions.contains(&(from, to))
+    }
+    /// Does the turn restriction stop this traffic? By default, it's cars at any time.
+    fn turn_restriction_applies(&self, restriction: &TurnRestriction) -> bool {
+        restriction.applies(VehicleClass::Car, None)
+    }
+    /// Extra time for turning at intersections. By default, turns are free.
+    fn turn_penalties(&self) -> &TurnPenalties {
+        &TurnPenalties::NONE
+    }
+    /// Extra time for waiting at controlled intersections. By default, there's no delay.
+    fn intersection_delays(&self) -> &IntersectionDelays {
+        &IntersectionDelays::NONE
+    }
+}
+
+/// Wraps any `RouterInput`, replacing the time to cross every road
+pub(crate) struct OverrideCosts<'a, R> {
+    pub inner: &'a R,
+    pub seconds: HashMap<RoadID, f64>,
+}
+
+impl<R: RouterInput> RouterInput for OverrideCosts<'_, R> {
+    fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+        self.inner.roads_iter()
+    }
+
+    fn get_r(&self, r: RoadID) -> &Road {
+        self.inner.get_r(r)
+    }
+
+    fn get_i(&self, i: IntersectionID) -> &Intersection {
+        self.inner.get_i(i)
+    }
+
+    fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
+        self.inner.modal_filter(r)
+    }
+
+    fn has_modal_filter(&self, r: RoadID) -> bool {
+        self.inner.has_modal_filter(r)
+    }
+
+    fn travel_flow(&self, r: RoadID) -> TravelFlow {
+        self.inner.travel_flow(r)
+    }
+
+    fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+        self.inner.diagonal_filter(i)
+    }
+
+    fn cost_seconds(&self, road: &Road) -> f64 {
+        self.seconds[&road.id]
+    }
+
+    fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+        self.inner.turn_banned(i, from, to)
+    }
+
+    fn turn_restriction_applies(&self, restriction: &TurnRestriction) -> bool {
+        self.inner.turn_restriction_applies(restriction)
+    }
+
+    fn turn_penalties(&self) -> &TurnPenalties {
+        self.inner.turn_penalties()
+    }
+
+    fn intersection_delays(&self) -> &IntersectionDelays {
+        self.inner.intersection_delays()
+    }
 }
 
 impl Router {
@@ -47,6 +151,7 @@ impl Router {
         Self {
             ch,
             node_map,
+            restricted_nodes: HashMap::new(),
             main_road_penalty: 1.0,
         }
     }
@@ -54,16 +159,15 @@ impl Router {
     pub fn new(router_input: &impl RouterInput, main_road_penalty: f64) -> Self {
         let mut input_graph = InputGraph::new();
         let mut node_map = NodeMap::new();
+        // Sets of restrictions through other roads that apply, by intersection and indices. Every
+        // restriction in a set has the same `from` and the same `via` roads so far.
+        let mut restriction_sets: Vec<(IntersectionID, Vec<usize>)> = Vec::new();
+        // Reached some step along the via roads of a restriction in some direction, but not yet
+        // linked onwards
+        let mut restricted_queue: Vec<(usize, usize, Direction)> = Vec::new();
+        let mut restricted_nodes: HashMap<RoadID, Vec<usize>> = HashMap::new();
 
-        for road in router_input.roads_iter() {
-            if router_input.has_modal_filter(road.id) {
-                continue;
-            }
-            // Loops can't be part of a shortest path
-            if road.src_i == road.dst_i {
-                continue;
-            }
-
+        let cost = |road: &Road| {
             let penalty = if road.tags.is_any(
                 "highway",
                 vec![
@@ -83,17 +187,60 @@ impl Router {
             } else {
                 1.0
             };
-            let cost = (penalty * road.cost_seconds() * 100.0) as usize;
+            (penalty * router_input.cost_seconds(road) * 100.0) as usize
+        };
+        let turn_penalties = router_input.turn_penalties();
+        let intersection_delays = router_input.intersection_delays();
+        let turn_cost = |intersection: &Intersection, from: &Road, to: RoadID| {
+            let seconds = turn_penalties.cost(intersection, from, router_input.get_r(to))
+                + intersection_delays.cost(intersection, from);
+            (seconds * 100.0) as usize
+        };
+
+        for road in router_input.roads_iter() {
+            if router_input.has_modal_filter(road.id) {
+                continue;
+            }
+            // Loops can't be part of a shortest path
+            if road.src_i == road.dst_i {
+                continue;
+            }
+            let cost = cost(road);
 
             let mut link_through_intersection =
                 |intersection: &Intersection, direction: Direction| {
                     // a given NodeId might refer to a different physical feature across rebuilds of
                     // the routing graph - do not assume they are stable.
-                    let from = node_map.get_or_insert((road.id, direction));
-                    for outgoing_road in intersection.allowed_movements_from(road.id, router_input)
+                    let from = node_map.get_or_insert(RoutingNode::new(road.id, direction));
+                    for (to_r, to_direction) in
+                        intersection.allowed_movements_from(road.id, router_input)
                     {
-                        let to = node_map.get_or_insert(outgoing_road);
-                        input_graph.add_edge(from, to, cost);
+                        // Entering restrictions through other roads leads to a separate copy of
+                        // the first via road.
+                        let active: Vec<usize> = intersection
+                            .restrictions
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, restriction)| {
+                                restriction.from == road.id
+                                    && restriction.via.first() == Some(&to_r)
+                                    && router_input.turn_restriction_applies(restriction)
+                            })
+                            .map(|(idx, _)| idx)
+                            .collect();
+                        let to = if !active.is_empty() {
+                            let idx = restriction_sets.len();
+                            restriction_sets.push((intersection.id, active));
+                            restricted_queue.push((idx, 0, to_direction));
+                            node_map.get_or_insert(RoutingNode {
+                                road: to_r,
+                                direction: to_direction,
+                                restriction: Some((idx, 0)),
+                            })
+                        } else {
+                            node_map.get_or_insert(RoutingNode::new(to_r, to_direction))
+                        };
+                        input_graph.add_edge(from, to, cost + turn_cost(intersection, road, to_r));
                     }
                 };
 
@@ -105,12 +252,84 @@ impl Router {
                 link_through_intersection(router_input.get_i(road.src_i), Direction::Backwards);
             }
         }
+
+        // Traffic on a copy of a via road can do anything it could normally do, except continue
+        // any of the restricted movements
+        if !restricted_queue.is_empty() {
+            let included: HashSet<RoadID> = router_input.roads_iter().map(|r| r.id).collect();
+            while let Some((idx, step, direction)) = restricted_queue.pop() {
+                let (i, active) = restriction_sets[idx].clone();
+                let restrictions = &router_input.get_i(i).restrictions;
+                let road = router_input.get_r(restrictions[active[0]].via[step]);
+                let from = node_map.get_or_insert(RoutingNode {
+                    road: road.id,
+                    direction,
+                    restriction: Some((idx, step)),
+                });
+                restricted_nodes.entry(road.id).or_default().push(from);
+                if !included.contains(&road.id)
+                    || router_input.has_modal_filter(road.id)
+                    || road.src_i == road.dst_i
+                {
+                    continue;
+                }
+
+                let intersection = router_input.get_i(if direction == Direction::Forwards {
+                    road.dst_i
+                } else {
+                    road.src_i
+                });
+                for (to_r, to_direction) in
+                    intersection.allowed_movements_from(road.id, router_input)
+                {
+                    // Restrictions ending here might ban the turn. Others continue if this is
+                    // their next via road.
+                    let mut banned = false;
+                    let mut still_active = Vec::new();
+                    for restriction_idx in &active {
+                        let restriction = &restrictions[*restriction_idx];
+                        if step + 1 == restriction.via.len() {
+                            banned |= restriction.bans(to_r);
+                        } else if restriction.via[step + 1] == to_r {
+                            still_active.push(*restriction_idx);
+                        }
+                    }
+                    if banned {
+                        continue;
+                    }
+
+                    let to = if still_active.is_empty() {
+                        node_map.get_or_insert(RoutingNode::new(to_r, to_direction))
+                    } else {
+                        let next = if still_active == active {
+                            idx
+                        } else {
+                            restriction_sets.push((i, still_active));
+                            restriction_sets.len() - 1
+                        };
+                        restricted_queue.push((next, step + 1, to_direction));
+                        node_map.get_or_insert(RoutingNode {
+                            road: to_r,
+                            direction: to_direction,
+                            restriction: Some((next, step + 1)),
+                        })
+                    };
+                    input_graph.add_edge(
+                        from,
+                        to,
+                        cost(road) + turn_cost(intersection, road, to_r),
+                    );
+                }
+            }
+        }
+
         input_graph.freeze();
         let ch = fast_paths::prepare(&input_graph);
 
         Self {
             ch,
             node_map,
+            restricted_nodes,
             main_road_penalty,
         }
     }
@@ -126,20 +345,42 @@ impl Router {
             return None;
         }
 
-        // TODO Reuse: (Note `calc_path` is mutable)
-        let mut path_calc = fast_paths::create_calculator(&self.ch);
+        let mut path_calc = self.create_calculator();
+        self.route_with_calculator(&mut path_calc, start, end)
+    }
+
+    /// For `route_with_calculator`
+    pub(crate) fn create_calculator(&self) -> PathCalculator {
+        fast_paths::create_calculator(&self.ch)
+    }
+
+    /// Like `route_from_roads`, but reuses a calculator created from this router, which is faster
+    /// when finding many routes.
+    pub(crate) fn route_with_calculator(
+        &self,
+        path_calc: &mut PathCalculator,
+        start: RoadID,
+        end: RoadID,
+    ) -> Option<Route> {
+        if start == end {
+            return None;
+        }
+
         let mut starts = vec![];
         let mut ends = vec![];
         for direction in [Direction::Forwards, Direction::Backwards] {
             // We consider all start/end pairs equally.
             let extra_weight = 0;
-            if let Some(start) = self.node_map.get((start, direction)) {
+            if let Some(start) = self.node_map.get(RoutingNode::new(start, direction)) {
                 starts.push((start, extra_weight));
             };
-            if let Some(end) = self.node_map.get((end, direction)) {
+            if let Some(end) = self.node_map.get(RoutingNode::new(end, direction)) {
                 ends.push((end, extra_weight));
             };
         }
+        for node in self.restricted_nodes.get(&end).into_iter().flatten() {
+            ends.push((*node, 0));
+        }
         if starts.is_empty() || ends.is_empty() {
             return None;
         }
@@ -148,16 +389,38 @@ impl Router {
         let steps: Vec<_> = shortest_path
             .get_nodes()
             .iter()
-            .map(|n| self.node_map.translate_id(*n))
+            .map(|n| {
+                let node = self.node_map.translate_id(*n);
+                (node.road, node.direction)
+            })
             .collect();
         Some(Route { steps })
     }
 
-    /// Produce routes for all the requests and count how many routes cross each road
-    pub fn od_to_counts(&self, requests: &Vec<(RoadID, RoadID, usize)>) -> HashMap<RoadID, usize> {
+    /// Produce routes for all the requests and count how many routes cross each road. On native
+    /// builds, big batches of requests are split between threads; the counts are the same either
+    /// way.
+    pub fn od_to_counts(&self, requests: &[(RoadID, RoadID, usize)]) -> HashMap<RoadID, usize> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let threads = std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1);
+            if threads > 1 && requests.len() >= 2 * MIN_REQUESTS_PER_THREAD {
+                return self.od_to_counts_in_threads(requests, threads);
+            }
+        }
+        self.od_to_counts_sequential(requests)
+    }
+
+    fn od_to_counts_sequential(
+        &self,
+        requests: &[(RoadID, RoadID, usize)],
+    ) -> HashMap<RoadID, usize> {
+        let mut path_calc = fast_paths::create_calculator(&self.ch);
         let mut results = HashMap::new();
         for (r1, r2, count) in requests {
-            if let Some(route) = self.route_from_roads(*r1, *r2) {
+            if let Some(route) = self.route_with_calculator(&mut path_calc, *r1, *r2) {
                 for (r, _) in route.steps {
                     *results.entry(r).or_insert(0) += *count;
                 }
@@ -165,8 +428,40 @@ impl Router {
         }
         results
     }
+
+    /// Splits the requests into contiguous chunks, counts each chunk in its own thread with its
+    /// own calculator, then sums the counts. Addition doesn't depend on order, so the result
+    /// matches `od_to_counts_sequential`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn od_to_counts_in_threads(
+        &self,
+        requests: &[(RoadID, RoadID, usize)],
+        threads: usize,
+    ) -> HashMap<RoadID, usize> {
+        let chunk_size = requests
+            .len()
+            .div_ceil(threads)
+            .max(MIN_REQUESTS_PER_THREAD);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = requests
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || self.od_to_counts_sequential(chunk)))
+                .collect();
+            let mut results = HashMap::new();
+            for handle in handles {
+                for (r, count) in handle.join().expect("routing thread panicked") {
+                    *results.entry(r).or_insert(0) += count;
+                }
+            }
+            results
+        })
+    }
 }
 
+/// Starting a thread and a calculator isn't free, so don't split off smaller batches
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_REQUESTS_PER_THREAD: usize = 100;
+
 impl Route {
     pub fn to_linestring(&self, map: &MapModel) -> LineString {
         let mut pts = Vec::new();
@@ -195,7 +490,7 @@ impl Route {
         for (r, _) in &self.steps {
             let road = &map.roads[r.0];
             distance += Euclidean.length(&road.linestring);
-            time += road.cost_seconds();
+            time += map.road_cost_seconds(road);
         }
         (distance, time)
     }
@@ -277,8 +572,11 @@ mod tests {
         // ```
         let mut map = load_osm_xml("simple_four_way_intersection");
 
-        map.intersections[1].turn_restrictions =
-            vec![(r(1), r(3)), (r(3), r(0)), (r(0), r(2)), (r(2), r(1))];
+        // Turns are banned through edits, so they apply to the router after edits
+        for (from, to) in [(r(1), r(3)), (r(3), r(0)), (r(0), r(2)), (r(2), r(1))] {
+            map.set_turn_banned(IntersectionID(1), from, to, true)
+                .unwrap();
+        }
         map.rebuild_router(1.1);
 
         let Route {
@@ -300,4 +598,25 @@ mod tests {
             .route_from_roads(r(3), r(0));
         assert!(left_turn_path.is_none());
     }
+
+    #[test]
+    fn od_counts_in_threads_match_sequential() {
+        let map = load_osm_xml("parallel_routes");
+        let router = &map.router_before;
+        let mut requests = Vec::new();
+        for r1 in 0..map.roads.len() {
+            for r2 in 0..map.roads.len() {
+                requests.push((r(r1), r(r2), 1 + (r1 * 7 + r2 * 3) % 5));
+            }
+        }
+        // Enough requests for every thread to get some
+        let requests = requests.repeat(1 + 1000 / requests.len());
+
+        let expected = router.od_to_counts_sequential(&requests);
+        assert!(!expected.is_empty());
+        for threads in [2, 3, 8] {
+            assert_eq!(router.od_to_counts_in_threads(&requests, threads), expected);
+        }
+        assert_eq!(router.od_to_counts(&requests), expected);
+    }
 }
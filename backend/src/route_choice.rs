@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use nanorand::{Rng, WyRand};
+use serde::{Deserialize, Serialize};
+
+use crate::route::{OverrideCosts, RouterInput};
+use crate::{RoadID, Router};
+
+/// How drivers pick between routes. Real drivers don't agree on the single fastest route, so
+/// trips between the same places can be spread over several routes. Each sample randomly scales
+/// the time to cross every road, then finds the fastest route with those times. Routes that are
+/// nearly as fast as the best one are picked by some samples.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RouteChoice {
+    /// How many samples to take. 1 always picks the fastest route.
+    pub samples: usize,
+    /// Each road's time is scaled by a random factor within this fraction, like 0.3 for 70% to
+    /// 130%
+    pub spread: f64,
+    /// The same seed always picks the same routes
+    pub seed: u64,
+}
+
+impl RouteChoice {
+    pub const FASTEST: RouteChoice = RouteChoice {
+        samples: 1,
+        spread: 0.3,
+        seed: 42,
+    };
+
+    pub fn stochastic(samples: usize) -> Self {
+        Self {
+            samples,
+            ..Self::FASTEST
+        }
+    }
+
+    /// Builds a router per sample. With one sample, travel times are unchanged.
+    pub fn routers(&self, router_input: &impl RouterInput, main_road_penalty: f64) -> Vec<Router> {
+        if self.samples <= 1 {
+            return vec![Router::new(router_input, main_road_penalty)];
+        }
+        (0..self.samples)
+            .map(|sample| {
+                let mut rng = WyRand::new_seed(self.seed.wrapping_add(sample as u64));
+                let seconds = router_input
+                    .roads_iter()
+                    .map(|road| {
+                        // Uniform in [-1, 1]
+                        let x = rng.generate_range(0_u32..=20_000) as f64 / 10_000.0 - 1.0;
+                        let factor = 1.0 + self.spread * x;
+                        (road.id, factor * router_input.cost_seconds(road))
+                    })
+                    .collect();
+                Router::new(
+                    &OverrideCosts {
+                        inner: router_input,
+                        seconds,
+                    },
+                    main_road_penalty,
+                )
+            })
+            .collect()
+    }
+
+    /// Routes every request and counts trips per road. Each request's trips are split evenly
+    /// between the samples, rotating which samples get any remainder, so the total number of
+    /// trips stays the same. `fastest` can be an already built router for `router_input` with no
+    /// main road penalty, used when there's only one sample.
+    pub fn od_to_counts(
+        &self,
+        router_input: &impl RouterInput,
+        fastest: Option<&Router>,
+        requests: &[(RoadID, RoadID, usize)],
+    ) -> HashMap<RoadID, usize> {
+        if self.samples <= 1 {
+            if let Some(router) = fastest {
+                return router.od_to_counts(requests);
+            }
+        }
+
+        let routers = self.routers(router_input, 1.0);
+        let k = routers.len();
+        let mut counts = HashMap::new();
+        for (sample, router) in routers.iter().enumerate() {
+            let sample_requests: Vec<(RoadID, RoadID, usize)> = requests
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, (r1, r2, count))| {
+                    let remainder = (sample + k - idx % k) % k < count % k;
+                    let trips = count / k + usize::from(remainder);
+                    (trips > 0).then_some((*r1, *r2, trips))
+                })
+                .collect();
+            for (r, count) in router.od_to_counts(&sample_requests) {
+                *counts.entry(r).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+}
+
+impl Default for RouteChoice {
+    fn default() -> Self {
+        Self::FASTEST
+    }
+}
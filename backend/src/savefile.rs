@@ -0,0 +1,1046 @@
+//! Savefiles are a GeoJSON FeatureCollection with edits and neighbourhood boundaries. Because ids
+//! like RoadID and IntersectionID aren't guaranteed to be stable across loads, everything is
+//! anchored to the map by WGS84 geometry.
+//!
+//! Each feature has a `kind` property determining its other properties. The collection has a
+//! `version` foreign member; files written before this existed are version 0. Older versions are
+//! migrated when loading, so projects saved long ago keep working.
+//!
+//! Edits belong to a named scenario, listed in the `scenarios` foreign member. The
+//! `active_scenario` is the one being edited.
+//!
+//! Optionally, the undo/redo history of each neighbourhood in the active scenario is kept in the
+//! `edit_history` foreign member. Unlike edits, history refers to RoadIDs and IntersectionIDs, so
+//! it's only restored when the map hasn't changed.
+//!
+//! Modal filters with an access policy other than the default for their kind have an `access`
+//! property, listing the `allowed` vehicle classes and `active` time windows.
+//!
+//! Turns banned or allowed, compared to OSM's turn restrictions, are LineStrings from a point on
+//! the `from` road, through the intersection, to a point on the `to` road.
+//!
+//! The `edit_log` foreign member lists every edit made, with timestamps, authors, and comments,
+//! as GeoJSON features.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use geo::{LineString, MultiPolygon, Point};
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, JsonValue};
+use serde::{Deserialize, Serialize};
+
+use crate::access::AccessPolicy;
+use crate::edit_log::EditLogEntry;
+use crate::{Error, FilterKind, IntersectionID, RoadID, TravelFlow};
+
+/// Bump this and add a case to `migrate` whenever the format changes
+///
+/// - 0: Before versioning
+/// - 1: Modal filters don't carry properties only used for rendering
+/// - 2: Edits belong to a scenario. Later version 2 files may also have `osm` anchors on edits,
+///   the `edit_history` and `edit_log` foreign members, and `access` on modal filters. These are
+///   all optional, and older builds ignore them, so they didn't need a new version.
+/// - 3: Diagonal filters may have custom `groups`, and there are `turn_ban` features. Older builds
+///   would misread the groups as a 4-way filter, and can't parse turn bans.
+pub const CURRENT_VERSION: u64 = 3;
+
+/// A savefile migrated to the current version and checked for problems. Geometry is in WGS84.
+pub struct Savefile {
+    pub study_area_name: Option<String>,
+    /// Every scenario, including ones without edits
+    pub scenarios: Vec<String>,
+    pub active_scenario: String,
+    pub features: Vec<SavefileFeature>,
+    /// Keyed by neighbourhood name. Empty if history wasn't saved.
+    pub edit_history: BTreeMap<String, SavedHistory>,
+    pub edit_log: Vec<EditLogEntry>,
+}
+
+/// Older savefiles only had one set of edits, which are put in a scenario with this name
+pub const DEFAULT_SCENARIO: &str = "Default";
+
+/// Edits on roads may also record OSM IDs, which are used before geometry to re-anchor them when
+/// the OSM data changes. Older savefiles don't have these.
+pub enum SavefileFeature {
+    ModalFilter {
+        scenario: String,
+        pt: Point,
+        kind: FilterKind,
+        /// `None` means the default for `kind`
+        access: Option<AccessPolicy>,
+        anchor: Option<OsmAnchor>,
+    },
+    /// Placed at the intersection closest to `pt`, or at `osm_node`
+    DiagonalFilter {
+        scenario: String,
+        pt: Point,
+        is_rotated: bool,
+        osm_node: Option<i64>,
+        /// Only for filters with custom groups, instead of `is_rotated`
+        groups: Option<Vec<Vec<SavedGroupRoad>>>,
+    },
+    /// A filter from OSM that was removed
+    DeletedExistingModalFilter {
+        scenario: String,
+        pt: Point,
+        anchor: Option<OsmAnchor>,
+    },
+    /// The road most similar to `linestring` has a different direction than in OSM. The
+    /// direction is relative to `linestring`.
+    TravelFlow {
+        scenario: String,
+        linestring: LineString,
+        travel_flow: TravelFlow,
+        anchor: Option<OsmAnchor>,
+    },
+    /// Turning from the road at the start of `linestring` to the road at the end, through the
+    /// intersection in the middle, is banned or allowed, unlike in OSM
+    TurnBan {
+        scenario: String,
+        linestring: LineString,
+        banned: bool,
+        anchor: Option<TurnAnchor>,
+    },
+    /// The whole feature is kept, because the UI stores extra properties, like route snapper
+    /// waypoints
+    Boundary {
+        name: String,
+        feature: Feature,
+    },
+    StudyAreaBoundary {
+        boundary: MultiPolygon,
+    },
+}
+
+/// Identifies the road segment an edit was made on, using OSM IDs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OsmAnchor {
+    pub way: i64,
+    /// The OSM nodes at the start and end of the road, in the direction of the way
+    pub src_node: i64,
+    pub dst_node: i64,
+    /// For edits at a point, the fraction along the road
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent_along: Option<f64>,
+}
+
+/// One road in a group of a diagonal filter, matched to the road at the intersection closest to
+/// `pt` (in WGS84), preferring roads on the same OSM way
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedGroupRoad {
+    pub way: i64,
+    pub pt: (f64, f64),
+}
+
+/// Identifies a turn using OSM IDs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TurnAnchor {
+    pub node: i64,
+    pub from_way: i64,
+    pub to_way: i64,
+}
+
+/// Undo and redo history for one neighbourhood, oldest first
+#[derive(Serialize, Deserialize)]
+pub struct SavedHistory {
+    pub undo: Vec<SavedCommand>,
+    pub redo: Vec<SavedCommand>,
+}
+
+/// A `Command`, with OSM IDs to check it still applies to the same roads and intersections
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SavedCommand {
+    SetModalFilter {
+        road: usize,
+        osm: OsmAnchor,
+        filter: Option<SavedModalFilter>,
+    },
+    SetDiagonalFilter {
+        intersection: usize,
+        osm_node: i64,
+        /// `None` means no filter
+        is_rotated: Option<bool>,
+        /// For custom groups, the RoadIDs in each
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        groups: Option<Vec<Vec<usize>>>,
+    },
+    SetTravelFlow {
+        road: usize,
+        osm: OsmAnchor,
+        #[serde(with = "travel_flow_string")]
+        travel_flow: TravelFlow,
+    },
+    SetTurnBanned {
+        intersection: usize,
+        osm: TurnAnchor,
+        from: usize,
+        to: usize,
+        banned: bool,
+    },
+    Multiple {
+        commands: Vec<SavedCommand>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedModalFilter {
+    #[serde(with = "filter_kind_string")]
+    pub filter_kind: FilterKind,
+    pub percent_along: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessPolicy>,
+}
+
+/// Something wrong with a savefile, found while validating it
+#[derive(Debug)]
+pub struct SavefileProblem {
+    /// The feature with the problem, or `None` if the problem is with the whole file
+    pub feature_index: Option<usize>,
+    pub reason: String,
+}
+
+impl fmt::Display for SavefileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.feature_index {
+            Some(i) => write!(f, "feature #{i}: {}", self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+/// The properties of each kind of feature, as they appear in the file
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Properties {
+    ModalFilter {
+        #[serde(with = "filter_kind_string")]
+        filter_kind: FilterKind,
+        /// Only for diagonal filters
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<DiagonalFilterProperties>,
+        /// Only if it's not the default for the kind of filter
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        access: Option<AccessPolicy>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        osm: Option<OsmAnchor>,
+    },
+    DeletedExistingModalFilter {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        osm: Option<OsmAnchor>,
+    },
+    TravelFlow {
+        #[serde(with = "travel_flow_string")]
+        travel_flow: TravelFlow,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        osm: Option<OsmAnchor>,
+    },
+    TurnBan {
+        banned: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        osm: Option<TurnAnchor>,
+    },
+    Boundary {
+        name: String,
+    },
+    StudyAreaBoundary,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiagonalFilterProperties {
+    is_rotated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    osm_node: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<Vec<SavedGroupRoad>>>,
+}
+
+impl Savefile {
+    /// Migrates a savefile from any older version, then validates every feature. All problems
+    /// are reported at once, rather than just the first.
+    pub fn parse(mut gj: FeatureCollection) -> Result<Self, Error> {
+        let version = match gj
+            .foreign_members
+            .as_ref()
+            .and_then(|members| members.get("version"))
+        {
+            None => 0,
+            Some(value) => match value.as_u64() {
+                Some(version) => version,
+                None => {
+                    return Err(Error::SavefileValidation(vec![SavefileProblem {
+                        feature_index: None,
+                        reason: format!("version {value} isn't a non-negative integer"),
+                    }]));
+                }
+            },
+        };
+        if version > CURRENT_VERSION {
+            return Err(Error::UnsupportedSavefileVersion(version));
+        }
+        migrate(&mut gj, version);
+
+        let mut problems = Vec::new();
+
+        let study_area_name = match gj
+            .foreign_members
+            .as_ref()
+            .and_then(|members| members.get("study_area_name"))
+        {
+            None | Some(JsonValue::Null) => None,
+            Some(JsonValue::String(name)) => Some(name.clone()),
+            Some(value) => {
+                problems.push(SavefileProblem {
+                    feature_index: None,
+                    reason: format!("study_area_name {value} isn't a string"),
+                });
+                None
+            }
+        };
+
+        let members = gj.foreign_members.as_ref();
+        let scenarios: Vec<String> = match members
+            .and_then(|members| members.get("scenarios"))
+            .and_then(|x| serde_json::from_value(x.clone()).ok())
+        {
+            Some(scenarios) => scenarios,
+            None => {
+                problems.push(SavefileProblem {
+                    feature_index: None,
+                    reason: "scenarios isn't a list of names".to_string(),
+                });
+                Vec::new()
+            }
+        };
+        let active_scenario = match members
+            .and_then(|members| members.get("active_scenario"))
+            .and_then(|x| x.as_str())
+        {
+            Some(name) if scenarios.iter().any(|x| x == name) => name.to_string(),
+            _ => {
+                problems.push(SavefileProblem {
+                    feature_index: None,
+                    reason: "active_scenario isn't one of the scenarios".to_string(),
+                });
+                String::new()
+            }
+        };
+        if scenarios.iter().collect::<BTreeSet<_>>().len() != scenarios.len() {
+            problems.push(SavefileProblem {
+                feature_index: None,
+                reason: "scenarios has duplicate names".to_string(),
+            });
+        }
+
+        let edit_history = match members.and_then(|members| members.get("edit_history")) {
+            None | Some(JsonValue::Null) => BTreeMap::new(),
+            Some(value) => match serde_json::from_value(value.clone()) {
+                Ok(edit_history) => edit_history,
+                Err(err) => {
+                    problems.push(SavefileProblem {
+                        feature_index: None,
+                        reason: format!("edit_history is invalid: {err}"),
+                    });
+                    BTreeMap::new()
+                }
+            },
+        };
+
+        let mut edit_log = Vec::new();
+        match members.and_then(|members| members.get("edit_log")) {
+            None | Some(JsonValue::Null) => {}
+            Some(JsonValue::Array(list)) => {
+                for (index, value) in list.iter().enumerate() {
+                    match serde_json::from_value::<Feature>(value.clone())
+                        .map_err(|err| err.to_string())
+                        .and_then(|f| EditLogEntry::from_gj(&f))
+                    {
+                        Ok(entry) => edit_log.push(entry),
+                        Err(reason) => problems.push(SavefileProblem {
+                            feature_index: None,
+                            reason: format!("edit_log entry #{index}: {reason}"),
+                        }),
+                    }
+                }
+            }
+            Some(_) => problems.push(SavefileProblem {
+                feature_index: None,
+                reason: "edit_log isn't a list".to_string(),
+            }),
+        }
+
+        let mut features = Vec::new();
+        let mut boundary_names = BTreeSet::new();
+        for (feature_index, f) in gj.features.into_iter().enumerate() {
+            match parse_feature(f) {
+                Ok(feature) => {
+                    if let SavefileFeature::Boundary { ref name, .. } = feature {
+                        if !boundary_names.insert(name.clone()) {
+                            problems.push(SavefileProblem {
+                                feature_index: Some(feature_index),
+                                reason: format!("Multiple boundaries named {name}"),
+                            });
+                        }
+                    }
+                    if let Some(scenario) = feature.scenario() {
+                        if !scenarios.iter().any(|x| x == scenario) {
+                            problems.push(SavefileProblem {
+                                feature_index: Some(feature_index),
+                                reason: format!("Unknown scenario {scenario}"),
+                            });
+                        }
+                    }
+                    features.push(feature);
+                }
+                Err(reason) => problems.push(SavefileProblem {
+                    feature_index: Some(feature_index),
+                    reason,
+                }),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(Error::SavefileValidation(problems));
+        }
+        Ok(Self {
+            study_area_name,
+            scenarios,
+            active_scenario,
+            features,
+            edit_history,
+            edit_log,
+        })
+    }
+
+    /// Always writes the current version
+    pub fn to_gj(&self) -> FeatureCollection {
+        let features = self.features.iter().map(|f| f.to_gj()).collect();
+        let mut foreign_members = serde_json::json!({
+            "version": CURRENT_VERSION,
+            "study_area_name": self.study_area_name,
+            "scenarios": self.scenarios,
+            "active_scenario": self.active_scenario,
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+        if !self.edit_log.is_empty() {
+            let edit_log: Vec<Feature> = self
+                .edit_log
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| entry.to_gj(index))
+                .collect();
+            foreign_members.insert(
+                "edit_log".to_string(),
+                serde_json::to_value(edit_log).expect("valid JSON fields"),
+            );
+        }
+        if !self.edit_history.is_empty() {
+            foreign_members.insert(
+                "edit_history".to_string(),
+                serde_json::to_value(&self.edit_history).expect("valid JSON fields"),
+            );
+        }
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(foreign_members),
+        }
+    }
+}
+
+impl SavefileFeature {
+    /// The scenario an edit belongs to. Boundaries are shared by all scenarios.
+    pub fn scenario(&self) -> Option<&str> {
+        match self {
+            Self::ModalFilter { scenario, .. }
+            | Self::DiagonalFilter { scenario, .. }
+            | Self::DeletedExistingModalFilter { scenario, .. }
+            | Self::TravelFlow { scenario, .. }
+            | Self::TurnBan { scenario, .. } => Some(scenario),
+            Self::Boundary { .. } | Self::StudyAreaBoundary { .. } => None,
+        }
+    }
+
+    fn to_gj(&self) -> Feature {
+        let (geometry, props) = match self {
+            Self::ModalFilter {
+                pt,
+                kind,
+                access,
+                anchor,
+                ..
+            } => (
+                Geometry::from(pt),
+                Properties::ModalFilter {
+                    filter_kind: *kind,
+                    filter: None,
+                    access: access.clone(),
+                    osm: anchor.clone(),
+                },
+            ),
+            Self::DiagonalFilter {
+                pt,
+                is_rotated,
+                osm_node,
+                groups,
+                ..
+            } => (
+                Geometry::from(pt),
+                Properties::ModalFilter {
+                    filter_kind: FilterKind::DiagonalFilter,
+                    filter: Some(DiagonalFilterProperties {
+                        is_rotated: *is_rotated,
+                        osm_node: *osm_node,
+                        groups: groups.clone(),
+                    }),
+                    access: None,
+                    osm: None,
+                },
+            ),
+            Self::DeletedExistingModalFilter { pt, anchor, .. } => (
+                Geometry::from(pt),
+                Properties::DeletedExistingModalFilter {
+                    osm: anchor.clone(),
+                },
+            ),
+            Self::TravelFlow {
+                linestring,
+                travel_flow,
+                anchor,
+                ..
+            } => (
+                Geometry::from(linestring),
+                Properties::TravelFlow {
+                    travel_flow: *travel_flow,
+                    osm: anchor.clone(),
+                },
+            ),
+            Self::TurnBan {
+                linestring,
+                banned,
+                anchor,
+                ..
+            } => (
+                Geometry::from(linestring),
+                Properties::TurnBan {
+                    banned: *banned,
+                    osm: anchor.clone(),
+                },
+            ),
+            Self::Boundary { name, feature } => {
+                let mut f = feature.clone();
+                f.set_property("kind", "boundary");
+                f.set_property("name", name.clone());
+                return f;
+            }
+            Self::StudyAreaBoundary { boundary } => {
+                (Geometry::from(boundary), Properties::StudyAreaBoundary)
+            }
+        };
+
+        let JsonValue::Object(mut properties) =
+            serde_json::to_value(props).expect("valid JSON fields")
+        else {
+            unreachable!("properties always serialize as an object");
+        };
+        if let Some(scenario) = self.scenario() {
+            properties.insert("scenario".to_string(), scenario.into());
+        }
+        Feature {
+            bbox: None,
+            geometry: Some(geometry),
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
+}
+
+fn parse_feature(f: Feature) -> Result<SavefileFeature, String> {
+    let props: Properties = serde_json::from_value(JsonValue::Object(
+        f.properties.clone().unwrap_or_else(JsonObject::new),
+    ))
+    .map_err(|err| err.to_string())?;
+    let Some(ref geometry) = f.geometry else {
+        return Err("Feature doesn't have a geometry".to_string());
+    };
+    let geometry: geo::Geometry = geometry
+        .try_into()
+        .map_err(|err: geojson::Error| err.to_string())?;
+    // Only used by edits
+    let scenario = || match f.property("scenario").and_then(|x| x.as_str()) {
+        Some(scenario) => Ok(scenario.to_string()),
+        None => Err("Edit doesn't have a scenario".to_string()),
+    };
+
+    match (props, geometry) {
+        (
+            Properties::ModalFilter {
+                filter_kind: FilterKind::DiagonalFilter,
+                filter,
+                ..
+            },
+            geo::Geometry::Point(pt),
+        ) => {
+            let Some(filter) = filter else {
+                return Err("Diagonal filter missing filter.is_rotated".to_string());
+            };
+            Ok(SavefileFeature::DiagonalFilter {
+                scenario: scenario()?,
+                pt,
+                is_rotated: filter.is_rotated,
+                osm_node: filter.osm_node,
+                groups: filter.groups,
+            })
+        }
+        (
+            Properties::ModalFilter {
+                filter_kind,
+                access,
+                osm,
+                ..
+            },
+            geo::Geometry::Point(pt),
+        ) => Ok(SavefileFeature::ModalFilter {
+            scenario: scenario()?,
+            pt,
+            kind: filter_kind,
+            access,
+            anchor: osm,
+        }),
+        (Properties::DeletedExistingModalFilter { osm }, geo::Geometry::Point(pt)) => {
+            Ok(SavefileFeature::DeletedExistingModalFilter {
+                scenario: scenario()?,
+                pt,
+                anchor: osm,
+            })
+        }
+        (Properties::TravelFlow { travel_flow, osm }, geo::Geometry::LineString(linestring)) => {
+            Ok(SavefileFeature::TravelFlow {
+                scenario: scenario()?,
+                linestring,
+                travel_flow,
+                anchor: osm,
+            })
+        }
+        (Properties::TurnBan { banned, osm }, geo::Geometry::LineString(linestring)) => {
+            if linestring.0.len() != 3 {
+                return Err("Turn should have 3 points".to_string());
+            }
+            Ok(SavefileFeature::TurnBan {
+                scenario: scenario()?,
+                linestring,
+                banned,
+                anchor: osm,
+            })
+        }
+        (Properties::Boundary { name }, geo::Geometry::Polygon(_)) => {
+            Ok(SavefileFeature::Boundary { name, feature: f })
+        }
+        (Properties::StudyAreaBoundary, geo::Geometry::Polygon(p)) => {
+            Ok(SavefileFeature::StudyAreaBoundary { boundary: p.into() })
+        }
+        (Properties::StudyAreaBoundary, geo::Geometry::MultiPolygon(boundary)) => {
+            Ok(SavefileFeature::StudyAreaBoundary { boundary })
+        }
+        (_, geometry) => Err(format!(
+            "Unexpected {} geometry for this kind",
+            geometry_type(&geometry)
+        )),
+    }
+}
+
+fn geometry_type(geometry: &geo::Geometry) -> &'static str {
+    match geometry {
+        geo::Geometry::Point(_) => "Point",
+        geo::Geometry::Line(_) => "Line",
+        geo::Geometry::LineString(_) => "LineString",
+        geo::Geometry::Polygon(_) => "Polygon",
+        geo::Geometry::MultiPoint(_) => "MultiPoint",
+        geo::Geometry::MultiLineString(_) => "MultiLineString",
+        geo::Geometry::MultiPolygon(_) => "MultiPolygon",
+        geo::Geometry::GeometryCollection(_) => "GeometryCollection",
+        geo::Geometry::Rect(_) => "Rect",
+        geo::Geometry::Triangle(_) => "Triangle",
+    }
+}
+
+/// If the runner-up candidate is within this many meters of the best, a match is ambiguous
+const AMBIGUOUS_MATCH_METERS: f64 = 2.0;
+/// Saved points further than this from any road or intersection are dropped
+pub(crate) const MAX_POINT_MATCH_METERS: f64 = 50.0;
+/// Saved lines with endpoints further than this (on average) from any road are dropped
+pub(crate) const MAX_LINESTRING_MATCH_METERS: f64 = 20.0;
+
+/// Describes how each edit in a savefile was matched to the current map. When the OSM data changes
+/// underneath a project, edits may land somewhere unexpected or be dropped, and planners should
+/// review these.
+#[derive(Default)]
+pub struct LoadReport {
+    pub edits: Vec<EditMatch>,
+}
+
+pub struct EditMatch {
+    pub feature_index: usize,
+    /// The savefile `kind`, like `modal_filter`
+    pub kind: &'static str,
+    /// The saved geometry, in WGS84
+    pub saved: geo::Geometry,
+    pub status: MatchStatus,
+    /// `None` if the edit was dropped
+    pub matched_by: Option<MatchedBy>,
+    /// A road that was split may have several parts
+    pub roads: Vec<RoadID>,
+    pub intersection: Option<IntersectionID>,
+    /// For points, the distance in meters to the matched road or intersection. For lines, the
+    /// mean distance between the saved and matched endpoints.
+    pub distance: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatchStatus {
+    Matched,
+    /// Applied to the best candidate, but another was nearly as close
+    Ambiguous {
+        runner_up_distance: f64,
+    },
+    /// Not applied at all
+    Dropped {
+        reason: String,
+    },
+}
+
+/// How an edit was re-anchored to the current map. OSM IDs are tried before geometry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchedBy {
+    /// The same OSM way and nodes
+    SameWay,
+    /// The same OSM way and nodes, but the way now points the other direction
+    ReversedWay,
+    /// The same OSM way, now split into different roads or merged with others
+    SplitWay,
+    /// The OSM way is gone, but a road still ends at one of the same OSM nodes
+    SameNode,
+    /// Only the saved geometry
+    Geometry,
+}
+
+impl MatchedBy {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            Self::SameWay => "same_way",
+            Self::ReversedWay => "reversed_way",
+            Self::SplitWay => "split_way",
+            Self::SameNode => "same_node",
+            Self::Geometry => "geometry",
+        }
+    }
+}
+
+impl MatchStatus {
+    /// Judges a match from the distances to the best and second-best candidates
+    pub(crate) fn classify(best: Option<f64>, runner_up: Option<f64>, max_distance: f64) -> Self {
+        match (best, runner_up) {
+            (None, _) => Self::Dropped {
+                reason: "nothing nearby".to_string(),
+            },
+            (Some(best), _) if best > max_distance => Self::Dropped {
+                reason: format!("closest candidate is {best:.1}m away"),
+            },
+            (Some(best), Some(runner_up)) if runner_up - best < AMBIGUOUS_MATCH_METERS => {
+                Self::Ambiguous {
+                    runner_up_distance: runner_up,
+                }
+            }
+            _ => Self::Matched,
+        }
+    }
+
+    pub fn is_dropped(&self) -> bool {
+        matches!(self, Self::Dropped { .. })
+    }
+}
+
+impl LoadReport {
+    /// Edits that didn't cleanly match, for review
+    pub fn problems(&self) -> impl Iterator<Item = &EditMatch> {
+        self.edits
+            .iter()
+            .filter(|e| e.status != MatchStatus::Matched)
+    }
+
+    /// One feature per edit, with the saved geometry
+    pub fn to_gj(&self) -> FeatureCollection {
+        let mut features = Vec::new();
+        for e in &self.edits {
+            let mut f = Feature::from(Geometry::from(&e.saved));
+            f.set_property("feature_index", e.feature_index);
+            f.set_property("kind", e.kind);
+            match e.status {
+                MatchStatus::Matched => {
+                    f.set_property("status", "matched");
+                }
+                MatchStatus::Ambiguous { runner_up_distance } => {
+                    f.set_property("status", "ambiguous");
+                    f.set_property("runner_up_distance", runner_up_distance);
+                }
+                MatchStatus::Dropped { ref reason } => {
+                    f.set_property("status", "dropped");
+                    f.set_property("reason", reason.clone());
+                }
+            }
+            if let Some(matched_by) = e.matched_by {
+                f.set_property("matched_by", matched_by.to_string());
+            }
+            if !e.roads.is_empty() {
+                f.set_property("roads", e.roads.iter().map(|r| r.0).collect::<Vec<_>>());
+            }
+            if let Some(i) = e.intersection {
+                f.set_property("intersection", i.0);
+            }
+            if let Some(distance) = e.distance {
+                f.set_property("distance", distance);
+            }
+            features.push(f);
+        }
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+}
+
+/// Upgrades a savefile from `version` to `CURRENT_VERSION`, one step at a time
+fn migrate(gj: &mut FeatureCollection, mut version: u64) {
+    while version < CURRENT_VERSION {
+        match version {
+            0 => migrate_v0_to_v1(gj),
+            1 => migrate_v1_to_v2(gj),
+            2 => migrate_v2_to_v3(gj),
+            _ => unreachable!("no migration from version {version}"),
+        }
+        version += 1;
+    }
+    gj.foreign_members
+        .get_or_insert_with(JsonObject::new)
+        .insert("version".to_string(), version.into());
+}
+
+/// Version 0 modal filters carried along properties only used for rendering, including RoadIDs and
+/// IntersectionIDs that aren't stable across loads. Nothing reads these, so drop them.
+fn migrate_v0_to_v1(gj: &mut FeatureCollection) {
+    for f in &mut gj.features {
+        if f.property("kind").and_then(|x| x.as_str()) != Some("modal_filter") {
+            continue;
+        }
+        for key in ["road", "angle", "edited", "intersection_id"] {
+            f.remove_property(key);
+        }
+        if let Some(filter) = f
+            .properties
+            .as_mut()
+            .and_then(|props| props.get_mut("filter"))
+            .and_then(|x| x.as_object_mut())
+        {
+            filter.retain(|key, _| key == "is_rotated");
+        }
+    }
+}
+
+/// Version 1 had only one set of edits. Put them in a default scenario.
+fn migrate_v1_to_v2(gj: &mut FeatureCollection) {
+    for f in &mut gj.features {
+        if matches!(
+            f.property("kind").and_then(|x| x.as_str()),
+            Some("modal_filter" | "deleted_existing_modal_filter" | "travel_flow")
+        ) {
+            f.set_property("scenario", DEFAULT_SCENARIO);
+        }
+    }
+    let members = gj.foreign_members.get_or_insert_with(JsonObject::new);
+    members.insert("scenarios".to_string(), vec![DEFAULT_SCENARIO].into());
+    members.insert("active_scenario".to_string(), DEFAULT_SCENARIO.into());
+}
+
+/// Version 3 only added things that version 2 files don't have, so there's nothing to change. The
+/// bump is so that older builds refuse newer files, instead of misreading them.
+fn migrate_v2_to_v3(_: &mut FeatureCollection) {}
+
+mod filter_kind_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::FilterKind;
+
+    pub fn serialize<S: Serializer>(kind: &FilterKind, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(kind.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<FilterKind, D::Error> {
+        let x = String::deserialize(d)?;
+        FilterKind::from_string(&x).map_err(D::Error::custom)
+    }
+}
+
+mod travel_flow_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::TravelFlow;
+
+    pub fn serialize<S: Serializer>(dir: &TravelFlow, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(dir.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<TravelFlow, D::Error> {
+        let x = String::deserialize(d)?;
+        TravelFlow::from_string(&x).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(gj: serde_json::Value) -> Result<Savefile, Error> {
+        Savefile::parse(serde_json::from_value(gj).unwrap())
+    }
+
+    #[test]
+    fn migrate_v0() {
+        let savefile = parse(serde_json::json!({
+            "type": "FeatureCollection",
+            "study_area_name": "bristol",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-2.6, 51.4] },
+                    "properties": {
+                        "kind": "modal_filter",
+                        "filter_kind": "diagonal_filter",
+                        "intersection_id": 12,
+                        "edited": true,
+                        "filter": { "group_a": [1, 2], "group_b": [3, 4], "is_rotated": true, "angle": 45.0 }
+                    }
+                }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(savefile.study_area_name.as_deref(), Some("bristol"));
+        assert!(matches!(
+            savefile.features[0],
+            SavefileFeature::DiagonalFilter {
+                is_rotated: true,
+                ..
+            }
+        ));
+
+        let gj = savefile.to_gj();
+        assert_eq!(
+            gj.foreign_members.as_ref().unwrap()["version"],
+            CURRENT_VERSION
+        );
+        assert_eq!(
+            gj.features[0].property("filter").unwrap(),
+            &serde_json::json!({ "is_rotated": true })
+        );
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let Err(Error::SavefileValidation(problems)) = parse(serde_json::json!({
+            "type": "FeatureCollection",
+            "version": 1,
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-2.6, 51.4] },
+                    "properties": { "kind": "modal_filter", "filter_kind": "moat" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-2.6, 51.4] },
+                    "properties": { "kind": "modal_filter", "filter_kind": "no_entry" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-2.6, 51.4] },
+                    "properties": { "kind": "travel_flow", "travel_flow": "forwards" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": null,
+                    "properties": { "kind": "deleted_existing_modal_filter" }
+                }
+            ]
+        })) else {
+            panic!("savefile should be invalid");
+        };
+        let indices: Vec<_> = problems.iter().map(|p| p.feature_index).collect();
+        assert_eq!(indices, vec![Some(0), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn classify_matches() {
+        assert_eq!(
+            MatchStatus::classify(Some(1.0), Some(30.0), 50.0),
+            MatchStatus::Matched
+        );
+        assert_eq!(
+            MatchStatus::classify(Some(1.0), Some(2.5), 50.0),
+            MatchStatus::Ambiguous {
+                runner_up_distance: 2.5
+            }
+        );
+        assert!(MatchStatus::classify(Some(60.0), None, 50.0).is_dropped());
+        assert!(MatchStatus::classify(None, None, 50.0).is_dropped());
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let savefile = parse(serde_json::json!({
+            "type": "FeatureCollection",
+            "version": 2,
+            "scenarios": ["Default"],
+            "active_scenario": "Default",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [-2.6, 51.4] },
+                    "properties": {
+                        "kind": "modal_filter",
+                        "filter_kind": "diagonal_filter",
+                        "scenario": "Default",
+                        "filter": { "is_rotated": true }
+                    }
+                }
+            ]
+        }))
+        .unwrap();
+        assert!(matches!(
+            savefile.features[0],
+            SavefileFeature::DiagonalFilter {
+                is_rotated: true,
+                groups: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            savefile.to_gj().foreign_members.unwrap()["version"],
+            CURRENT_VERSION
+        );
+    }
+
+    #[test]
+    fn newer_version() {
+        assert!(matches!(
+            parse(serde_json::json!({
+                "type": "FeatureCollection",
+                "version": CURRENT_VERSION + 1,
+                "features": []
+            })),
+            Err(Error::UnsupportedSavefileVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use geo::LineInterpolatePoint;
+use geojson::FeatureCollection;
+
+use crate::intersection_control::IntersectionDelays;
+use crate::map_model::DiagonalFilter;
+use crate::route::RouterInput;
+use crate::turns::TurnPenalties;
+use crate::{Error, Intersection, IntersectionID, MapModel, ModalFilter, Road, RoadID, TravelFlow};
+
+/// A named layer of edits over the basemap, so different options can be compared in one project.
+/// The active scenario's edits are stored directly in `MapModel`; the others are stashed in
+/// `MapModel::other_scenarios`.
+#[derive(Clone)]
+pub struct Scenario {
+    pub modal_filters: BTreeMap<RoadID, ModalFilter>,
+    pub diagonal_filters: BTreeMap<IntersectionID, DiagonalFilter>,
+    pub travel_flows: BTreeMap<RoadID, TravelFlow>,
+    pub banned_turns: BTreeSet<(IntersectionID, RoadID, RoadID)>,
+}
+
+impl Scenario {
+    /// No edits, just what's in OSM
+    pub fn from_basemap(map: &MapModel) -> Self {
+        Self {
+            modal_filters: map.original_modal_filters.clone(),
+            diagonal_filters: BTreeMap::new(),
+            travel_flows: map
+                .roads
+                .iter()
+                .map(|r| (r.id, TravelFlow::from_osm(&r.tags)))
+                .collect(),
+            banned_turns: map.osm_banned_turns(),
+        }
+    }
+
+    /// Routes over the whole map with this scenario's edits, whether or not it's active
+    pub fn router_input<'a>(&'a self, map: &'a MapModel) -> impl RouterInput + 'a {
+        struct ScenarioRouterInput<'a> {
+            map: &'a MapModel,
+            scenario: &'a Scenario,
+        }
+
+        impl RouterInput for ScenarioRouterInput<'_> {
+            fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+                self.map.roads.iter()
+            }
+
+            fn get_r(&self, r: RoadID) -> &Road {
+                self.map.get_r(r)
+            }
+
+            fn get_i(&self, i: IntersectionID) -> &Intersection {
+                self.map.get_i(i)
+            }
+
+            fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
+                self.scenario.modal_filters.get(&r)
+            }
+
+            fn travel_flow(&self, r: RoadID) -> TravelFlow {
+                self.scenario.travel_flows[&r]
+            }
+
+            fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+                self.scenario.diagonal_filters.get(&i)
+            }
+
+            fn turn_banned(&self, i: IntersectionID, from: RoadID, to: RoadID) -> bool {
+                self.scenario.banned_turns.contains(&(i, from, to))
+            }
+
+            fn turn_penalties(&self) -> &TurnPenalties {
+                &self.map.turn_penalties
+            }
+
+            fn intersection_delays(&self) -> &IntersectionDelays {
+                &self.map.intersection_delays
+            }
+
+            fn cost_seconds(&self, road: &Road) -> f64 {
+                self.map.road_cost_seconds(road)
+            }
+        }
+
+        ScenarioRouterInput {
+            map,
+            scenario: self,
+        }
+    }
+}
+
+impl MapModel {
+    /// Every scenario name, in order, including the active one
+    pub fn scenario_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.other_scenarios.keys().cloned().collect();
+        names.push(self.active_scenario.clone());
+        names.sort();
+        names
+    }
+
+    /// A copy of the edits in any scenario
+    pub fn get_scenario(&self, name: &str) -> Result<Scenario, Error> {
+        if name == self.active_scenario {
+            return Ok(Scenario {
+                modal_filters: self.modal_filters.clone(),
+                diagonal_filters: self.diagonal_filters.clone(),
+                travel_flows: self.travel_flows.clone(),
+                banned_turns: self.banned_turns.clone(),
+            });
+        }
+        self.other_scenarios
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownScenario(name.to_string()))
+    }
+
+    /// Adds a scenario without any edits. It doesn't become active.
+    pub fn new_scenario(&mut self, name: String) -> Result<(), Error> {
+        self.check_new_scenario_name(&name)?;
+        let scenario = Scenario::from_basemap(self);
+        self.other_scenarios.insert(name, scenario);
+        Ok(())
+    }
+
+    /// Copies all edits from one scenario into a new one. It doesn't become active.
+    pub fn clone_scenario(&mut self, from: &str, name: String) -> Result<(), Error> {
+        self.check_new_scenario_name(&name)?;
+        let scenario = self.get_scenario(from)?;
+        self.other_scenarios.insert(name, scenario);
+        Ok(())
+    }
+
+    /// Makes another scenario active. Undo history only applies to one scenario, so every
+    /// neighbourhood's is cleared.
+    pub fn switch_scenario(&mut self, name: &str) -> Result<(), Error> {
+        if name == self.active_scenario {
+            return Ok(());
+        }
+        let Some(next) = self.other_scenarios.remove(name) else {
+            return Err(Error::UnknownScenario(name.to_string()));
+        };
+        let prev = self.replace_edits(next);
+        let prev_name = std::mem::replace(&mut self.active_scenario, name.to_string());
+        self.other_scenarios.insert(prev_name, prev);
+
+        self.clear_edit_histories();
+        self.after_edited();
+        Ok(())
+    }
+
+    /// The active scenario can't be deleted
+    pub fn delete_scenario(&mut self, name: &str) -> Result<(), Error> {
+        if name == self.active_scenario {
+            return Err(Error::ActiveScenario(name.to_string()));
+        }
+        if self.other_scenarios.remove(name).is_none() {
+            return Err(Error::UnknownScenario(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns GJ with a feature for every modal filter, diagonal filter, travel flow, and banned
+    /// turn that differs between two scenarios. The `a` and `b` properties describe the edit in
+    /// each, with null meaning no filter. Modal filters also have `a_access` and `b_access`, and
+    /// diagonal filters have `a_custom`, `b_custom`, `a_groups`, and `b_groups`.
+    pub fn diff_scenarios(&self, name_a: &str, name_b: &str) -> Result<FeatureCollection, Error> {
+        let a = self.get_scenario(name_a)?;
+        let b = self.get_scenario(name_b)?;
+        let mut features = Vec::new();
+
+        let roads: BTreeSet<&RoadID> = a
+            .modal_filters
+            .keys()
+            .chain(b.modal_filters.keys())
+            .collect();
+        for r in roads {
+            let filter_a = a.modal_filters.get(r);
+            let filter_b = b.modal_filters.get(r);
+            if filter_a == filter_b {
+                continue;
+            }
+            // Place the point where the filter is in b, if it exists
+            let percent_along = filter_b.or(filter_a).unwrap().percent_along;
+            let pt = self
+                .get_r(*r)
+                .linestring
+                .line_interpolate_point(percent_along)
+                .unwrap();
+            let mut f = self.mercator.to_wgs84_gj(&pt);
+            f.set_property("kind", "modal_filter");
+            f.set_property("road", r.0);
+            f.set_property("a", filter_a.map(|f| f.kind.to_string()));
+            f.set_property("b", filter_b.map(|f| f.kind.to_string()));
+            // The kind may be the same, with only who can pass or when changing
+            f.set_property(
+                "a_access",
+                serde_json::to_value(filter_a.map(|f| &f.access)).unwrap(),
+            );
+            f.set_property(
+                "b_access",
+                serde_json::to_value(filter_b.map(|f| &f.access)).unwrap(),
+            );
+            features.push(f);
+        }
+
+        let intersections: BTreeSet<&IntersectionID> = a
+            .diagonal_filters
+            .keys()
+            .chain(b.diagonal_filters.keys())
+            .collect();
+        for i in intersections {
+            let filter_a = a.diagonal_filters.get(i);
+            let filter_b = b.diagonal_filters.get(i);
+            if filter_a == filter_b {
+                continue;
+            }
+            let mut f = self.mercator.to_wgs84_gj(&self.get_i(*i).point);
+            f.set_property("kind", "diagonal_filter");
+            f.set_property("intersection", i.0);
+            f.set_property("a", filter_a.map(|f| f.is_rotated));
+            f.set_property("b", filter_b.map(|f| f.is_rotated));
+            // Custom groups are always unrotated, so describe the groups too
+            for (key, filter) in [("a", filter_a), ("b", filter_b)] {
+                f.set_property(format!("{key}_custom"), filter.map(|f| f.custom));
+                f.set_property(
+                    format!("{key}_groups"),
+                    filter.map(|f| {
+                        f.groups
+                            .iter()
+                            .map(|group| group.iter().map(|r| r.0).collect::<Vec<_>>())
+                            .collect::<Vec<_>>()
+                    }),
+                );
+            }
+            features.push(f);
+        }
+
+        for r in &self.roads {
+            let flow_a = a.travel_flows[&r.id];
+            let flow_b = b.travel_flows[&r.id];
+            if flow_a == flow_b {
+                continue;
+            }
+            let mut f = self.mercator.to_wgs84_gj(&r.linestring);
+            f.set_property("kind", "travel_flow");
+            f.set_property("road", r.id.0);
+            f.set_property("a", flow_a.to_string());
+            f.set_property("b", flow_b.to_string());
+            features.push(f);
+        }
+
+        for (i, from, to) in a.banned_turns.symmetric_difference(&b.banned_turns) {
+            let mut f = self
+                .mercator
+                .to_wgs84_gj(&self.movement_linestring(*i, *from, *to));
+            f.set_property("kind", "turn_ban");
+            f.set_property("intersection", i.0);
+            f.set_property("from", from.0);
+            f.set_property("to", to.0);
+            f.set_property("a", a.banned_turns.contains(&(*i, *from, *to)));
+            f.set_property("b", b.banned_turns.contains(&(*i, *from, *to)));
+            features.push(f);
+        }
+
+        Ok(FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "scenario_a": name_a,
+                    "scenario_b": name_b,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        })
+    }
+
+    /// Swaps the active scenario's edits for new ones, returning the old
+    pub(crate) fn replace_edits(&mut self, scenario: Scenario) -> Scenario {
+        Scenario {
+            modal_filters: std::mem::replace(&mut self.modal_filters, scenario.modal_filters),
+            diagonal_filters: std::mem::replace(
+                &mut self.diagonal_filters,
+                scenario.diagonal_filters,
+            ),
+            travel_flows: std::mem::replace(&mut self.travel_flows, scenario.travel_flows),
+            banned_turns: std::mem::replace(&mut self.banned_turns, scenario.banned_turns),
+        }
+    }
+
+    fn check_new_scenario_name(&self, name: &str) -> Result<(), Error> {
+        if name == self.active_scenario || self.other_scenarios.contains_key(name) {
+            return Err(Error::DuplicateScenario(name.to_string()));
+        }
+        Ok(())
+    }
+}
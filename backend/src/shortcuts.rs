@@ -1,10 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use geo::{Euclidean, Length, LineString};
 use geojson::Feature;
 
 use crate::map_model::Direction;
-use crate::route::Router;
 use crate::{MapModel, Neighbourhood, RoadID};
 
 pub struct Shortcuts {
@@ -16,12 +15,18 @@ pub struct Path {
     // TODO: dedupe - make this `Route`
     steps: Vec<(RoadID, Direction)>,
     directness: f64,
+    /// The fraction of sampled trips between the same two roads taking this path
+    share: f64,
 }
 
 impl Shortcuts {
+    /// Finds routes between every pair of roads crossing the neighbourhood boundary that cut
+    /// through the interior. With the default `MapModel::route_choice`, this is only the fastest
+    /// route. With more samples, there may be several alternative paths between the same pair,
+    /// but each road still counts as one shortcut per pair.
     pub fn new(map: &MapModel, neighbourhood: &Neighbourhood) -> Self {
         let router_input = neighbourhood.router_input(map);
-        let router = Router::new(&router_input, 1.0);
+        let routers = map.route_choice.routers(&router_input, 1.0);
         let mut paths = Vec::new();
         let mut count_per_road = HashMap::new();
         for start_i in &neighbourhood.border_intersections {
@@ -41,33 +46,53 @@ impl Shortcuts {
                         if !neighbourhood.interior_roads.contains(end_r) {
                             continue;
                         }
-                        let Some(route) = router.route_from_roads(*start_r, *end_r) else {
+                        // Each distinct route, with how many samples picked it
+                        let mut alternatives: Vec<(Vec<(RoadID, Direction)>, usize)> = Vec::new();
+                        for router in &routers {
+                            let Some(route) = router.route_from_roads(*start_r, *end_r) else {
+                                continue;
+                            };
+                            match alternatives
+                                .iter_mut()
+                                .find(|(steps, _)| *steps == route.steps)
+                            {
+                                Some((_, count)) => *count += 1,
+                                None => alternatives.push((route.steps, 1)),
+                            }
+                        }
+                        if alternatives.is_empty() {
                             continue;
-                        };
-                        let mut shortcut_length = 0.0;
-                        for (r, _direction) in &route.steps {
-                            let road = map.get_r(*r);
-                            *count_per_road.entry(road.id).or_insert(0) += 1;
-                            shortcut_length += Euclidean.length(&road.linestring);
                         }
 
                         // How long is the shortest route through the original router, using this
                         // neighbourhood or not?
-                        let direct_length = match map
+                        let direct_length = map
                             .router_before
                             .route_from_roads(*start_r, *end_r)
-                        {
-                            Some(route) => Euclidean.length(&route.to_linestring(map)),
-                            None => {
+                            .map(|route| Euclidean.length(&route.to_linestring(map)));
+
+                        let mut roads_used = HashSet::new();
+                        for (steps, count) in alternatives {
+                            let mut shortcut_length = 0.0;
+                            for (r, _direction) in &steps {
+                                roads_used.insert(*r);
+                                shortcut_length += Euclidean.length(&map.get_r(*r).linestring);
+                            }
+
+                            let direct_length = direct_length.unwrap_or_else(|| {
                                 warn!("Found a shortcut from {start_r} to {end_r}, but not a route using the whole map");
                                 shortcut_length
-                            }
-                        };
-                        let directness = shortcut_length / direct_length;
-                        paths.push(Path {
-                            steps: route.steps,
-                            directness,
-                        });
+                            });
+                            let directness = shortcut_length / direct_length;
+                            paths.push(Path {
+                                steps,
+                                directness,
+                                share: count as f64 / routers.len() as f64,
+                            });
+                        }
+                        for r in roads_used {
+                            *count_per_road.entry(r).or_insert(0) += 1;
+                        }
                     }
                 }
             }
@@ -108,6 +133,7 @@ impl Path {
         let length = Euclidean.length(&linestring);
         let mut f = map.mercator.to_wgs84_gj(&linestring);
         f.set_property("directness", self.directness);
+        f.set_property("share", self.share);
         f.set_property("length_meters", length);
         f
     }
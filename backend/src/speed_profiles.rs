@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use osm_reader::WayID;
+use serde::{Deserialize, Serialize};
+
+use crate::speeds::KPH_TO_MPH;
+use crate::Error;
+
+/// Which observed speeds to use for routing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimePeriod {
+    /// Ignore observed speeds, and drive at the speed limit
+    FreeFlow,
+    Peak,
+    OffPeak,
+}
+
+/// Observed speeds per OSM way, like from GPS traces, in mph. Ways without an entry, or without a
+/// speed for some time period, use their speed limit.
+#[derive(Clone, Debug, Default)]
+pub struct SpeedProfiles {
+    per_way: HashMap<WayID, [Option<f64>; 2]>,
+}
+
+impl SpeedProfiles {
+    /// Parses CSV with a header row. The `way` column has OSM way IDs. Speeds are in `peak_mph`
+    /// and `off_peak_mph` columns, or `peak_kph` and `off_peak_kph`. Either speed may be blank.
+    /// Other columns are ignored. Fields can't be quoted.
+    pub fn from_csv(input: &str) -> Result<Self, Error> {
+        let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+        let header: Vec<&str> = lines
+            .next()
+            .ok_or_else(|| Error::InvalidSpeedProfile("empty input".to_string()))?
+            .split(',')
+            .map(|x| x.trim())
+            .collect();
+        let column = |name: &str| header.iter().position(|x| *x == name);
+        let way_column =
+            column("way").ok_or_else(|| Error::InvalidSpeedProfile("no way column".to_string()))?;
+        let mut speed_columns = Vec::new();
+        for period in ["peak", "off_peak"] {
+            let (idx, factor) = if let Some(idx) = column(&format!("{period}_mph")) {
+                (idx, 1.0)
+            } else if let Some(idx) = column(&format!("{period}_kph")) {
+                (idx, KPH_TO_MPH)
+            } else {
+                return Err(Error::InvalidSpeedProfile(format!(
+                    "no {period}_mph or {period}_kph column"
+                )));
+            };
+            speed_columns.push((idx, factor));
+        }
+
+        let mut per_way = HashMap::new();
+        // The header is line 1
+        for (line_number, line) in lines.enumerate().map(|(idx, line)| (idx + 2, line)) {
+            let fields: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+            let invalid =
+                |reason: &str| Error::InvalidSpeedProfile(format!("line {line_number}: {reason}"));
+            let way = fields
+                .get(way_column)
+                .and_then(|x| x.parse::<i64>().ok())
+                .ok_or_else(|| invalid("bad way ID"))?;
+
+            let mut speeds = [None, None];
+            for (speed, (idx, factor)) in speeds.iter_mut().zip(&speed_columns) {
+                let value = fields.get(*idx).cloned().unwrap_or("");
+                if value.is_empty() {
+                    continue;
+                }
+                let x = value
+                    .parse::<f64>()
+                    .map_err(|_| invalid(&format!("bad speed {value}")))?;
+                if !x.is_finite() || x <= 0.0 {
+                    return Err(invalid(&format!("speed {value} isn't positive")));
+                }
+                *speed = Some(x * factor);
+            }
+            per_way.insert(WayID(way), speeds);
+        }
+        Ok(Self { per_way })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.per_way.is_empty()
+    }
+
+    pub fn has_way(&self, way: WayID) -> bool {
+        self.per_way.contains_key(&way)
+    }
+
+    /// In mph, if there's an observed speed for this way and time period
+    pub fn speed_mph(&self, way: WayID, period: TimePeriod) -> Option<f64> {
+        let speeds = self.per_way.get(&way)?;
+        match period {
+            TimePeriod::FreeFlow => None,
+            TimePeriod::Peak => speeds[0],
+            TimePeriod::OffPeak => speeds[1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv() {
+        let profiles = SpeedProfiles::from_csv(
+            "way,name,peak_kph,off_peak_kph\n\
+             123,High Street,16.09,48.28\n\
+             456,Back Lane,,32.19\n",
+        )
+        .unwrap();
+        let speed = |way, period| {
+            profiles
+                .speed_mph(WayID(way), period)
+                .map(|x| (x * 10.0).round() / 10.0)
+        };
+        assert_eq!(speed(123, TimePeriod::Peak), Some(10.0));
+        assert_eq!(speed(123, TimePeriod::OffPeak), Some(30.0));
+        assert_eq!(speed(123, TimePeriod::FreeFlow), None);
+        assert_eq!(speed(456, TimePeriod::Peak), None);
+        assert_eq!(speed(456, TimePeriod::OffPeak), Some(20.0));
+        assert_eq!(speed(789, TimePeriod::OffPeak), None);
+
+        assert!(SpeedProfiles::from_csv("way,peak_mph\n1,10\n").is_err());
+        assert!(SpeedProfiles::from_csv("way,peak_mph,off_peak_mph\n1,0,10\n").is_err());
+        assert!(SpeedProfiles::from_csv("way,peak_mph,off_peak_mph\nx,10,10\n").is_err());
+    }
+}
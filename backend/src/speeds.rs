@@ -0,0 +1,392 @@
+use geo::Coord;
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+pub(crate) const KPH_TO_MPH: f64 = 0.621371;
+
+/// Where a road's speed limit came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedSource {
+    /// A number in `maxspeed`
+    Tagged,
+    /// A country-specific code like `GB:zone20` or `DE:urban`, in `maxspeed` or a related tag
+    Zone,
+    /// The legal default for the type of road in the study area's region
+    LegalDefault,
+    /// The region is unknown, so a rough guess for the type of road
+    Guess,
+}
+
+/// A place with its own default speed limits. To support another country, add a variant, its
+/// bounds, and its defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedRegion {
+    England,
+    Scotland,
+    Wales,
+    France,
+    Germany,
+    UnitedStates,
+}
+
+/// Boxes as (min lon, min lat, max lon, max lat), each well inside one region. They leave out
+/// land near borders, so a study area there has no region, rather than getting a neighbouring
+/// country's defaults.
+const REGION_BOUNDS: &[(SpeedRegion, [f64; 4])] = &[
+    (SpeedRegion::England, [-2.5, 50.5, 1.45, 52.0]),
+    (SpeedRegion::England, [-2.5, 52.0, 1.8, 53.3]),
+    (SpeedRegion::England, [-3.05, 53.3, 0.5, 54.9]),
+    (SpeedRegion::England, [-2.5, 54.9, -1.3, 55.2]),
+    (SpeedRegion::England, [-5.75, 49.9, -2.5, 51.2]),
+    (SpeedRegion::England, [-2.75, 51.2, -2.5, 51.52]),
+    (SpeedRegion::Scotland, [-7.7, 55.6, -2.6, 58.7]),
+    (SpeedRegion::Scotland, [-2.6, 55.85, -1.7, 58.7]),
+    (SpeedRegion::Scotland, [-3.5, 58.7, -0.7, 60.9]),
+    (SpeedRegion::Scotland, [-5.2, 54.75, -3.6, 55.6]),
+    (SpeedRegion::Scotland, [-3.6, 55.1, -2.9, 55.6]),
+    (SpeedRegion::Wales, [-5.4, 51.5, -3.15, 52.0]),
+    (SpeedRegion::Wales, [-3.35, 51.4, -3.05, 51.6]),
+    (SpeedRegion::Wales, [-3.05, 51.52, -2.85, 51.75]),
+    (SpeedRegion::Wales, [-4.8, 52.0, -3.2, 53.1]),
+    (SpeedRegion::Wales, [-4.7, 53.1, -3.3, 53.45]),
+    (SpeedRegion::France, [-1.3, 43.4, 5.5, 49.4]),
+    (SpeedRegion::France, [2.5, 42.6, 7.3, 43.4]),
+    (SpeedRegion::France, [5.5, 43.4, 6.5, 45.9]),
+    (SpeedRegion::France, [6.5, 43.4, 7.3, 43.95]),
+    (SpeedRegion::France, [0.0, 49.4, 3.9, 50.2]),
+    (SpeedRegion::France, [1.6, 50.2, 3.15, 50.68]),
+    (SpeedRegion::France, [1.6, 50.68, 2.4, 51.1]),
+    (SpeedRegion::France, [5.5, 47.6, 6.4, 49.3]),
+    (SpeedRegion::France, [6.4, 47.65, 7.45, 48.5]),
+    (SpeedRegion::France, [6.4, 48.5, 7.77, 48.95]),
+    (SpeedRegion::France, [-5.2, 46.2, -1.3, 48.9]),
+    (SpeedRegion::France, [-1.85, 48.9, 0.0, 49.7]),
+    (SpeedRegion::France, [8.5, 41.35, 9.6, 43.1]),
+    (SpeedRegion::Germany, [7.4, 50.9, 13.9, 54.4]),
+    (SpeedRegion::Germany, [6.5, 50.3, 7.4, 51.7]),
+    (SpeedRegion::Germany, [6.6, 49.6, 7.4, 50.3]),
+    (SpeedRegion::Germany, [6.97, 49.22, 7.4, 49.6]),
+    (SpeedRegion::Germany, [7.82, 47.85, 12.3, 48.45]),
+    (SpeedRegion::Germany, [8.3, 48.45, 12.3, 49.5]),
+    (SpeedRegion::Germany, [7.4, 49.2, 8.3, 49.6]),
+    (SpeedRegion::Germany, [7.4, 49.5, 12.0, 50.9]),
+    (SpeedRegion::UnitedStates, [-106.0, 31.9, -95.0, 48.8]),
+    (SpeedRegion::UnitedStates, [-122.7, 32.8, -106.0, 48.8]),
+    (SpeedRegion::UnitedStates, [-112.0, 31.9, -106.7, 32.8]),
+    (SpeedRegion::UnitedStates, [-124.5, 37.0, -122.7, 48.1]),
+    (SpeedRegion::UnitedStates, [-117.3, 32.65, -116.5, 32.8]),
+    (SpeedRegion::UnitedStates, [-95.0, 31.9, -80.0, 41.5]),
+    (SpeedRegion::UnitedStates, [-95.0, 41.5, -84.5, 46.2]),
+    (SpeedRegion::UnitedStates, [-80.0, 36.0, -69.9, 42.5]),
+    (SpeedRegion::UnitedStates, [-79.9, 32.0, -75.0, 36.0]),
+    (SpeedRegion::UnitedStates, [-95.0, 24.5, -79.9, 31.9]),
+    (SpeedRegion::UnitedStates, [-99.5, 28.0, -95.0, 31.9]),
+    (SpeedRegion::UnitedStates, [-97.8, 26.5, -95.0, 28.0]),
+    (SpeedRegion::UnitedStates, [-78.5, 42.5, -69.9, 43.7]),
+    (SpeedRegion::UnitedStates, [-75.0, 43.7, -69.9, 44.8]),
+    (SpeedRegion::UnitedStates, [-168.0, 54.0, -141.5, 71.5]),
+];
+
+impl SpeedRegion {
+    /// From a WGS84 point, like the center of the study area. None if the point isn't clearly
+    /// inside one region, including near borders.
+    pub fn from_location(pt: Coord) -> Option<Self> {
+        REGION_BOUNDS
+            .iter()
+            .find(|(_, [x1, y1, x2, y2])| *x1 <= pt.x && pt.x <= *x2 && *y1 <= pt.y && pt.y <= *y2)
+            .map(|(region, _)| *region)
+    }
+
+    fn uses_mph(self) -> bool {
+        matches!(
+            self,
+            Self::England | Self::Scotland | Self::Wales | Self::UnitedStates
+        )
+    }
+
+    /// In mph, on lit roads in built-up areas
+    fn urban_default(self) -> f64 {
+        match self {
+            Self::England | Self::Scotland => 30.0,
+            // Since September 2023
+            Self::Wales => 20.0,
+            Self::France | Self::Germany => 50.0 * KPH_TO_MPH,
+            // This varies by state
+            Self::UnitedStates => 25.0,
+        }
+    }
+
+    /// In mph. Roads are assumed to be in built-up areas, unless they're tagged `lit=no` or
+    /// they're motorways or trunk roads not tagged `lit=yes`.
+    fn legal_default(self, tags: &Tags) -> f64 {
+        let highway = tags.get("highway").map(|x| x.as_str()).unwrap_or("");
+        let highway = highway.trim_end_matches("_link");
+        let dual_carriageway =
+            tags.is("dual_carriageway", "yes") || (highway == "trunk" && tags.is("oneway", "yes"));
+        let urban = match highway {
+            "motorway" => false,
+            "trunk" => tags.is("lit", "yes"),
+            _ => !tags.is("lit", "no"),
+        };
+        let kph = |x: f64| x * KPH_TO_MPH;
+
+        match (self, highway) {
+            (_, "pedestrian") => {
+                if self.uses_mph() {
+                    10.0
+                } else {
+                    kph(7.0)
+                }
+            }
+            (Self::England | Self::Scotland | Self::Wales, "motorway") => 70.0,
+            (Self::England | Self::Scotland | Self::Wales, "living_street") => 15.0,
+            (Self::England | Self::Scotland | Self::Wales, _) => {
+                if urban {
+                    self.urban_default()
+                } else if dual_carriageway {
+                    70.0
+                } else {
+                    60.0
+                }
+            }
+
+            (Self::France, "motorway") => kph(130.0),
+            // Zones de rencontre
+            (Self::France, "living_street") => kph(20.0),
+            (Self::France, _) => {
+                if urban {
+                    kph(50.0)
+                } else if dual_carriageway {
+                    kph(110.0)
+                } else {
+                    kph(80.0)
+                }
+            }
+
+            // There's no limit on much of the Autobahn, so use the advisory speed
+            (Self::Germany, "motorway") => kph(130.0),
+            // Walking speed
+            (Self::Germany, "living_street") => kph(7.0),
+            (Self::Germany, _) => {
+                if urban {
+                    kph(50.0)
+                } else {
+                    kph(100.0)
+                }
+            }
+
+            (Self::UnitedStates, "motorway") => 65.0,
+            (Self::UnitedStates, "trunk") => 55.0,
+            (Self::UnitedStates, "residential" | "living_street" | "service") => 25.0,
+            (Self::UnitedStates, _) => {
+                if urban {
+                    30.0
+                } else {
+                    55.0
+                }
+            }
+        }
+    }
+}
+
+/// Infers the speed limit of a road in mph, preferring `maxspeed`, then zone codes, then the
+/// legal default for the region. Without a region, falls back to a rough guess by road type.
+pub fn infer_speed_limit(tags: &Tags, region: Option<SpeedRegion>) -> (usize, SpeedSource) {
+    let (mph, source) = if let Some(mph) = tags.get("maxspeed").and_then(|x| parse_maxspeed(x)) {
+        (mph, SpeedSource::Tagged)
+    } else if let Some(mph) = [
+        "maxspeed",
+        "maxspeed:type",
+        "source:maxspeed",
+        "zone:maxspeed",
+    ]
+    .into_iter()
+    .find_map(|key| parse_zone(tags.get(key)?, region))
+    {
+        (mph, SpeedSource::Zone)
+    } else if let Some(region) = region {
+        (region.legal_default(tags), SpeedSource::LegalDefault)
+    } else {
+        (guess(tags), SpeedSource::Guess)
+    };
+    (mph.round() as usize, source)
+}
+
+/// Parses numeric values, like `30 mph` or `50`, which is km/h. Returns mph.
+fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(mph) = value.strip_suffix("mph") {
+        return mph.trim().parse::<f64>().ok();
+    }
+    let kph = ["km/h", "kmh", "kph"]
+        .into_iter()
+        .find_map(|unit| value.strip_suffix(unit))
+        .unwrap_or(value);
+    kph.trim().parse::<f64>().ok().map(|x| x * KPH_TO_MPH)
+}
+
+/// Parses implicit limits, like `GB:zone20`, `DE:zone:30`, `FR:urban`, or `GB:nsl_single`, plus
+/// `walk` and `none`. Returns mph.
+fn parse_zone(value: &str, region: Option<SpeedRegion>) -> Option<f64> {
+    match value {
+        "walk" => return Some(7.0 * KPH_TO_MPH),
+        // Use the German advisory speed
+        "none" => return Some(130.0 * KPH_TO_MPH),
+        _ => {}
+    }
+
+    let (country, kind) = value.split_once(':')?;
+    let uses_mph = matches!(country, "GB" | "UK" | "US");
+    if let Some(limit) = kind.strip_prefix("zone") {
+        let limit = limit.trim_start_matches(':').parse::<f64>().ok()?;
+        return Some(if uses_mph { limit } else { limit * KPH_TO_MPH });
+    }
+
+    let mph = match (country, kind) {
+        ("GB" | "UK", "nsl_single") => 60.0,
+        ("GB" | "UK", "nsl_dual" | "motorway") => 70.0,
+        ("GB" | "UK", "urban" | "restricted") => region
+            .filter(|r| matches!(r, SpeedRegion::Wales))
+            .map(|r| r.urban_default())
+            .unwrap_or(30.0),
+        ("FR", "urban") => 50.0 * KPH_TO_MPH,
+        ("FR", "rural") => 80.0 * KPH_TO_MPH,
+        ("FR", "motorway") => 130.0 * KPH_TO_MPH,
+        ("DE", "urban") => 50.0 * KPH_TO_MPH,
+        ("DE", "rural") => 100.0 * KPH_TO_MPH,
+        ("DE", "motorway") => 130.0 * KPH_TO_MPH,
+        ("DE", "living_street") => 7.0 * KPH_TO_MPH,
+        ("DE", "bicycle_road") => 30.0 * KPH_TO_MPH,
+        _ => return None,
+    };
+    Some(mph)
+}
+
+/// In mph
+fn guess(tags: &Tags) -> f64 {
+    match tags.get("highway").map(|x| x.as_str()).unwrap_or("") {
+        "motorway" | "motorway_link" => 70.0,
+        "trunk" | "trunk_link" => 60.0,
+        "primary" | "primary_link" => 40.0,
+        "secondary" | "secondary_link" | "tertiary" | "tertiary_link" => 30.0,
+        "residential" | "service" | "unclassified" => 20.0,
+        "living_street" => 15.0,
+        "pedestrian" => 10.0,
+        // Should look into these
+        _ => 10.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(kv: &[(&str, &str)]) -> Tags {
+        Tags(
+            kv.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn tagged_and_zones() {
+        let england = Some(SpeedRegion::England);
+        for (input, expected) in [
+            (vec![("maxspeed", "20 mph")], (20, SpeedSource::Tagged)),
+            (vec![("maxspeed", "50")], (31, SpeedSource::Tagged)),
+            (vec![("maxspeed", "30 km/h")], (19, SpeedSource::Tagged)),
+            (vec![("maxspeed", "GB:zone20")], (20, SpeedSource::Zone)),
+            (
+                vec![("highway", "primary"), ("maxspeed:type", "GB:nsl_single")],
+                (60, SpeedSource::Zone),
+            ),
+            (
+                vec![("highway", "residential"), ("source:maxspeed", "DE:urban")],
+                (31, SpeedSource::Zone),
+            ),
+            (
+                vec![("highway", "residential")],
+                (30, SpeedSource::LegalDefault),
+            ),
+            (
+                vec![("highway", "trunk"), ("dual_carriageway", "yes")],
+                (70, SpeedSource::LegalDefault),
+            ),
+        ] {
+            assert_eq!(infer_speed_limit(&tags(&input), england), expected);
+        }
+
+        let residential = tags(&[("highway", "residential")]);
+        assert_eq!(
+            infer_speed_limit(&residential, Some(SpeedRegion::Wales)),
+            (20, SpeedSource::LegalDefault)
+        );
+        assert_eq!(
+            infer_speed_limit(&residential, Some(SpeedRegion::France)),
+            (31, SpeedSource::LegalDefault)
+        );
+        assert_eq!(
+            infer_speed_limit(&residential, None),
+            (20, SpeedSource::Guess)
+        );
+    }
+
+    #[test]
+    fn regions() {
+        for (lon, lat, expected) in [
+            (-2.59, 51.45, Some(SpeedRegion::England)),
+            (-0.13, 51.5, Some(SpeedRegion::England)),
+            (-2.24, 53.48, Some(SpeedRegion::England)),
+            (-3.19, 55.95, Some(SpeedRegion::Scotland)),
+            (-4.25, 55.86, Some(SpeedRegion::Scotland)),
+            (-3.18, 51.48, Some(SpeedRegion::Wales)),
+            (2.35, 48.86, Some(SpeedRegion::France)),
+            (7.75, 48.58, Some(SpeedRegion::France)),
+            (5.37, 43.3, Some(SpeedRegion::France)),
+            (13.4, 52.52, Some(SpeedRegion::Germany)),
+            (11.58, 48.14, Some(SpeedRegion::Germany)),
+            (6.96, 50.94, Some(SpeedRegion::Germany)),
+            (-122.33, 47.61, Some(SpeedRegion::UnitedStates)),
+            (-87.63, 41.88, Some(SpeedRegion::UnitedStates)),
+            (151.2, -33.87, None),
+            // Neighbouring countries
+            (2.17, 41.39, None),
+            (-2.93, 43.26, None),
+            (-0.88, 41.65, None),
+            (14.42, 50.09, None),
+            (8.54, 47.38, None),
+            (13.04, 47.8, None),
+            (14.55, 53.43, None),
+            (-6.26, 53.35, None),
+            (-5.93, 54.6, None),
+            (4.35, 50.85, None),
+            (-79.38, 43.65, None),
+            (-123.37, 48.43, None),
+            // Too close to a border to tell
+            (7.59, 47.56, None),
+            (7.81, 48.57, None),
+        ] {
+            assert_eq!(
+                SpeedRegion::from_location(Coord { x: lon, y: lat }),
+                expected,
+                "({lon}, {lat})"
+            );
+        }
+    }
+
+    #[test]
+    fn bounds_dont_overlap() {
+        for (i, (region1, [x1, y1, x2, y2])) in REGION_BOUNDS.iter().enumerate() {
+            for (region2, [x3, y3, x4, y4]) in &REGION_BOUNDS[i + 1..] {
+                let overlap = x1 <= x4 && x3 <= x2 && y1 <= y4 && y3 <= y2;
+                assert!(
+                    region1 == region2 || !overlap,
+                    "{region1:?} and {region2:?}"
+                );
+            }
+        }
+    }
+}
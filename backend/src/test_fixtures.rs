@@ -59,12 +59,12 @@ impl NeighbourhoodFixture {
 
         Ok(move || {
             let demand = None;
-            MapModel::new(
+            Ok(MapModel::new(
                 &input_bytes,
                 multi_polygon.clone(),
                 Some(study_area_name.to_string()),
                 demand,
-            )
+            )?)
         })
     }
 
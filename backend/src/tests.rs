@@ -43,6 +43,7 @@ fn get_gj(neighbourhood_fixture: &NeighbourhoodFixture) -> Result<String> {
         f.remove_property("road");
         f.remove_property("edited");
         f.remove_property("angle");
+        f.remove_property("access");
         gj.features.push(f);
     }
 
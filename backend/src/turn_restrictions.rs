@@ -0,0 +1,481 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use osm_reader::{NodeID, OsmID, WayID};
+use utils::Tags;
+
+use crate::access::{TimeWindow, VehicleClass};
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// A turn restriction that doesn't reduce to a simple banned (from, to) pair at one intersection,
+/// because it passes through other roads, has exceptions, or only applies some of the time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnRestriction {
+    pub from: RoadID,
+    /// The roads between `from` and `to`, in order. Empty when the restriction is at a single
+    /// intersection.
+    pub via: Vec<RoadID>,
+    pub to: RoadID,
+    /// If true, traffic from `from` must continue to `to`. Otherwise, it can't.
+    pub only: bool,
+    /// These can ignore the restriction
+    pub except: BTreeSet<VehicleClass>,
+    /// The restriction only applies during these windows. If empty, it always applies.
+    pub active: Vec<TimeWindow>,
+}
+
+impl TurnRestriction {
+    /// Does the restriction apply to this vehicle at this time? With no time, every time window
+    /// is assumed to apply.
+    pub fn applies(&self, vehicle: VehicleClass, minute_of_day: Option<u16>) -> bool {
+        if self.except.contains(&vehicle) {
+            return false;
+        }
+        match minute_of_day {
+            Some(minute) if !self.active.is_empty() => {
+                self.active.iter().any(|window| window.contains(minute))
+            }
+            _ => true,
+        }
+    }
+
+    /// After reaching the end of `via`, is it banned to continue onto this road?
+    pub fn bans(&self, to: RoadID) -> bool {
+        if self.only {
+            to != self.to
+        } else {
+            to == self.to
+        }
+    }
+}
+
+#[derive(Clone)]
+enum OsmVia {
+    Node(NodeID),
+    Ways(Vec<WayID>),
+}
+
+/// A restriction relation as tagged in OSM, before matching to roads
+pub(crate) struct OsmTurnRestriction {
+    from: WayID,
+    via: OsmVia,
+    to: WayID,
+    only: bool,
+    except: BTreeSet<VehicleClass>,
+    active: Vec<TimeWindow>,
+}
+
+impl OsmTurnRestriction {
+    /// Handles https://wiki.openstreetmap.org/wiki/Relation:restriction with one `from` and one
+    /// `to` way, and either one `via` node or some `via` ways. Restrictions only for other
+    /// vehicles, like `restriction:hgv`, are ignored. For `restriction:conditional`, only the time
+    /// of day is understood, not days of the week. It replaces any plain `restriction` during its
+    /// time windows, so there can be two restrictions, or none with a value like `none`.
+    pub(crate) fn parse(members: &Vec<(String, OsmID)>, tags: &Tags) -> Vec<Self> {
+        if !tags.is("type", "restriction") {
+            return Vec::new();
+        }
+        let conditional = tags
+            .get("restriction:conditional")
+            .and_then(|x| x.split_once('@'))
+            .map(|(value, condition)| (value.trim(), parse_time_windows(condition)))
+            // Conditions depending on something else, like the weather, can't be modelled
+            .filter(|(_, active)| !active.is_empty());
+        let mut values = Vec::new();
+        match (tags.get("restriction"), conditional) {
+            (Some(value), None) => {
+                values.push((value.as_str(), Vec::new()));
+            }
+            (Some(value), Some((conditional_value, active))) => {
+                if conditional_value == value {
+                    values.push((value.as_str(), Vec::new()));
+                } else {
+                    if let Some(outside) = outside_time_windows(&active) {
+                        values.push((value.as_str(), outside));
+                    }
+                    values.push((conditional_value, active));
+                }
+            }
+            (None, Some((value, active))) => {
+                values.push((value, active));
+            }
+            (None, None) => {}
+        }
+
+        let Some((from, via, to)) = parse_members(members) else {
+            return Vec::new();
+        };
+        let except = tags
+            .get("except")
+            .map(|x| parse_except(x))
+            .unwrap_or_default();
+        values
+            .into_iter()
+            .filter_map(|(value, active)| {
+                let only = if value.starts_with("only_") {
+                    true
+                } else if value.starts_with("no_") {
+                    false
+                } else {
+                    return None;
+                };
+                Some(Self {
+                    from,
+                    via: via.clone(),
+                    to,
+                    only,
+                    except: except.clone(),
+                    active,
+                })
+            })
+            .collect()
+    }
+
+    /// Is this simple enough to store as banned (from, to) pairs?
+    fn is_unconditional(&self) -> bool {
+        self.except.is_empty() && self.active.is_empty()
+    }
+}
+
+/// The `from`, `via`, and `to` members of a restriction relation
+fn parse_members(members: &Vec<(String, OsmID)>) -> Option<(WayID, OsmVia, WayID)> {
+    let mut from = None;
+    let mut via_node = None;
+    let mut via_ways = Vec::new();
+    let mut to = None;
+    for (role, member) in members {
+        match (role.as_str(), member) {
+            ("from", OsmID::Way(w)) if from.is_none() => {
+                from = Some(*w);
+            }
+            ("to", OsmID::Way(w)) if to.is_none() => {
+                to = Some(*w);
+            }
+            ("via", OsmID::Node(n)) if via_node.is_none() && via_ways.is_empty() => {
+                via_node = Some(*n);
+            }
+            ("via", OsmID::Way(w)) if via_node.is_none() => {
+                via_ways.push(*w);
+            }
+            // Some other case, bail out
+            _ => {
+                return None;
+            }
+        }
+    }
+    let via = match via_node {
+        Some(n) => OsmVia::Node(n),
+        None if !via_ways.is_empty() => OsmVia::Ways(via_ways),
+        None => {
+            return None;
+        }
+    };
+
+    Some((from?, via, to?))
+}
+
+/// Finds the roads each restriction applies to, and stores them in the intersection where the
+/// restricted movement begins
+pub(crate) fn apply_turn_restrictions(
+    map: &mut MapModel,
+    turn_restrictions: Vec<OsmTurnRestriction>,
+) {
+    let mut roads_per_way: HashMap<WayID, Vec<RoadID>> = HashMap::new();
+    for road in &map.roads {
+        roads_per_way.entry(road.way).or_default().push(road.id);
+    }
+    let intersection_per_node: HashMap<NodeID, IntersectionID> =
+        map.intersections.iter().map(|i| (i.node, i.id)).collect();
+
+    for restriction in turn_restrictions {
+        match restriction.via {
+            OsmVia::Node(node) => {
+                let Some(i) = intersection_per_node.get(&node) else {
+                    continue;
+                };
+                let intersection = &mut map.intersections[i.0];
+
+                // One OSM way turns into multiple Roads. The restriction only makes sense on the
+                // Road connected to this intersection. So search only this intersection's roads.
+                let mut from = None;
+                let mut to = None;
+                for r in &intersection.roads {
+                    let way = map.roads[r.0].way;
+                    if way == restriction.from {
+                        from = Some(*r);
+                    } else if way == restriction.to {
+                        to = Some(*r);
+                    }
+                }
+                let (Some(from), Some(to)) = (from, to) else {
+                    continue;
+                };
+
+                if !restriction.is_unconditional() {
+                    intersection.restrictions.push(TurnRestriction {
+                        from,
+                        via: Vec::new(),
+                        to,
+                        only: restriction.only,
+                        except: restriction.except,
+                        active: restriction.active,
+                    });
+                } else if restriction.only {
+                    // Ban every other turn
+                    for other in &intersection.roads {
+                        if *other != from && *other != to {
+                            intersection.turn_restrictions.push((from, *other));
+                        }
+                    }
+                } else {
+                    intersection.turn_restrictions.push((from, to));
+                }
+            }
+            OsmVia::Ways(ref via_ways) => {
+                let Some((i, from, via, to)) = find_via_roads(
+                    map,
+                    &roads_per_way,
+                    restriction.from,
+                    via_ways,
+                    restriction.to,
+                ) else {
+                    continue;
+                };
+                map.intersections[i.0].restrictions.push(TurnRestriction {
+                    from,
+                    via,
+                    to,
+                    only: restriction.only,
+                    except: restriction.except,
+                    active: restriction.active,
+                });
+            }
+        }
+    }
+}
+
+/// Finds the shortest sequence of roads along `via_ways` connecting a road of `from_way` to a road
+/// of `to_way`. Returns the intersection where `from` meets the first via road too.
+fn find_via_roads(
+    map: &MapModel,
+    roads_per_way: &HashMap<WayID, Vec<RoadID>>,
+    from_way: WayID,
+    via_ways: &[WayID],
+    to_way: WayID,
+) -> Option<(IntersectionID, RoadID, Vec<RoadID>, RoadID)> {
+    let roads_of = |w: &WayID| roads_per_way.get(w).cloned().unwrap_or_default();
+    let via_roads: BTreeSet<RoadID> = via_ways.iter().flat_map(roads_of).collect();
+    let to_roads: BTreeSet<RoadID> = roads_of(&to_way).into_iter().collect();
+
+    // Search from every end of every from road at once. Each state is the current intersection
+    // and the via roads taken so far.
+    let mut queue: VecDeque<(RoadID, IntersectionID, IntersectionID, Vec<RoadID>)> =
+        VecDeque::new();
+    for from in roads_of(&from_way) {
+        let road = map.get_r(from);
+        for i in [road.src_i, road.dst_i] {
+            queue.push_back((from, i, i, Vec::new()));
+        }
+    }
+    while let Some((from, start_i, i, path)) = queue.pop_front() {
+        let intersection = map.get_i(i);
+        if !path.is_empty() {
+            if let Some(to) = intersection.roads.iter().find(|r| to_roads.contains(r)) {
+                return Some((start_i, from, path, *to));
+            }
+        }
+        // Every via road can be used once, so the search ends
+        for r in &intersection.roads {
+            if via_roads.contains(r) && !path.contains(r) {
+                let road = map.get_r(*r);
+                let next_i = if road.src_i == i {
+                    road.dst_i
+                } else {
+                    road.src_i
+                };
+                let mut path = path.clone();
+                path.push(*r);
+                queue.push_back((from, start_i, next_i, path));
+            }
+        }
+    }
+    None
+}
+
+/// Extracts times of day from an opening hours condition like `(Mo-Fr 07:00-09:30,16:00-18:00)`.
+/// Days are ignored.
+fn parse_time_windows(condition: &str) -> Vec<TimeWindow> {
+    condition
+        .split(|c: char| c == ';' || c == ',' || c.is_whitespace())
+        .filter_map(|token| {
+            let token = token.trim_matches(|c| c == '(' || c == ')');
+            let (start, end) = token.split_once('-')?;
+            Some(TimeWindow {
+                start: parse_hh_mm(start)?,
+                end: parse_hh_mm(end)?,
+            })
+        })
+        .collect()
+}
+
+/// The times of day outside every window, or `None` if the windows cover the whole day
+fn outside_time_windows(windows: &[TimeWindow]) -> Option<Vec<TimeWindow>> {
+    let mut outside = Vec::new();
+    let mut start = None;
+    for minute in 0..24 * 60 {
+        let inside = windows.iter().any(|w| w.contains(minute));
+        match (inside, start) {
+            (false, None) => {
+                start = Some(minute);
+            }
+            (true, Some(s)) => {
+                outside.push(TimeWindow {
+                    start: s,
+                    end: minute,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    match start {
+        // The windows never apply
+        Some(0) => Some(Vec::new()),
+        // Wrap past midnight, joining any window from midnight
+        Some(s) => {
+            match outside.first_mut() {
+                Some(first) if first.start == 0 => {
+                    first.start = s;
+                }
+                _ => {
+                    outside.push(TimeWindow { start: s, end: 0 });
+                }
+            }
+            Some(outside)
+        }
+        None if outside.is_empty() => None,
+        None => Some(outside),
+    }
+}
+
+fn parse_hh_mm(x: &str) -> Option<u16> {
+    let (hours, minutes) = x.split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    if hours > 24 || minutes >= 60 {
+        return None;
+    }
+    Some((hours * 60 + minutes) % (24 * 60))
+}
+
+/// Unrecognized values, like `hgv`, are ignored
+fn parse_except(x: &str) -> BTreeSet<VehicleClass> {
+    x.split(';')
+        .filter_map(|value| match value.trim() {
+            "psv" | "bus" => Some(VehicleClass::Bus),
+            "bicycle" => Some(VehicleClass::Bicycle),
+            "emergency" => Some(VehicleClass::Emergency),
+            "motorcar" | "motor_vehicle" => Some(VehicleClass::Car),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditional_times() {
+        assert_eq!(
+            parse_time_windows(" (Mo-Fr 07:00-09:30,16:00-18:00)"),
+            vec![
+                TimeWindow {
+                    start: 7 * 60,
+                    end: 9 * 60 + 30
+                },
+                TimeWindow {
+                    start: 16 * 60,
+                    end: 18 * 60
+                }
+            ]
+        );
+        assert!(parse_time_windows("wet").is_empty());
+    }
+
+    #[test]
+    fn plain_and_conditional() {
+        let members = vec![
+            ("from".to_string(), OsmID::Way(WayID(1))),
+            ("via".to_string(), OsmID::Node(NodeID(2))),
+            ("to".to_string(), OsmID::Way(WayID(3))),
+        ];
+        let parse = |kv: &[(&str, &str)]| {
+            let mut tags = vec![("type", "restriction")];
+            tags.extend(kv);
+            let tags = Tags(
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            );
+            OsmTurnRestriction::parse(&members, &tags)
+                .into_iter()
+                .map(|r| (r.only, r.active))
+                .collect::<Vec<_>>()
+        };
+        let window = |start, end| TimeWindow {
+            start: start * 60,
+            end: end * 60,
+        };
+
+        // The conditional value replaces the plain one during its windows
+        assert_eq!(
+            parse(&[
+                ("restriction", "no_left_turn"),
+                ("restriction:conditional", "none @ (07:00-19:00)")
+            ]),
+            vec![(false, vec![window(19, 7)])]
+        );
+        assert_eq!(
+            parse(&[
+                ("restriction", "no_left_turn"),
+                (
+                    "restriction:conditional",
+                    "only_straight_on @ (Mo-Fr 08:00-09:00)"
+                )
+            ]),
+            vec![(false, vec![window(9, 8)]), (true, vec![window(8, 9)])]
+        );
+        // Days of the week alone aren't understood, so the plain restriction always applies
+        assert_eq!(
+            parse(&[
+                ("restriction", "no_left_turn"),
+                ("restriction:conditional", "none @ (Sa,Su)")
+            ]),
+            vec![(false, Vec::new())]
+        );
+        assert_eq!(
+            parse(&[("restriction:conditional", "no_u_turn @ (22:00-06:00)")]),
+            vec![(false, vec![window(22, 6)])]
+        );
+        assert_eq!(outside_time_windows(&[window(0, 24)]), None);
+    }
+
+    #[test]
+    fn exceptions() {
+        let restriction = TurnRestriction {
+            from: RoadID(0),
+            via: Vec::new(),
+            to: RoadID(1),
+            only: false,
+            except: parse_except("psv;bicycle;hgv"),
+            active: vec![TimeWindow {
+                start: 7 * 60,
+                end: 9 * 60,
+            }],
+        };
+        assert!(!restriction.applies(VehicleClass::Bus, None));
+        assert!(restriction.applies(VehicleClass::Car, None));
+        assert!(restriction.applies(VehicleClass::Car, Some(8 * 60)));
+        assert!(!restriction.applies(VehicleClass::Car, Some(12 * 60)));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geo_helpers::bearing_from_endpoint;
+use crate::{Intersection, Road};
+
+/// Turns sharper than this many degrees aren't straight
+const STRAIGHT_DEGREES: f64 = 30.0;
+/// Turns sharper than this many degrees double back
+const U_TURN_DEGREES: f64 = 150.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrivingSide {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnType {
+    Straight,
+    Left,
+    Right,
+    UTurn,
+}
+
+impl TurnType {
+    /// Classifies the movement from one road to another through an intersection, using the
+    /// bearings of the roads where they meet it
+    pub fn classify(intersection: &Intersection, from: &Road, to: &Road) -> Self {
+        // Travelling towards the intersection is the opposite of the bearing away from it
+        let heading_in =
+            (bearing_from_endpoint(intersection.point, &from.linestring) + 180.0) % 360.0;
+        let heading_out = bearing_from_endpoint(intersection.point, &to.linestring);
+        // Positive is clockwise, in [-180, 180)
+        let angle = (heading_out - heading_in + 540.0) % 360.0 - 180.0;
+        if angle.abs() <= STRAIGHT_DEGREES {
+            Self::Straight
+        } else if angle.abs() >= U_TURN_DEGREES {
+            Self::UTurn
+        } else if angle > 0.0 {
+            Self::Right
+        } else {
+            Self::Left
+        }
+    }
+}
+
+/// Extra seconds added to routes for each kind of turn, so drivers prefer simpler routes. Turning
+/// across oncoming traffic is right where traffic drives on the right, and left where it drives on
+/// the left.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TurnPenalties {
+    pub driving_side: DrivingSide,
+    pub straight: f64,
+    pub with_traffic: f64,
+    pub across_traffic: f64,
+    pub u_turn: f64,
+}
+
+impl TurnPenalties {
+    /// Turns are free
+    pub const NONE: TurnPenalties = TurnPenalties {
+        driving_side: DrivingSide::Left,
+        straight: 0.0,
+        with_traffic: 0.0,
+        across_traffic: 0.0,
+        u_turn: 0.0,
+    };
+
+    /// Rough delays for a car slowing down to turn
+    pub fn typical(driving_side: DrivingSide) -> Self {
+        Self {
+            driving_side,
+            straight: 0.0,
+            with_traffic: 3.0,
+            across_traffic: 8.0,
+            u_turn: 20.0,
+        }
+    }
+
+    /// In seconds
+    pub fn penalty(&self, turn: TurnType) -> f64 {
+        match (turn, self.driving_side) {
+            (TurnType::Straight, _) => self.straight,
+            (TurnType::UTurn, _) => self.u_turn,
+            (TurnType::Left, DrivingSide::Left) | (TurnType::Right, DrivingSide::Right) => {
+                self.with_traffic
+            }
+            (TurnType::Left, DrivingSide::Right) | (TurnType::Right, DrivingSide::Left) => {
+                self.across_traffic
+            }
+        }
+    }
+
+    /// In seconds. Only junctions of 3 or more roads have turns; anything else is a bend in the
+    /// road.
+    pub fn cost(&self, intersection: &Intersection, from: &Road, to: &Road) -> f64 {
+        if intersection.roads.len() < 3 || *self == Self::NONE {
+            return 0.0;
+        }
+        self.penalty(TurnType::classify(intersection, from, to))
+    }
+}
+
+impl Default for TurnPenalties {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driving_side() {
+        let left = TurnPenalties::typical(DrivingSide::Left);
+        let right = TurnPenalties::typical(DrivingSide::Right);
+        assert_eq!(left.penalty(TurnType::Right), right.penalty(TurnType::Left));
+        assert!(left.penalty(TurnType::Right) > left.penalty(TurnType::Left));
+        assert_eq!(left.penalty(TurnType::Straight), 0.0);
+    }
+}
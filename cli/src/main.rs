@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use geo::MultiPolygon;
+use geojson::{Feature, FeatureCollection, GeoJson};
+
+use backend::{od::DemandModel, Project};
+
+/// Analyse neighbourhoods from a savefile without the web UI, writing GeoJSON reports to disk.
+#[derive(Parser)]
+struct Args {
+    /// Path to an osm.pbf or osm.xml file covering the study area
+    #[arg(long)]
+    osm: PathBuf,
+
+    /// Path to a GeoJSON Feature with the Polygon or MultiPolygon study area boundary
+    #[arg(long)]
+    boundary: PathBuf,
+
+    /// Path to a savefile exported from the web UI
+    #[arg(long)]
+    savefile: PathBuf,
+
+    /// Path to a demand model `.bin`, used to predict impact. Without this, synthetic demand is
+    /// used.
+    #[arg(long)]
+    demand: Option<PathBuf>,
+
+    /// Only analyse these neighbourhoods. By default, every boundary in the savefile is used.
+    #[arg(long)]
+    neighbourhood: Vec<String>,
+
+    /// Calculate shortcuts across perimeter roads too
+    #[arg(long)]
+    edit_perimeter_roads: bool,
+
+    /// Directory to write output files into. It'll be created if needed.
+    #[arg(long, default_value = "out")]
+    output: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    std::fs::create_dir_all(&args.output)?;
+
+    let mut project = load_project(&args)?;
+
+    let names = if args.neighbourhood.is_empty() {
+        project.map.boundaries.keys().cloned().collect()
+    } else {
+        args.neighbourhood.clone()
+    };
+
+    // A bad boundary shouldn't stop a long batch run, so record failures in the summary instead
+    let mut summary = Vec::new();
+    let mut used_prefixes = HashSet::new();
+    for name in names {
+        let prefix = file_prefix(&name, &mut used_prefixes);
+        match analyse_neighbourhood(&mut project, &name, &prefix, &args) {
+            Ok(result) => summary.push(result),
+            Err(err) => {
+                println!("Skipping neighbourhood {name}: {err}");
+                summary.push(serde_json::json!({
+                    "name": name,
+                    "error": err.to_string(),
+                }));
+            }
+        }
+    }
+
+    println!("Predicting impact across the study area");
+    write_json(
+        &args.output.join("impact.geojson"),
+        &project.predict_impact(),
+    )?;
+
+    write_json(&args.output.join("summary.json"), &summary)?;
+    println!("Wrote results to {}", args.output.display());
+    Ok(())
+}
+
+fn load_project(args: &Args) -> Result<Project> {
+    let boundary: Feature = std::fs::read_to_string(&args.boundary)?.parse()?;
+    let boundary_geom: geo::Geometry = boundary.try_into()?;
+    let multi_polygon: MultiPolygon = match boundary_geom {
+        geo::Geometry::Polygon(p) => p.into(),
+        geo::Geometry::MultiPolygon(mp) => mp,
+        _ => bail!("unexpected boundary geometry type"),
+    };
+
+    let savefile: FeatureCollection = std::fs::read_to_string(&args.savefile)?.parse()?;
+    let study_area_name = savefile
+        .foreign_members
+        .as_ref()
+        .and_then(|members| members.get("study_area_name"))
+        .and_then(|x| x.as_str())
+        .map(|x| x.to_string());
+
+    let demand = match args.demand {
+        Some(ref path) => Some(DemandModel::from_bincode(&std::fs::read(path)?)?),
+        None => None,
+    };
+
+    println!("Building the map model from {}", args.osm.display());
+    let mut project = Project::new(
+        &std::fs::read(&args.osm)?,
+        multi_polygon,
+        study_area_name,
+        demand,
+    )?;
+    let report = project.load_savefile(savefile)?;
+    let problems = report.problems().count();
+    if problems > 0 {
+        println!(
+            "{problems} of {} saved edits didn't cleanly match the map. See load_report.geojson",
+            report.edits.len()
+        );
+    }
+    write_json(&args.output.join("load_report.geojson"), &report.to_gj())?;
+    Ok(project)
+}
+
+/// Writes the neighbourhood and its shortcuts, returning a JSON summary
+fn analyse_neighbourhood(
+    project: &mut Project,
+    name: &str,
+    prefix: &str,
+    args: &Args,
+) -> Result<serde_json::Value> {
+    println!("Analysing neighbourhood {name}");
+    project.set_current_neighbourhood(name, args.edit_perimeter_roads)?;
+    let neighbourhood = project.neighbourhood()?;
+    let shortcuts = project.shortcuts()?;
+
+    write_json(
+        &args.output.join(format!("{prefix}_neighbourhood.geojson")),
+        &project.render_neighbourhood()?,
+    )?;
+    write_json(
+        &args.output.join(format!("{prefix}_shortcuts.geojson")),
+        &GeoJson::from(
+            shortcuts
+                .paths
+                .iter()
+                .map(|path| path.to_gj(&project.map))
+                .collect::<Vec<_>>(),
+        ),
+    )?;
+
+    Ok(serde_json::json!({
+        "name": name,
+        "file_prefix": prefix,
+        "interior_roads": neighbourhood.interior_roads.len(),
+        "perimeter_roads": neighbourhood.perimeter_roads.len(),
+        "shortcuts": shortcuts.paths.len(),
+        "max_shortcuts_per_road": shortcuts.count_per_road.values().max().cloned().unwrap_or(0),
+    }))
+}
+
+/// Neighbourhood names are free text from the UI, so make them safe to use in a filename. Different
+/// names like "a b" and "a_b" can map to the same prefix, so a number is added to later ones to
+/// avoid overwriting earlier output.
+fn file_prefix(name: &str, used: &mut HashSet<String>) -> String {
+    let base: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let mut prefix = base.clone();
+    let mut n = 2;
+    while !used.insert(prefix.clone()) {
+        prefix = format!("{base}_{n}");
+        n += 1;
+    }
+    prefix
+}
+
+fn write_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    std::fs::write(path, serde_json::to_string(value)?)?;
+    Ok(())
+}
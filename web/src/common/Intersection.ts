@@ -3,14 +3,18 @@ import type { Feature, Point } from "geojson";
 export type IntersectionId = number;
 
 export type DiagonalFilter = {
+  groups: number[][];
   is_rotated: boolean;
+  custom: boolean;
   angle: number;
+  barrier_bearings: number[];
 };
 
 export type IntersectionProperties = {
   kind: "editable_intersection";
   intersection_id: number;
   filter?: DiagonalFilter;
+  roads: { road: number; name?: string }[];
 };
 export type IntersectionFeature = Feature<Point, IntersectionProperties>;
 
@@ -32,6 +36,10 @@ export class Intersection {
     return this.feature.properties.filter?.angle;
   }
 
+  get isFourWay(): boolean {
+    return this.feature.properties.roads.length == 4;
+  }
+
   get hasRotatedFilter(): boolean {
     return this.feature.properties.filter?.is_rotated ?? false;
   }
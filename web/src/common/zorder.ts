@@ -86,6 +86,7 @@ const layerZorder = [
   "debug-filters",
 
   "cells",
+  "paths",
   "interior-roads-outlines",
   "interior-roads",
   "debug-intersections",
@@ -94,6 +95,7 @@ const layerZorder = [
   "debug-editable-intersections",
   "editable-intersections",
   "intersection-filters",
+  "intersection-filter-barriers",
 
   "compare-route",
 
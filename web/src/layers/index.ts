@@ -3,4 +3,5 @@ export { default as HighlightBoundaryLayer } from "./HighlightBoundaryLayer.svel
 export { default as InteriorRoadLayer } from "./InteriorRoadLayer.svelte";
 export { default as ModalFilterLayer } from "./ModalFilterLayer.svelte";
 export { default as OneWayLayer } from "./OneWayLayer.svelte";
+export { default as PathLayer } from "./PathLayer.svelte";
 export { default as RenderNeighbourhood } from "./RenderNeighbourhood.svelte";
@@ -89,7 +89,11 @@ export function autosave() {
   if (!key) {
     window.alert("Autosave failed; no projectName set?!");
   }
-  window.localStorage.setItem(key, JSON.stringify(get(backend)!.toSavefile()));
+  // Keep undo/redo history across reloads
+  window.localStorage.setItem(
+    key,
+    JSON.stringify(get(backend)!.toSavefile(true)),
+  );
 }
 
 export let useLocalVite: Writable<boolean> = writable(false);
@@ -2,6 +2,7 @@ import { LTN } from "backend";
 import type {
   Feature,
   FeatureCollection,
+  Geometry,
   LineString,
   MultiPolygon,
   Point,
@@ -113,6 +114,10 @@ export class Backend {
     this.inner.rotateDiagonalFilter(intersection.intersectionId);
   }
 
+  setDiagonalFilterGroups(intersection: Intersection, groups: number[][]) {
+    this.inner.setDiagonalFilterGroups(intersection.intersectionId, groups);
+  }
+
   deleteDiagonalFilter(intersection: Intersection) {
     this.inner.deleteDiagonalFilter(intersection.intersectionId);
   }
@@ -121,6 +126,122 @@ export class Backend {
     this.inner.toggleTravelFlow(road);
   }
 
+  setTurnBanned(intersection: number, from: number, to: number, banned: boolean) {
+    this.inner.setTurnBanned(intersection, from, to, banned);
+  }
+
+  setTurnPenalties(penalties: {
+    driving_side: "left" | "right";
+    straight: number;
+    with_traffic: number;
+    across_traffic: number;
+    u_turn: number;
+  }) {
+    this.inner.setTurnPenalties(penalties);
+  }
+
+  // Off by default. Rough typical values are signals 15, stop 8, give_way 4, and
+  // roundabout 4 seconds.
+  setIntersectionDelays(delays: {
+    signals: number;
+    stop: number;
+    give_way: number;
+    roundabout: number;
+  }) {
+    this.inner.setIntersectionDelays(delays);
+  }
+
+  setSpeedRegion(region: SpeedRegion | null) {
+    this.inner.setSpeedRegion(region);
+  }
+
+  // Returns how many roads have an observed speed
+  setSpeedProfiles(csv: string): number {
+    return this.inner.setSpeedProfiles(csv);
+  }
+
+  setTimePeriod(period: "free_flow" | "peak" | "off_peak") {
+    this.inner.setTimePeriod(period);
+  }
+
+  getSpeedRegion(): SpeedRegion | null {
+    return JSON.parse(this.inner.getSpeedRegion());
+  }
+
+  getScenarios(): { scenarios: string[]; active: string } {
+    return JSON.parse(this.inner.getScenarios());
+  }
+
+  switchScenario(name: string) {
+    this.inner.switchScenario(name);
+  }
+
+  newScenario(name: string) {
+    this.inner.newScenario(name);
+  }
+
+  cloneScenario(from: string, name: string) {
+    this.inner.cloneScenario(from, name);
+  }
+
+  deleteScenario(name: string) {
+    this.inner.deleteScenario(name);
+  }
+
+  // The a and b properties describe the edit in each scenario, with null meaning
+  // no filter. Modal filters also have a_access and b_access, and diagonal
+  // filters have a_custom, b_custom, a_groups, and b_groups.
+  diffScenarios(
+    a: string,
+    b: string,
+  ): FeatureCollection<
+    Point | LineString,
+    {
+      kind: "modal_filter" | "diagonal_filter" | "travel_flow" | "turn_ban";
+      road?: number;
+      intersection?: number;
+      from?: number;
+      to?: number;
+      a: string | boolean | null;
+      b: string | boolean | null;
+      a_access?: AccessPolicy | null;
+      b_access?: AccessPolicy | null;
+      a_custom?: boolean | null;
+      b_custom?: boolean | null;
+      a_groups?: number[][] | null;
+      b_groups?: number[][] | null;
+    }
+  > {
+    return JSON.parse(this.inner.diffScenarios(a, b));
+  }
+
+  setEditAuthor(author: string | null) {
+    this.inner.setEditAuthor(author);
+  }
+
+  setEditComment(index: number, comment: string | null) {
+    this.inner.setEditComment(index, comment);
+  }
+
+  getEditLog(): FeatureCollection<
+    Geometry,
+    {
+      index: number;
+      timestamp: number;
+      author: string | null;
+      comment: string | null;
+      scenario: string;
+      neighbourhood: string | null;
+      action: string;
+    }
+  > {
+    return JSON.parse(this.inner.getEditLog());
+  }
+
+  getEditLogCsv(): string {
+    return this.inner.getEditLogCsv();
+  }
+
   undo() {
     this.inner.undo();
   }
@@ -137,18 +258,39 @@ export class Backend {
     return JSON.parse(this.inner.getAllShortcuts());
   }
 
-  toSavefile(): FeatureCollection {
-    return JSON.parse(this.inner.toSavefile());
+  toSavefile(includeHistory = false): FeatureCollection {
+    return JSON.parse(this.inner.toSavefile(includeHistory));
   }
 
-  loadSavefile(gj: FeatureCollection) {
-    this.inner.loadSavefile(gj);
+  // Describes how each saved edit matched the current map
+  loadSavefile(gj: FeatureCollection): FeatureCollection<
+    Geometry,
+    {
+      feature_index: number;
+      kind: string;
+      status: "matched" | "ambiguous" | "dropped";
+      runner_up_distance?: number;
+      reason?: string;
+      matched_by?:
+        | "same_way"
+        | "reversed_way"
+        | "split_way"
+        | "same_node"
+        | "geometry";
+      roads?: number[];
+      intersection?: number;
+      distance?: number;
+    }
+  > {
+    return JSON.parse(this.inner.loadSavefile(gj));
   }
 
   compareRoute(
     pt1: LngLat,
     pt2: LngLat,
     mainRoadPenalty: number,
+    vehicle: VehicleClass = "car",
+    minuteOfDay: number | null = null,
   ): FeatureCollection<
     LineString,
     { kind: "before" | "after"; distance: number; time: number }
@@ -160,10 +302,16 @@ export class Backend {
         pt2.lng,
         pt2.lat,
         mainRoadPenalty,
+        vehicle,
+        minuteOfDay,
       ),
     );
   }
 
+  setModalFilterAccess(road: number, access: AccessPolicy) {
+    this.inner.setModalFilterAccess(road, access);
+  }
+
   impactToOneDestination(pt: LngLat): FeatureCollection<
     LineString,
     {
@@ -178,6 +326,24 @@ export class Backend {
     return JSON.parse(this.inner.impactToOneDestination(pt.lng, pt.lat));
   }
 
+  setAssignmentSettings(settings: {
+    iterations: number;
+    convergence: number;
+    bpr_alpha: number;
+    bpr_beta: number;
+    period_hours: number;
+  }) {
+    this.inner.setAssignmentSettings(settings);
+  }
+
+  setRouteChoice(routeChoice: {
+    samples: number;
+    spread: number;
+    seed: number;
+  }) {
+    this.inner.setRouteChoice(routeChoice);
+  }
+
   predictImpact(): FeatureCollection<
     LineString,
     { id: number; before: number; after: number }
@@ -185,6 +351,77 @@ export class Backend {
     return JSON.parse(this.inner.predictImpact());
   }
 
+  responseTimes(
+    depots: LngLat[],
+    passableKinds: string[],
+    thresholdSeconds: number,
+  ): FeatureCollection<
+    LineString,
+    {
+      id: number;
+      time_before: number | null;
+      time_after: number | null;
+      flagged: boolean;
+    }
+  > & { threshold_seconds: number; num_flagged: number } {
+    return JSON.parse(
+      this.inner.responseTimes(
+        depots.map((pt) => [pt.lng, pt.lat]),
+        passableKinds,
+        thresholdSeconds,
+      ),
+    );
+  }
+
+  getPermeability(): FeatureCollection<
+    LineString,
+    {
+      id: number;
+      driving_before_directness: number | null;
+      driving_after_directness: number | null;
+      cycling_before_directness: number | null;
+      cycling_after_directness: number | null;
+      walking_directness: number | null;
+    }
+  > & {
+    driving_before_directness: number | null;
+    driving_after_directness: number | null;
+    cycling_before_directness: number | null;
+    cycling_after_directness: number | null;
+    walking_directness: number | null;
+  } {
+    return JSON.parse(this.inner.getPermeability());
+  }
+
+  checkBusRoutes(): FeatureCollection<
+    Point,
+    {
+      kind: "modal_filter" | "movement";
+      route: string;
+      relation: number;
+      existing: boolean;
+      filter_kind?: string;
+      road?: number;
+      intersection?: number;
+      has_diagonal_filter?: boolean;
+      roads?: [number, number];
+    }
+  > & {
+    routes: { name: string; relation: number; broken: boolean }[];
+  } {
+    return JSON.parse(this.inner.checkBusRoutes());
+  }
+
+  compareScenarioImpact(
+    a: string,
+    b: string,
+  ): FeatureCollection<
+    LineString,
+    { id: number; a: number; b: number; difference: number }
+  > & { scenario_a: string; scenario_b: string; max_count: number } {
+    return JSON.parse(this.inner.compareScenarioImpact(a, b));
+  }
+
   getImpactsOnRoad(
     road: number,
   ): Array<
@@ -198,12 +435,18 @@ export class Backend {
 
   getAllIntersections(): FeatureCollection<
     Point,
-    { has_turn_restrictions: boolean }
+    {
+      has_turn_restrictions: boolean;
+      intersection_id: number;
+      control: "uncontrolled" | "signals" | "roundabout" | "priority";
+    }
   > {
     return JSON.parse(this.inner.getAllIntersections());
   }
 
-  getMovements(intersection: number): FeatureCollection<Polygon> {
+  getMovements(
+    intersection: number,
+  ): FeatureCollection<Polygon, { from: number; to: number; banned: boolean }> {
     return JSON.parse(this.inner.getMovements(intersection));
   }
 
@@ -217,6 +460,24 @@ export class Backend {
   }
 }
 
+export type VehicleClass = "car" | "bus" | "emergency" | "bicycle" | "foot";
+
+// Minutes since midnight. If end is before start, the window wraps past midnight.
+export type SpeedRegion =
+  | "england"
+  | "scotland"
+  | "wales"
+  | "france"
+  | "germany"
+  | "united_states";
+
+export type TimeWindow = { start: number; end: number };
+
+export type AccessPolicy = {
+  allowed: VehicleClass[];
+  active?: TimeWindow[];
+};
+
 export type ZoneDemandProps = {
   name: string;
   counts_from: number[];
@@ -240,11 +501,21 @@ export interface RenderNeighbourhoodOutput {
           road: number;
           cell_color: "disconnected" | number;
           speed_mph: number;
+          speed_source: "tagged" | "zone" | "legal_default" | "guess";
           // Populated by setCellColors, not in the Rust backend
           color: string;
           // TODO Plus all the stuff from Road::to_gj
         }
       >
+    | Feature<
+        LineString,
+        {
+          kind: "path";
+          id: number;
+          highway: string;
+          // TODO Plus all the stuff from Road::to_gj
+        }
+      >
     | Feature<
         LineString,
         {
@@ -282,7 +553,7 @@ export interface RenderNeighbourhoodOutput {
 
 export type AllShortcuts = FeatureCollection<
   LineString,
-  { directness: number; length_meters: number }
+  { directness: number; length_meters: number; share: number }
 >;
 
 // Sets a 'color' property on any cell polygons. Idempotent.